@@ -1,4 +1,722 @@
+use core::fmt::Write;
 use vek::{Vec3,Quaternion};
 
+use hal::calibration::{
+    CalibratedHal, Calibration, ImuCalibrator, LaserCalibration, LaserCalibrator,
+};
+use hal::layout::LaserLayout;
+use hal::params::{Param, ParamError, ParamId, ParamRegistry};
+use hal::safety::{MotorSafety, MotorSafetyConfig, SafeHal};
+use hal::{
+    elapsed, new_protocol_buffer, BatteryData, BumperData, Deg, DeviceHal, EncoderData, HalError,
+    ImuData, LaserData, Mm, MotorPower, ProtocolBuffer, Timestamp, Timestamped, Voltage,
+    WheelEncoderData, LASER_COUNT,
+};
+use protocol::map::{Map, MapError, MapReceiver};
+use protocol::protocol::{
+    BotCommand, BotEvent, FrameMode, ProtocolBatteryData, ProtocolBotStatus, ProtocolBumperData,
+    ProtocolEncoderData, ProtocolFeatures, ProtocolFineAngle, ProtocolLaserCalibration,
+    ProtocolLogLineData, ProtocolMotorPower, ProtocolParam, ProtocolRacingData,
+    ProtocolVersionData, ProtocolWaitingData, ProtocolWallData, ProtocolWheelEncoderData,
+    FEATURE_BINARY, FEATURE_BUMPERS, FEATURE_CALIBRATION, FEATURE_CHECKSUM, FEATURE_PARAMS,
+};
+
+#[cfg(test)]
+mod test;
+
 pub type V3 = Vec3<f32>;
 pub type Q = Quaternion<f32>;
+
+/// Default low battery threshold (2S LiPo at 3.3V per cell)
+pub const BATTERY_LOW_VOLTAGE: Voltage = 6.6;
+
+/// Delay before starting a race (ms)
+pub const START_DELAY: i32 = 5000;
+/// Delay before restarting a race (ms)
+pub const RESTART_DELAY: i32 = 500;
+
+/// Decimal value of a version number component
+const fn version_number(digits: &str) -> i32 {
+    let digits = digits.as_bytes();
+    let mut value = 0;
+    let mut index = 0;
+    while index < digits.len() {
+        value = value * 10 + (digits[index] - b'0') as i32;
+        index += 1;
+    }
+    value
+}
+
+/// Firmware build number (major * 10000 + minor * 100 + patch)
+pub const FIRMWARE_BUILD: i32 = version_number(env!("CARGO_PKG_VERSION_MAJOR")) * 10000
+    + version_number(env!("CARGO_PKG_VERSION_MINOR")) * 100
+    + version_number(env!("CARGO_PKG_VERSION_PATCH"));
+
+/// Parameter: voltage below which the bot refuses to start (V)
+pub const PARAM_BATTERY_LOW_VOLTAGE: ParamId = 1;
+/// Parameter: motor watchdog timeout (ms)
+pub const PARAM_MOTOR_TIMEOUT: ParamId = 2;
+/// Parameter: maximum motor power change per second
+pub const PARAM_MOTOR_MAX_SLEW_RATE: ParamId = 3;
+
+fn motor_power(power: ProtocolMotorPower) -> MotorPower {
+    power as MotorPower / 100.0
+}
+
+fn wheel_encoder_data(data: &WheelEncoderData) -> ProtocolWheelEncoderData {
+    ProtocolWheelEncoderData {
+        ticks: data.ticks,
+        velocity: data.velocity.to_degrees().round() as i32,
+    }
+}
+
+/// Event streaming wheel encoder readings
+pub fn encoders_event(data: &EncoderData) -> BotEvent {
+    BotEvent::Encoders(ProtocolEncoderData {
+        back_left: wheel_encoder_data(&data.back_left),
+        back_right: wheel_encoder_data(&data.back_right),
+        front_left: wheel_encoder_data(&data.front_left),
+        front_right: wheel_encoder_data(&data.front_right),
+    })
+}
+
+/// Event reporting battery voltage and current
+pub fn battery_event(data: &BatteryData) -> BotEvent {
+    BotEvent::Battery(ProtocolBatteryData {
+        voltage: (data.voltage * 1000.0).round() as i32,
+        current: (data.current * 1000.0).round() as i32,
+    })
+}
+
+/// Event reporting the bumper switches
+pub fn bumpers_event(data: &BumperData) -> BotEvent {
+    BotEvent::Bumpers(ProtocolBumperData {
+        front: data.front,
+        rear: data.rear,
+    })
+}
+
+/// Protocol laser scale units
+const PROTOCOL_SCALE: f32 = 10000.0;
+/// Protocol fine angle units (per degree)
+const PROTOCOL_FINE_ANGLE: f32 = 100.0;
+/// Protocol parameter value units
+const PROTOCOL_PARAM_SCALE: f32 = 1000.0;
+
+/// Event reporting the value of a parameter
+pub fn param_event(param: &Param) -> BotEvent {
+    BotEvent::Param(ProtocolParam {
+        id: param.id,
+        value: (param.value * PROTOCOL_PARAM_SCALE).round() as i32,
+    })
+}
+
+fn laser_calibration(data: &ProtocolLaserCalibration) -> LaserCalibration {
+    LaserCalibration {
+        offset: Mm(data.offset as f32),
+        scale: data.scale as f32 / PROTOCOL_SCALE,
+    }
+}
+
+/// Events reporting a calibration (one per laser, and one for the IMU)
+pub fn calibration_events(calibration: &Calibration) -> [BotEvent; LASER_COUNT + 1] {
+    let heading_bias = calibration.imu_heading_bias.to_deg().0 * PROTOCOL_FINE_ANGLE;
+    let mut events =
+        [BotEvent::CalibrationImu(heading_bias.round() as ProtocolFineAngle); LASER_COUNT + 1];
+    for (index, laser) in calibration.lasers.iter().enumerate() {
+        events[index] = BotEvent::CalibrationLaser(ProtocolLaserCalibration {
+            index,
+            offset: laser.offset.0.round() as i32,
+            scale: (laser.scale * PROTOCOL_SCALE).round() as i32,
+        });
+    }
+    events
+}
+
+/// Laser readings as points in the car frame (mm, x left, y up, z front)
+pub fn laser_points(layout: &LaserLayout, data: &LaserData) -> [V3; LASER_COUNT] {
+    let mut points = [V3::zero(); LASER_COUNT];
+    for i in 0..LASER_COUNT {
+        let point = layout.lasers[i].point(data[i]);
+        points[i] = V3::new(point[0].0, point[1].0, point[2].0);
+    }
+    points
+}
+
+/// Event reporting the protocol version, limits and features
+pub fn version_event(features: ProtocolFeatures) -> BotEvent {
+    BotEvent::Version(ProtocolVersionData::new(FIRMWARE_BUILD, features))
+}
+
+/// Events reporting a device failure: the error status and a diagnostic log line
+pub fn device_error_events(error: HalError) -> [BotEvent; 2] {
+    let mut line = ProtocolLogLineData::new();
+    let _ = write!(line, "device error: {}", error);
+    [
+        BotEvent::Status(ProtocolBotStatus::DeviceError),
+        BotEvent::Log(line),
+    ]
+}
+
+/// Bot logic, independent from the actual (real or simulated) hardware
+///
+/// Sensor readings are calibrated: the calibration is loaded from the
+/// device storage at init, and can be changed with protocol commands.
+pub struct Bot<H: DeviceHal> {
+    hal: CalibratedHal<SafeHal<H>>,
+    status: ProtocolBotStatus,
+    now: Timestamp,
+    waiting_start: Timestamp,
+    laser_layout: LaserLayout,
+    lasers: Timestamped<LaserData>,
+    imu: Option<Timestamped<ImuData>>,
+    encoders: Option<EncoderData>,
+    battery: Option<BatteryData>,
+    battery_low_voltage: Voltage,
+    battery_low_warned: bool,
+    bumpers: Option<BumperData>,
+    laser_calibrator: LaserCalibrator,
+    imu_calibrator: ImuCalibrator,
+    frame_mode: FrameMode,
+    params: ParamRegistry,
+    map: Map,
+    map_receiver: MapReceiver,
+}
+
+impl<H: DeviceHal> Bot<H> {
+    pub fn new(hal: H) -> Self {
+        let motors = MotorSafetyConfig::new();
+        let mut params = ParamRegistry::new();
+        // (cannot fail: the registry is empty and identifiers are distinct)
+        let _ = params.register(
+            PARAM_BATTERY_LOW_VOLTAGE,
+            "battery_low_voltage",
+            BATTERY_LOW_VOLTAGE,
+            0.0,
+            20.0,
+        );
+        let _ = params.register(
+            PARAM_MOTOR_TIMEOUT,
+            "motor_timeout",
+            motors.timeout,
+            10.0,
+            5000.0,
+        );
+        let _ = params.register(
+            PARAM_MOTOR_MAX_SLEW_RATE,
+            "motor_max_slew_rate",
+            motors.max_slew_rate,
+            0.1,
+            100.0,
+        );
+        Bot {
+            hal: CalibratedHal::new(SafeHal::new(hal, &motors), &Calibration::new()),
+            status: ProtocolBotStatus::InvalidMap,
+            now: 0,
+            waiting_start: 0,
+            laser_layout: LaserLayout::new(),
+            lasers: Timestamped {
+                timestamp: 0,
+                data: [Mm(0.0); LASER_COUNT],
+            },
+            imu: None,
+            encoders: None,
+            battery: None,
+            battery_low_voltage: BATTERY_LOW_VOLTAGE,
+            battery_low_warned: false,
+            bumpers: None,
+            laser_calibrator: LaserCalibrator::new(),
+            imu_calibrator: ImuCalibrator::new(),
+            frame_mode: FrameMode::Plain,
+            params,
+            map: Map::new(),
+            map_receiver: MapReceiver::new(),
+        }
+    }
+
+    pub fn hal(&self) -> &H {
+        self.hal.hal().hal()
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        self.hal.hal_mut().hal_mut()
+    }
+
+    /// Integrity checking of the frames on the link (negotiated with CHECKSUM)
+    pub fn frame_mode(&self) -> FrameMode {
+        self.frame_mode
+    }
+
+    /// Calibration applied to sensor readings
+    pub fn calibration(&self) -> &Calibration {
+        self.hal.calibration()
+    }
+
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.hal.set_calibration(calibration);
+    }
+
+    pub fn status(&self) -> ProtocolBotStatus {
+        self.status
+    }
+
+    /// Track map (invalid until one has been received)
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    /// Clock reading at the start of the last tick
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    /// Last laser readings
+    pub fn lasers(&self) -> &Timestamped<LaserData> {
+        &self.lasers
+    }
+
+    pub fn laser_layout(&self) -> &LaserLayout {
+        &self.laser_layout
+    }
+
+    pub fn set_laser_layout(&mut self, layout: &LaserLayout) {
+        self.laser_layout = *layout;
+    }
+
+    /// Last laser readings as points in the car frame
+    pub fn laser_points(&self) -> [V3; LASER_COUNT] {
+        laser_points(&self.laser_layout, &self.lasers.data)
+    }
+
+    /// Last IMU reading (if any)
+    pub fn imu(&self) -> Option<Timestamped<ImuData>> {
+        self.imu
+    }
+
+    /// Last wheel encoders reading (if any)
+    pub fn encoders(&self) -> Option<EncoderData> {
+        self.encoders
+    }
+
+    /// Send the last wheel encoders reading (for odometry on the ground station)
+    pub fn emit_encoders(&mut self) {
+        if let Some(encoders) = self.encoders {
+            self.emit(encoders_event(&encoders));
+        }
+    }
+
+    /// Last battery reading (if any)
+    pub fn battery(&self) -> Option<BatteryData> {
+        self.battery
+    }
+
+    /// Send the last battery reading
+    pub fn emit_battery(&mut self) {
+        if let Some(battery) = self.battery {
+            self.emit(battery_event(&battery));
+        }
+    }
+
+    /// Last bumpers reading (None if the bot has no bumpers)
+    pub fn bumpers(&self) -> Option<BumperData> {
+        self.bumpers
+    }
+
+    pub fn battery_low_voltage(&self) -> Voltage {
+        self.battery_low_voltage
+    }
+
+    /// Set the voltage below which the bot refuses to start
+    pub fn set_battery_low_voltage(&mut self, voltage: Voltage) {
+        let _ = self.set_param(PARAM_BATTERY_LOW_VOLTAGE, voltage);
+    }
+
+    /// Motor watchdog and slew rate limiter
+    pub fn motor_safety(&self) -> &MotorSafety {
+        self.hal.hal().safety()
+    }
+
+    pub fn set_motor_safety_config(&mut self, config: &MotorSafetyConfig) {
+        let _ = self.set_param(PARAM_MOTOR_TIMEOUT, config.timeout);
+        let _ = self.set_param(PARAM_MOTOR_MAX_SLEW_RATE, config.max_slew_rate);
+    }
+
+    /// Optional protocol features supported (bumpers once they have been read)
+    pub fn features(&self) -> ProtocolFeatures {
+        let mut features = FEATURE_CHECKSUM | FEATURE_BINARY | FEATURE_PARAMS | FEATURE_CALIBRATION;
+        if self.bumpers.is_some() {
+            features |= FEATURE_BUMPERS;
+        }
+        features
+    }
+
+    /// Tunable parameters (controllers register their own here)
+    pub fn params(&self) -> &ParamRegistry {
+        &self.params
+    }
+
+    pub fn params_mut(&mut self) -> &mut ParamRegistry {
+        &mut self.params
+    }
+
+    /// Change a parameter, Ok is the value actually set (clamped to its range)
+    pub fn set_param(&mut self, id: ParamId, value: f32) -> Result<f32, ParamError> {
+        let value = self.params.set(id, value)?;
+        self.apply_params();
+        Ok(value)
+    }
+
+    /// Update the bot settings that are backed by parameters
+    fn apply_params(&mut self) {
+        if let Some(voltage) = self.params.value(PARAM_BATTERY_LOW_VOLTAGE) {
+            self.battery_low_voltage = voltage;
+        }
+        let mut config = *self.motor_safety().config();
+        if let Some(timeout) = self.params.value(PARAM_MOTOR_TIMEOUT) {
+            config.timeout = timeout;
+        }
+        if let Some(rate) = self.params.value(PARAM_MOTOR_MAX_SLEW_RATE) {
+            config.max_slew_rate = rate;
+        }
+        self.hal.hal_mut().set_config(&config);
+    }
+
+    fn emit_param(&mut self, id: ParamId) {
+        match self.params.get(id) {
+            Some(param) => self.emit(param_event(param)),
+            None => self.log(format_args!("params: {}", ParamError::Unknown(id))),
+        }
+    }
+
+    fn battery_is_low(&self) -> bool {
+        match self.battery {
+            Some(battery) => battery.voltage < self.battery_low_voltage,
+            None => false,
+        }
+    }
+
+    fn log(&mut self, args: core::fmt::Arguments) {
+        let mut line = ProtocolLogLineData::new();
+        let _ = line.write_fmt(args);
+        self.emit(BotEvent::Log(line));
+    }
+
+    fn warn_battery_low(&mut self) {
+        let voltage = self.battery.map(|b| b.voltage).unwrap_or(0.0);
+        let threshold = self.battery_low_voltage;
+        self.log(format_args!(
+            "warning: battery low ({:.2}V < {:.2}V)",
+            voltage, threshold
+        ));
+    }
+
+    /// Start waiting for a race (unless the battery is too low or unknown)
+    fn start(&mut self, delay: i32) {
+        if self.battery.is_none() || self.battery_is_low() {
+            match self.battery {
+                Some(_) => self.warn_battery_low(),
+                None => self.log(format_args!("warning: no battery reading")),
+            }
+            self.log(format_args!("refusing to start"));
+            return;
+        }
+        if self.status == ProtocolBotStatus::Stopped {
+            self.waiting_start = self.now;
+            self.status = ProtocolBotStatus::Waiting(ProtocolWaitingData {
+                target: delay,
+                elapsed: 0,
+            });
+            self.emit(BotEvent::Status(self.status));
+        }
+    }
+
+    /// Count down while waiting, and start racing when done
+    fn update_waiting(&mut self) {
+        if let ProtocolBotStatus::Waiting(data) = self.status {
+            let elapsed = elapsed(self.waiting_start, self.now) as i32;
+            if elapsed >= data.target {
+                self.status = ProtocolBotStatus::Racing(ProtocolRacingData {
+                    section: 0,
+                    completion_low: 0,
+                    completion_high: 0,
+                    positioning_left: 0,
+                    positioning_right: 0,
+                });
+                self.emit(BotEvent::Status(self.status));
+            } else {
+                self.status = ProtocolBotStatus::Waiting(ProtocolWaitingData {
+                    target: data.target,
+                    elapsed,
+                });
+            }
+        }
+    }
+
+    /// Initialize hardware and report the resulting status
+    pub fn init(&mut self) {
+        self.laser_calibrator.reset();
+        self.imu_calibrator.reset();
+        match self.hal.init() {
+            Ok(_) => {
+                self.status = if self.map.is_valid() {
+                    ProtocolBotStatus::Stopped
+                } else {
+                    ProtocolBotStatus::InvalidMap
+                };
+                self.emit(BotEvent::Status(self.status));
+            }
+            Err(error) => self.device_error(error),
+        }
+    }
+
+    fn emit(&mut self, event: BotEvent) {
+        let mut buf = new_protocol_buffer();
+        event.write_frame(&mut buf, self.frame_mode);
+        self.hal.send(buf);
+    }
+
+    fn device_error(&mut self, error: HalError) {
+        self.stop_motors();
+        self.status = ProtocolBotStatus::DeviceError;
+        for event in device_error_events(error).iter() {
+            self.emit(*event);
+        }
+    }
+
+    fn stop_motors(&mut self) {
+        self.hal.hal_mut().stop_motors();
+    }
+
+    /// Report the interventions of the motor safety layer
+    fn report_motor_interventions(&mut self) {
+        while let Some(intervention) = self.hal.hal_mut().take_intervention() {
+            self.log(format_args!("safety: {}", intervention));
+        }
+    }
+
+    fn emit_calibration(&mut self) {
+        for event in calibration_events(self.hal.calibration()).iter() {
+            self.emit(*event);
+        }
+    }
+
+    /// Take a laser calibration sample from the last raw readings
+    fn calibrate_wall(&mut self, wall: ProtocolWallData) {
+        let raw = match self.hal.raw_lasers() {
+            Some(raw) => raw,
+            None => return self.log(format_args!("calibration: no laser readings")),
+        };
+        self.laser_calibrator.add_wall_sample(
+            &self.laser_layout,
+            Mm(wall.distance as f32),
+            Deg(wall.yaw as f32).to_rad(),
+            &raw.data,
+        );
+        let mut calibration = *self.hal.calibration();
+        let count = self.laser_calibrator.update(&mut calibration);
+        self.hal.set_calibration(&calibration);
+        self.log(format_args!("calibration: {} lasers calibrated", count));
+    }
+
+    /// Take an IMU calibration sample from the last raw reading
+    fn calibrate_still(&mut self) {
+        let raw = match self.hal.raw_imu() {
+            Some(raw) => raw,
+            None => return self.log(format_args!("calibration: no IMU readings")),
+        };
+        self.imu_calibrator.add_sample(&raw.data);
+        let mut calibration = *self.hal.calibration();
+        self.imu_calibrator.update(&mut calibration);
+        self.hal.set_calibration(&calibration);
+        let samples = self.imu_calibrator.samples();
+        self.log(format_args!(
+            "calibration: IMU calibrated ({} samples)",
+            samples
+        ));
+    }
+
+    /// Use a received map (a valid one makes a bot without a map ready to start)
+    fn receive_map(&mut self, result: Result<Map, MapError>) {
+        match result {
+            // Stray map data leaves the current map alone
+            Err(MapError::NotStarted) => {
+                return self.log(format_args!("map: {}", MapError::NotStarted));
+            }
+            Ok(map) => {
+                self.map = map;
+                if self.status == ProtocolBotStatus::InvalidMap {
+                    self.status = ProtocolBotStatus::Stopped;
+                }
+                self.log(format_args!("map: {} sections", map.length));
+            }
+            Err(error) => {
+                self.map = Map::new();
+                if self.status != ProtocolBotStatus::DeviceError {
+                    self.stop_motors();
+                    self.status = error.into();
+                }
+                self.log(format_args!("map: {}", error));
+            }
+        }
+        self.emit(BotEvent::Status(self.status));
+    }
+
+    fn handle_command(&mut self, cmd: BotCommand) {
+        match cmd {
+            BotCommand::Reset => {
+                self.stop_motors();
+                self.init();
+            }
+            BotCommand::Pause => {
+                self.stop_motors();
+                if self.status != ProtocolBotStatus::InvalidMap
+                    && self.status != ProtocolBotStatus::DeviceError
+                {
+                    self.status = ProtocolBotStatus::Stopped;
+                }
+                self.emit(BotEvent::Status(self.status));
+            }
+            BotCommand::Direct(power) => {
+                self.hal.set_motor_power(
+                    motor_power(power.back_left),
+                    motor_power(power.back_right),
+                    motor_power(power.front_left),
+                    motor_power(power.front_right),
+                );
+            }
+            BotCommand::Start => self.start(START_DELAY),
+            BotCommand::Restart => self.start(RESTART_DELAY),
+            BotCommand::CalibrationLaser(data) => {
+                let mut calibration = *self.hal.calibration();
+                calibration.lasers[data.index] = laser_calibration(&data);
+                self.hal.set_calibration(&calibration);
+            }
+            BotCommand::CalibrationImu(bias) => {
+                let mut calibration = *self.hal.calibration();
+                calibration.imu_heading_bias = Deg(bias as f32 / PROTOCOL_FINE_ANGLE).to_rad();
+                self.hal.set_calibration(&calibration);
+            }
+            BotCommand::CalibrationQuery => self.emit_calibration(),
+            BotCommand::CalibrationStore => {
+                let calibration = *self.hal.calibration();
+                match self.hal.store_calibration(&calibration) {
+                    Ok(_) => self.log(format_args!("calibration: stored")),
+                    Err(error) => self.log(format_args!("calibration: {}", error)),
+                }
+            }
+            BotCommand::CalibrateWall(wall) => self.calibrate_wall(wall),
+            BotCommand::CalibrateStill => self.calibrate_still(),
+            BotCommand::Checksum(checked) => {
+                self.frame_mode = if checked {
+                    FrameMode::Checked
+                } else {
+                    FrameMode::Plain
+                };
+            }
+            BotCommand::Binary(binary) => {
+                self.frame_mode = if binary {
+                    FrameMode::Binary
+                } else {
+                    FrameMode::Plain
+                };
+            }
+            BotCommand::ParamSet(param) => {
+                match self.set_param(param.id, param.value as f32 / PROTOCOL_PARAM_SCALE) {
+                    Ok(_) => self.emit_param(param.id),
+                    Err(error) => self.log(format_args!("params: {}", error)),
+                }
+            }
+            BotCommand::ParamGet(id) => self.emit_param(id),
+            BotCommand::ParamList => {
+                let params = self.params;
+                for param in params.iter() {
+                    self.emit(param_event(param));
+                }
+            }
+            BotCommand::Hello => {
+                self.emit(version_event(self.features()));
+                self.emit(BotEvent::Status(self.status));
+            }
+            BotCommand::Version => self.emit(version_event(self.features())),
+            BotCommand::MapStart(_) | BotCommand::MapSection(_) | BotCommand::MapEnd => {
+                if let Some(result) = self.map_receiver.handle_command(&cmd) {
+                    self.receive_map(result);
+                }
+            }
+        }
+    }
+
+    /// Handle a received frame, acknowledging it in Checked and Binary modes
+    fn receive(&mut self, buf: &ProtocolBuffer) {
+        match BotCommand::parse_frame(buf, self.frame_mode) {
+            Ok((cmd, checksum)) => {
+                self.handle_command(cmd);
+                // Mode changes are always acknowledged (in the new mode)
+                let mode_change = matches!(cmd, BotCommand::Checksum(_) | BotCommand::Binary(_));
+                if mode_change || self.frame_mode != FrameMode::Plain {
+                    self.emit(BotEvent::Ack(checksum));
+                }
+            }
+            Err(error) => {
+                if self.frame_mode != FrameMode::Plain {
+                    self.emit(BotEvent::Nak(error.index));
+                }
+                self.log(format_args!("rejected command: {}", error));
+            }
+        }
+    }
+
+    /// Run one control cycle: handle pending commands, drive motors and read sensors
+    pub fn tick(&mut self) {
+        self.now = self.hal.now();
+        while let Some(buf) = self.hal.poll() {
+            self.receive(&buf);
+        }
+        self.update_waiting();
+        self.report_motor_interventions();
+
+        if self.status == ProtocolBotStatus::DeviceError {
+            return;
+        }
+        match self.hal.read_lasers() {
+            Ok(lasers) => self.lasers = lasers,
+            Err(error) => return self.device_error(error),
+        }
+        match self.hal.read_imu() {
+            Ok(imu) => self.imu = Some(imu),
+            Err(error) => return self.device_error(error),
+        }
+        match self.hal.read_encoders() {
+            Ok(encoders) => self.encoders = Some(encoders),
+            Err(error) => return self.device_error(error),
+        }
+        match self.hal.read_battery() {
+            Ok(battery) => self.battery = Some(battery),
+            Err(error) => {
+                self.battery = None;
+                return self.device_error(error);
+            }
+        }
+        // Report bumpers when they change
+        match self.hal.read_bumpers() {
+            Ok(bumpers) => {
+                if let Some(data) = bumpers {
+                    if bumpers != self.bumpers {
+                        self.emit(bumpers_event(&data));
+                    }
+                }
+                self.bumpers = bumpers;
+            }
+            Err(error) => return self.device_error(error),
+        }
+        // Warn once each time the battery goes low
+        if self.battery_is_low() {
+            if !self.battery_low_warned {
+                self.battery_low_warned = true;
+                self.warn_battery_low();
+            }
+        } else {
+            self.battery_low_warned = false;
+        }
+    }
+}
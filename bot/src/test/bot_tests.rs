@@ -0,0 +1,437 @@
+use crate::*;
+use hal::mock::MockHal;
+use hal::{ProtocolBuffer, Sensor};
+use protocol::map::{MapUploadStatus, MapUploader};
+use protocol::protocol::{
+    append_checksum, frame_checksum, MotorsPowerData, ProtocolMapSection, ProtocolMapSectionData,
+    ProtocolMapSectionDataStraight, ProtocolParam, ProtocolVersionData, FEATURE_BINARY,
+    FEATURE_BUMPERS, FEATURE_PARAMS,
+};
+
+/// Milliseconds to timestamp
+fn ms(time: u64) -> Timestamp {
+    time * 1000
+}
+
+fn command(cmd: BotCommand) -> ProtocolBuffer {
+    let mut buf = new_protocol_buffer();
+    cmd.write(&mut buf);
+    buf
+}
+
+fn sent_events(hal: &MockHal) -> Vec<BotEvent> {
+    hal.sent()
+        .iter()
+        .map(|(_, buf)| BotEvent::parse(buf).ok().unwrap())
+        .collect()
+}
+
+fn mock_bot() -> Bot<MockHal> {
+    let mut bot = Bot::new(MockHal::new());
+    bot.init();
+    bot.hal_mut().clear_captured();
+    bot
+}
+
+#[test]
+fn it_reports_status_at_init() {
+    let mut bot = Bot::new(MockHal::new());
+    bot.init();
+    assert_eq!(bot.hal().inits(), 1);
+    assert!(sent_events(bot.hal()) == vec![BotEvent::Status(ProtocolBotStatus::InvalidMap)]);
+}
+
+#[test]
+fn it_drives_motors_directly() {
+    let mut bot = mock_bot();
+    let power = MotorsPowerData {
+        back_left: 100,
+        back_right: 100,
+        front_left: -100,
+        front_right: 0,
+    };
+    for time in [0, 100, 200].iter() {
+        bot.hal_mut()
+            .incoming_at(ms(*time), command(BotCommand::Direct(power)));
+    }
+    bot.hal_mut().set_time_step(ms(100));
+    for _ in 0..4 {
+        bot.tick();
+    }
+
+    // Slew rate limited to full power (from the clock reading after a command)
+    let last = bot.hal().last_motor_command().unwrap();
+    assert_eq!(last.timestamp, ms(400));
+    assert_eq!(last.back_left, 1.0);
+    assert_eq!(last.front_left, -1.0);
+
+    // Watchdog stops the motors when commands stop
+    for _ in 0..3 {
+        bot.tick();
+    }
+    let last = bot.hal().last_motor_command().unwrap();
+    assert_eq!(last.back_left, 0.0);
+}
+
+#[test]
+fn it_stops_on_device_errors() {
+    let mut bot = mock_bot();
+    bot.hal_mut()
+        .lasers_at(ms(50), Err(HalError::Timeout(Sensor::Laser(2))));
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::InvalidMap);
+
+    bot.hal_mut().advance(ms(100));
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::DeviceError);
+    assert!(sent_events(bot.hal())[0] == BotEvent::Status(ProtocolBotStatus::DeviceError));
+    let last = bot.hal().last_motor_command().unwrap();
+    assert_eq!(last.back_left, 0.0);
+}
+
+#[test]
+fn it_warns_once_on_low_battery() {
+    let mut bot = mock_bot();
+    let low = BatteryData {
+        voltage: 6.0,
+        current: 1.0,
+    };
+    bot.hal_mut().battery_at(ms(10), Ok(low));
+    bot.hal_mut().set_time_step(ms(10));
+    for _ in 0..5 {
+        bot.tick();
+    }
+    assert_eq!(sent_events(bot.hal()).len(), 1);
+    assert!(bot.battery() == Some(low));
+}
+
+/// Bot with a valid map (a single straight section), ready to start
+fn stopped_bot() -> Bot<MockHal> {
+    let mut bot = mock_bot();
+    let straight = ProtocolMapSectionData::Straight(ProtocolMapSectionDataStraight {
+        length: 1000,
+        width_start: 800,
+        width_end: 800,
+    });
+    for cmd in [
+        BotCommand::MapStart(1),
+        BotCommand::MapSection(ProtocolMapSection {
+            index: 0,
+            data: straight,
+        }),
+        BotCommand::MapEnd,
+    ]
+    .iter()
+    {
+        bot.hal_mut().incoming_at(ms(0), command(*cmd));
+    }
+    bot.tick();
+    bot.hal_mut().clear_captured();
+    bot
+}
+
+fn refusing_to_start(hal: &MockHal) -> bool {
+    let refusal = BotEvent::Log(ProtocolLogLineData::from_message("refusing to start"));
+    sent_events(hal).contains(&refusal)
+}
+
+#[test]
+fn it_refuses_to_start_on_low_battery() {
+    let mut bot = stopped_bot();
+    let low = BatteryData {
+        voltage: 6.0,
+        current: 1.0,
+    };
+    bot.hal_mut().battery_at(ms(0), Ok(low));
+    bot.tick();
+    bot.hal_mut().clear_captured();
+    bot.hal_mut().incoming_at(ms(0), command(BotCommand::Start));
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::Stopped);
+    assert!(refusing_to_start(bot.hal()));
+}
+
+#[test]
+fn it_refuses_to_start_without_battery_readings() {
+    // Commands are handled before the first battery reading
+    let mut bot = mock_bot();
+    for cmd in [BotCommand::Reset, BotCommand::Start].iter() {
+        bot.hal_mut().incoming_at(ms(0), command(*cmd));
+    }
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::InvalidMap);
+    assert!(refusing_to_start(bot.hal()));
+
+    // A failed reading is not a reading
+    let mut bot = stopped_bot();
+    bot.hal_mut()
+        .battery_at(ms(0), Err(HalError::Timeout(Sensor::Battery)));
+    bot.tick();
+    for cmd in [BotCommand::Reset, BotCommand::Start].iter() {
+        bot.hal_mut().incoming_at(ms(0), command(*cmd));
+    }
+    bot.hal_mut().battery_at(
+        ms(0),
+        Ok(BatteryData {
+            voltage: 8.0,
+            current: 0.0,
+        }),
+    );
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::Stopped);
+    assert!(refusing_to_start(bot.hal()));
+}
+
+#[test]
+fn it_reports_bumper_changes() {
+    let mut bot = mock_bot();
+    let front = BumperData {
+        front: true,
+        rear: false,
+    };
+    bot.hal_mut().bumpers_at(ms(20), Ok(Some(front)));
+    bot.hal_mut().set_time_step(ms(10));
+    for _ in 0..4 {
+        bot.tick();
+    }
+    assert!(bot.bumpers() == Some(front));
+    assert!(sent_events(bot.hal()) == vec![bumpers_event(&front)]);
+}
+
+/// Acknowledgements sent (in Checked frame mode)
+fn sent_acks(hal: &MockHal) -> Vec<BotEvent> {
+    hal.sent()
+        .iter()
+        .filter_map(|(_, buf)| BotEvent::parse_frame(buf, FrameMode::Checked).ok())
+        .map(|(event, _)| event)
+        .filter(|event| matches!(event, BotEvent::Ack(_) | BotEvent::Nak(_)))
+        .collect()
+}
+
+#[test]
+fn it_acknowledges_checked_frames() {
+    let mut bot = mock_bot();
+    let negotiation = command(BotCommand::Checksum(true));
+    bot.hal_mut().incoming_at(ms(0), negotiation);
+    bot.tick();
+    assert_eq!(bot.frame_mode(), FrameMode::Checked);
+    let checksum = frame_checksum(&negotiation, 10);
+    assert!(sent_acks(bot.hal()) == vec![BotEvent::Ack(checksum)]);
+
+    // Corrupted frame
+    let mut pause = command(BotCommand::Pause);
+    let checksum = append_checksum(&mut pause);
+    let mut corrupted = pause;
+    corrupted[1] = b'O';
+    bot.hal_mut().clear_captured();
+    bot.hal_mut().incoming_at(ms(0), corrupted);
+    bot.tick();
+    assert!(sent_acks(bot.hal()) == vec![BotEvent::Nak(5)]);
+
+    bot.hal_mut().clear_captured();
+    bot.hal_mut().incoming_at(ms(0), pause);
+    bot.tick();
+    assert!(sent_acks(bot.hal()) == vec![BotEvent::Ack(checksum)]);
+}
+
+#[test]
+fn it_switches_to_binary_frames() {
+    let mut bot = mock_bot();
+    bot.hal_mut()
+        .incoming_at(ms(0), command(BotCommand::Binary(true)));
+    bot.tick();
+    assert_eq!(bot.frame_mode(), FrameMode::Binary);
+
+    // Everything is sent in binary from now on, starting with the ACK
+    let sent = bot.hal().sent();
+    assert!(!sent.is_empty());
+    assert!(sent
+        .iter()
+        .all(|(_, buf)| buf[0] == hal::BINARY_FRAME_MARKER));
+    let (ack, _) = BotEvent::parse_frame(&sent[0].1, FrameMode::Binary)
+        .ok()
+        .unwrap();
+    assert!(matches!(ack, BotEvent::Ack(_)));
+
+    // Back to text, with a binary command
+    let mut back = new_protocol_buffer();
+    BotCommand::Binary(false).write_frame(&mut back, FrameMode::Binary);
+    bot.hal_mut().clear_captured();
+    bot.hal_mut().incoming_at(ms(0), back);
+    bot.tick();
+    assert_eq!(bot.frame_mode(), FrameMode::Plain);
+    assert!(matches!(sent_events(bot.hal())[0], BotEvent::Ack(_)));
+}
+
+#[test]
+fn it_tunes_params() {
+    let mut bot = mock_bot();
+    let set = BotCommand::ParamSet(ProtocolParam {
+        id: PARAM_BATTERY_LOW_VOLTAGE,
+        value: 7000,
+    });
+    bot.hal_mut().incoming_at(ms(0), command(set));
+    bot.tick();
+    assert_eq!(bot.battery_low_voltage(), 7.0);
+    assert!(
+        sent_events(bot.hal())
+            == vec![BotEvent::Param(ProtocolParam {
+                id: PARAM_BATTERY_LOW_VOLTAGE,
+                value: 7000,
+            })]
+    );
+
+    // Values are clamped to the parameter range
+    let set = BotCommand::ParamSet(ProtocolParam {
+        id: PARAM_MOTOR_TIMEOUT,
+        value: 0,
+    });
+    bot.hal_mut().clear_captured();
+    bot.hal_mut().incoming_at(ms(0), command(set));
+    bot.tick();
+    assert_eq!(bot.motor_safety().config().timeout, 10.0);
+    assert!(
+        sent_events(bot.hal())
+            == vec![BotEvent::Param(ProtocolParam {
+                id: PARAM_MOTOR_TIMEOUT,
+                value: 10000,
+            })]
+    );
+
+    bot.hal_mut().clear_captured();
+    bot.hal_mut()
+        .incoming_at(ms(0), command(BotCommand::ParamList));
+    bot.tick();
+    let events = sent_events(bot.hal());
+    assert_eq!(events.len(), bot.params().len());
+    assert!(events
+        .iter()
+        .all(|event| matches!(event, BotEvent::Param(_))));
+
+    // Unknown parameters are logged
+    bot.hal_mut().clear_captured();
+    bot.hal_mut()
+        .incoming_at(ms(0), command(BotCommand::ParamGet(99)));
+    bot.tick();
+    assert!(matches!(sent_events(bot.hal())[..], [BotEvent::Log(_)]));
+}
+
+#[test]
+fn it_answers_hello() {
+    let mut bot = mock_bot();
+    bot.hal_mut().incoming_at(ms(0), command(BotCommand::Hello));
+    bot.tick();
+    let events = sent_events(bot.hal());
+    let version = ProtocolVersionData::new(FIRMWARE_BUILD, bot.features());
+    assert!(
+        events
+            == vec![
+                BotEvent::Version(version),
+                BotEvent::Status(ProtocolBotStatus::InvalidMap)
+            ]
+    );
+    assert!(version.supports(FEATURE_BINARY | FEATURE_PARAMS));
+    assert!(!version.supports(FEATURE_BUMPERS));
+}
+
+#[test]
+fn it_receives_maps() {
+    let mut bot = mock_bot();
+    let straight = ProtocolMapSectionData::Straight(ProtocolMapSectionDataStraight {
+        length: 1000,
+        width_start: 800,
+        width_end: 800,
+    });
+    for cmd in [
+        BotCommand::MapStart(1),
+        BotCommand::MapSection(ProtocolMapSection {
+            index: 0,
+            data: straight,
+        }),
+        BotCommand::MapEnd,
+    ]
+    .iter()
+    {
+        bot.hal_mut().incoming_at(ms(0), command(*cmd));
+    }
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::Stopped);
+    assert_eq!(bot.map().length, 1);
+
+    // A repeated MAP-END (a retransmission) keeps the map
+    bot.hal_mut().clear_captured();
+    bot.hal_mut()
+        .incoming_at(ms(0), command(BotCommand::MapEnd));
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::Stopped);
+    assert_eq!(bot.map().length, 1);
+    assert!(sent_events(bot.hal()).is_empty());
+
+    // A bad upload invalidates the map
+    for cmd in [BotCommand::MapStart(2), BotCommand::MapEnd].iter() {
+        bot.hal_mut().incoming_at(ms(0), command(*cmd));
+    }
+    bot.tick();
+    assert!(bot.status() == ProtocolBotStatus::InvalidMap);
+    assert!(!bot.map().is_valid());
+    let events = sent_events(bot.hal());
+    assert!(events[1] == BotEvent::Status(ProtocolBotStatus::InvalidMap));
+}
+
+#[test]
+fn it_receives_maps_despite_lost_acks() {
+    let mut bot = mock_bot();
+    bot.hal_mut().set_time_step(ms(50));
+    bot.hal_mut()
+        .incoming_at(ms(0), command(BotCommand::Checksum(true)));
+    bot.tick();
+
+    let straight = ProtocolMapSectionData::Straight(ProtocolMapSectionDataStraight {
+        length: 1000,
+        width_start: 800,
+        width_end: 800,
+    });
+    let sections = [
+        ProtocolMapSection {
+            index: 0,
+            data: straight,
+        },
+        ProtocolMapSection {
+            index: 1,
+            data: straight,
+        },
+    ];
+    let mut uploader = MapUploader::new(&sections);
+    // Drop the first ACK of MAP-START, MAP-SECTION and MAP-END
+    let mut dropped = [false; 3];
+    for _ in 0..100 {
+        if uploader.status() != MapUploadStatus::Sending {
+            break;
+        }
+        bot.hal_mut().clear_captured();
+        let mut kind = None;
+        if let Some(frame) = uploader.poll(bot.now()) {
+            let (cmd, _) = BotCommand::parse_frame(&frame, FrameMode::Checked)
+                .ok()
+                .unwrap();
+            kind = match cmd {
+                BotCommand::MapStart(_) => Some(0),
+                BotCommand::MapSection(_) => Some(1),
+                _ => Some(2),
+            };
+            let now = bot.now();
+            bot.hal_mut().incoming_at(now, frame);
+        }
+        bot.tick();
+        for ack in sent_acks(bot.hal()) {
+            match kind {
+                Some(kind) if !dropped[kind] => dropped[kind] = true,
+                _ => uploader.handle_event(&ack),
+            }
+        }
+    }
+    assert_eq!(dropped, [true; 3]);
+    assert_eq!(uploader.status(), MapUploadStatus::Done);
+    assert!(bot.status() == ProtocolBotStatus::Stopped);
+    assert_eq!(bot.map().length, 2);
+}
@@ -0,0 +1 @@
+mod bot_tests;
@@ -6,12 +6,16 @@ use kiss3d::window::Window;
 use nalgebra::RealField;
 use nalgebra::{Point3, Translation3, Vector3};
 
+use hal::LASER_COUNT;
 use map::*;
 use protocol::map::Map;
 
 mod ui;
 use ui::{gui, Ids, UiState};
 
+const LASER_MARKER_RADIUS: f32 = 0.006;
+const LASER_MARKER_DISTANCE: f32 = 0.015;
+
 pub struct VisualizedWorld {
     window: Window,
     camera: ArcBall,
@@ -40,12 +44,18 @@ impl VisualizedWorld {
 
         let mut car_group = window.add_group();
 
-        let mut car_body = car_group.add_cube(car.body_w(), car.body_h(), car.body_l());
+        let mut car_body = car_group.add_cube(car.body_w().0, car.body_h().0, car.body_l().0);
         car_body.set_color(0.0, 0.0, 1.0);
         car_body.set_local_translation(Translation3::from(car.body_position()));
-        let mut car_lasers = car_group.add_sphere(0.025);
-        car_lasers.set_color(1.0, 1.0, 0.0);
-        car_lasers.set_local_translation(Translation3::from(car.laser_position()));
+        let mut car_lasers = car_group.add_group();
+        for i in 0..LASER_COUNT {
+            // Slightly ahead of the mount, so that the beams fan out
+            let position =
+                car.laser_position(i) + (car.laser_direction(i) * LASER_MARKER_DISTANCE);
+            let mut laser = car_lasers.add_sphere(LASER_MARKER_RADIUS);
+            laser.set_color(1.0, 1.0, 0.0);
+            laser.set_local_translation(Translation3::from(position));
+        }
 
         let mut car_wheel_bl_joint = car_group.add_group();
         let mut car_wheel_br_joint = car_group.add_group();
@@ -75,22 +85,22 @@ impl VisualizedWorld {
         ]
         .iter_mut()
         {
-            let mut wheel = axle.add_cylinder(car.wheel_radius, car.wheel_thickness);
+            let mut wheel = axle.add_cylinder(car.wheel_radius.0, car.wheel_thickness.0);
             let mut rim1 = axle.add_quad(
-                (car.wheel_radius + CAR_WHEEL_SPACE) * 2.0,
-                car.wheel_thickness + CAR_WHEEL_SPACE,
+                ((car.wheel_radius + CAR_WHEEL_SPACE) * 2.0).0,
+                (car.wheel_thickness + CAR_WHEEL_SPACE).0,
                 1,
                 1,
             );
             let mut rim2 = axle.add_quad(
-                (car.wheel_radius + CAR_WHEEL_SPACE) * 2.0,
-                car.wheel_thickness + CAR_WHEEL_SPACE,
+                ((car.wheel_radius + CAR_WHEEL_SPACE) * 2.0).0,
+                (car.wheel_thickness + CAR_WHEEL_SPACE).0,
                 1,
                 1,
             );
             let mut rim3 = axle.add_quad(
-                (car.wheel_radius + CAR_WHEEL_SPACE) * 2.0,
-                car.wheel_thickness + CAR_WHEEL_SPACE,
+                ((car.wheel_radius + CAR_WHEEL_SPACE) * 2.0).0,
+                (car.wheel_thickness + CAR_WHEEL_SPACE).0,
                 1,
                 1,
             );
@@ -106,24 +116,24 @@ impl VisualizedWorld {
         }
 
         car_wheel_bl_joint.set_local_translation(Translation3::from(Vector3::new(
-            car.wheel_x(),
-            car.wheel_y(),
-            -car.wheel_z(),
+            car.wheel_x().0,
+            car.wheel_y().0,
+            -car.wheel_z().0,
         )));
         car_wheel_br_joint.set_local_translation(Translation3::from(Vector3::new(
-            -car.wheel_x(),
-            car.wheel_y(),
-            -car.wheel_z(),
+            -car.wheel_x().0,
+            car.wheel_y().0,
+            -car.wheel_z().0,
         )));
         car_wheel_fl_joint.set_local_translation(Translation3::from(Vector3::new(
-            car.wheel_x(),
-            car.wheel_y(),
-            car.wheel_z(),
+            car.wheel_x().0,
+            car.wheel_y().0,
+            car.wheel_z().0,
         )));
         car_wheel_fr_joint.set_local_translation(Translation3::from(Vector3::new(
-            -car.wheel_x(),
-            car.wheel_y(),
-            car.wheel_z(),
+            -car.wheel_x().0,
+            car.wheel_y().0,
+            car.wheel_z().0,
         )));
 
         let mut ground = window.add_quad(6.0, 6.0, 1, 1);
@@ -154,9 +164,11 @@ impl VisualizedWorld {
     }
 
     fn add_map_box(&mut self, section_box: &MapSectionBox, light: bool) {
-        let mut cube =
-            self.window
-                .add_cube(section_box.width, section_box.height, section_box.length);
+        let mut cube = self.window.add_cube(
+            section_box.width.0,
+            section_box.height.0,
+            section_box.length.0,
+        );
         cube.set_local_rotation(section_box.rotation);
         cube.set_local_translation(Translation3::from(section_box.center));
         if light {
@@ -0,0 +1,414 @@
+//! Sensor calibration
+//!
+//! Each laser has its own offset and scale error, and the IMU heading has a
+//! constant bias. A calibration maps raw readings to true ones; lasers are
+//! calibrated looking at a flat wall from known positions, the IMU keeping
+//! the bot still and aligned with the reference heading.
+
+use crate::layout::{LaserLayout, LaserMount};
+use crate::math::{atan2, cos, sin};
+use crate::{
+    Angle, BatteryData, BumperData, DeviceHal, Dim, EncoderData, HalError, ImuData, LaserData,
+    LinearDimension, Mm, MotorPower, ProtocolBuffer, Rad, Timestamp, Timestamped, LASER_COUNT,
+};
+
+/// Minimum cosine between a beam and the wall normal for a reading to be used
+pub const CALIBRATION_MIN_INCIDENCE: Dim = 0.7;
+
+/// Minimum spread of the raw readings of a laser to fit its scale
+/// (with less than this only the offset is fitted)
+pub const CALIBRATION_MIN_SPREAD: LinearDimension = Mm(50.0);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// Calibration of a single laser (true distance = raw * scale + offset)
+pub struct LaserCalibration {
+    pub offset: LinearDimension,
+    pub scale: Dim,
+}
+
+impl LaserCalibration {
+    /// Calibration leaving readings unchanged
+    pub fn new() -> Self {
+        LaserCalibration {
+            offset: Mm(0.0),
+            scale: 1.0,
+        }
+    }
+
+    pub fn apply(&self, raw: LinearDimension) -> LinearDimension {
+        raw * self.scale + self.offset
+    }
+}
+
+impl Default for LaserCalibration {
+    fn default() -> Self {
+        LaserCalibration::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// Calibration of all sensors
+pub struct Calibration {
+    /// Laser calibrations (same order as LaserData)
+    pub lasers: [LaserCalibration; LASER_COUNT],
+    /// IMU heading bias (true heading = raw heading - bias)
+    pub imu_heading_bias: Angle,
+}
+
+/// Angle in (-PI, PI]
+fn wrap_angle(angle: Angle) -> Angle {
+    Rad(atan2(sin(angle.0), cos(angle.0)))
+}
+
+impl Calibration {
+    /// Calibration leaving readings unchanged
+    pub fn new() -> Self {
+        Calibration {
+            lasers: [LaserCalibration::new(); LASER_COUNT],
+            imu_heading_bias: Rad(0.0),
+        }
+    }
+
+    pub fn apply_lasers(&self, data: &mut LaserData) {
+        for (value, laser) in data.iter_mut().zip(self.lasers.iter()) {
+            *value = laser.apply(*value);
+        }
+    }
+
+    pub fn apply_imu(&self, data: &mut ImuData) {
+        data.heading = wrap_angle(data.heading - self.imu_heading_bias);
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::new()
+    }
+}
+
+/// Distance a laser should read from a flat wall, if it can see it
+///
+/// The wall is at `distance` from the car center, and its normal points
+/// `yaw` away from the car heading (positive clockwise, like laser yaw).
+pub fn wall_distance(
+    laser: &LaserMount,
+    distance: LinearDimension,
+    yaw: Angle,
+) -> Option<LinearDimension> {
+    let normal = [-sin(yaw.0), 0.0, cos(yaw.0)];
+    let direction = laser.direction();
+    let incidence = direction[0] * normal[0] + direction[2] * normal[2];
+    if incidence < CALIBRATION_MIN_INCIDENCE {
+        return None;
+    }
+    let mount = laser.offset_x * normal[0] + laser.offset_z * normal[2];
+    let expected = (distance - mount) / incidence;
+    if expected <= laser.min_range || expected >= laser.max_range {
+        None
+    } else {
+        Some(expected)
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Least squares sums for a single laser (in f64, the spread of the
+/// readings is small compared to their squares)
+struct LaserFit {
+    count: usize,
+    raw: f64,
+    expected: f64,
+    raw_raw: f64,
+    raw_expected: f64,
+}
+
+impl LaserFit {
+    fn new() -> Self {
+        LaserFit {
+            count: 0,
+            raw: 0.0,
+            expected: 0.0,
+            raw_raw: 0.0,
+            raw_expected: 0.0,
+        }
+    }
+
+    fn add(&mut self, raw: LinearDimension, expected: LinearDimension) {
+        let (raw, expected) = (raw.0 as f64, expected.0 as f64);
+        self.count += 1;
+        self.raw += raw;
+        self.expected += expected;
+        self.raw_raw += raw * raw;
+        self.raw_expected += raw * expected;
+    }
+
+    fn fit(&self) -> Option<LaserCalibration> {
+        if self.count == 0 {
+            return None;
+        }
+        let n = self.count as f64;
+        let mean_raw = self.raw / n;
+        let mean_expected = self.expected / n;
+        let variance = self.raw_raw / n - mean_raw * mean_raw;
+        let min_spread = CALIBRATION_MIN_SPREAD.0 as f64;
+        let scale = if variance < min_spread * min_spread {
+            1.0
+        } else {
+            (self.raw_expected / n - mean_raw * mean_expected) / variance
+        };
+        Some(LaserCalibration {
+            offset: Mm((mean_expected - scale * mean_raw) as f32),
+            scale: scale as Dim,
+        })
+    }
+}
+
+/// Derives laser calibrations from readings of a flat wall
+///
+/// Samples taken at different wall positions add up: with a single
+/// distance only offsets can be fitted, with more also scales.
+pub struct LaserCalibrator {
+    fits: [LaserFit; LASER_COUNT],
+}
+
+impl LaserCalibrator {
+    pub fn new() -> Self {
+        LaserCalibrator {
+            fits: [LaserFit::new(); LASER_COUNT],
+        }
+    }
+
+    /// Forget all samples
+    pub fn reset(&mut self) {
+        self.fits = [LaserFit::new(); LASER_COUNT];
+    }
+
+    /// Samples collected for a laser
+    pub fn samples(&self, index: usize) -> usize {
+        self.fits[index].count
+    }
+
+    /// Add raw readings taken with a wall at a known position
+    /// (see wall_distance), skipping lasers that cannot see it
+    pub fn add_wall_sample(
+        &mut self,
+        layout: &LaserLayout,
+        distance: LinearDimension,
+        yaw: Angle,
+        raw: &LaserData,
+    ) {
+        let samples = self
+            .fits
+            .iter_mut()
+            .zip(layout.lasers.iter())
+            .zip(raw.iter());
+        for ((fit, laser), reading) in samples {
+            // Readings out of range are dropouts
+            if *reading <= laser.min_range || *reading >= laser.max_range {
+                continue;
+            }
+            if let Some(expected) = wall_distance(laser, distance, yaw) {
+                fit.add(*reading, expected);
+            }
+        }
+    }
+
+    /// Calibration of a laser (if it has samples)
+    pub fn fit(&self, index: usize) -> Option<LaserCalibration> {
+        self.fits[index].fit()
+    }
+
+    /// Update the calibration of lasers with samples, returning their count
+    pub fn update(&self, calibration: &mut Calibration) -> usize {
+        let mut count = 0;
+        for i in 0..LASER_COUNT {
+            if let Some(laser) = self.fit(i) {
+                calibration.lasers[i] = laser;
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+impl Default for LaserCalibrator {
+    fn default() -> Self {
+        LaserCalibrator::new()
+    }
+}
+
+/// Derives the IMU heading bias from readings taken with the bot still,
+/// aligned with the reference heading
+pub struct ImuCalibrator {
+    count: usize,
+    sin: f32,
+    cos: f32,
+}
+
+impl ImuCalibrator {
+    pub fn new() -> Self {
+        ImuCalibrator {
+            count: 0,
+            sin: 0.0,
+            cos: 0.0,
+        }
+    }
+
+    /// Forget all samples
+    pub fn reset(&mut self) {
+        *self = ImuCalibrator::new();
+    }
+
+    pub fn samples(&self) -> usize {
+        self.count
+    }
+
+    pub fn add_sample(&mut self, raw: &ImuData) {
+        // Average on the unit circle, headings wrap around
+        self.count += 1;
+        self.sin += sin(raw.heading.0);
+        self.cos += cos(raw.heading.0);
+    }
+
+    /// Heading bias (if there are samples)
+    pub fn heading_bias(&self) -> Option<Angle> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(Rad(atan2(self.sin, self.cos)))
+        }
+    }
+
+    /// Update the IMU calibration, returning false if there are no samples
+    pub fn update(&self, calibration: &mut Calibration) -> bool {
+        match self.heading_bias() {
+            Some(bias) => {
+                calibration.imu_heading_bias = bias;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for ImuCalibrator {
+    fn default() -> Self {
+        ImuCalibrator::new()
+    }
+}
+
+/// Device HAL decorator that applies a calibration to sensor readings
+///
+/// Init loads the stored calibration (if any). The last raw readings are
+/// kept, to be used for calibrating.
+pub struct CalibratedHal<H: DeviceHal> {
+    hal: H,
+    calibration: Calibration,
+    raw_lasers: Option<Timestamped<LaserData>>,
+    raw_imu: Option<Timestamped<ImuData>>,
+}
+
+impl<H: DeviceHal> CalibratedHal<H> {
+    pub fn new(hal: H, calibration: &Calibration) -> Self {
+        CalibratedHal {
+            hal,
+            calibration: *calibration,
+            raw_lasers: None,
+            raw_imu: None,
+        }
+    }
+
+    pub fn hal(&self) -> &H {
+        &self.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        &mut self.hal
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.calibration = *calibration;
+    }
+
+    /// Last laser readings, before calibration
+    pub fn raw_lasers(&self) -> Option<Timestamped<LaserData>> {
+        self.raw_lasers
+    }
+
+    /// Last IMU reading, before calibration
+    pub fn raw_imu(&self) -> Option<Timestamped<ImuData>> {
+        self.raw_imu
+    }
+}
+
+impl<H: DeviceHal> DeviceHal for CalibratedHal<H> {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.raw_lasers = None;
+        self.raw_imu = None;
+        self.hal.init()?;
+        if let Some(calibration) = self.hal.load_calibration() {
+            self.calibration = calibration;
+        }
+        Ok(())
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.hal.now()
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        let mut sample = self.hal.read_imu()?;
+        self.raw_imu = Some(sample);
+        self.calibration.apply_imu(&mut sample.data);
+        Ok(sample)
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        let mut sample = self.hal.read_lasers()?;
+        self.raw_lasers = Some(sample);
+        self.calibration.apply_lasers(&mut sample.data);
+        Ok(sample)
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.hal.read_encoders()
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.hal.read_battery()
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.hal.read_bumpers()
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.hal
+            .set_motor_power(back_left, back_right, front_left, front_right);
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.hal.poll()
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.hal.send(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.hal.load_calibration()
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.hal.store_calibration(calibration)
+    }
+}
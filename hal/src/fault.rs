@@ -0,0 +1,283 @@
+use crate::calibration::Calibration;
+use crate::{
+    Angle, BatteryData, BumperData, DeviceHal, Dim, EncoderData, HalError, ImuData, LaserData,
+    LinearDimension, MotorPower, Mm, ProtocolBuffer, Rad, Timestamp, Timestamped, LASER_COUNT,
+};
+use crate::layout::LASER_MAX_RANGE;
+use crate::math::{cos, ln, sqrt};
+use core::f32::consts::PI;
+
+/// Maximum injectable latency (in reads)
+pub const MAX_FAULT_LATENCY: usize = 16;
+
+/// Small seedable pseudo random generator (xorshift64*)
+pub struct FaultRng {
+    state: u64,
+}
+
+impl FaultRng {
+    pub fn new(seed: u64) -> Self {
+        FaultRng {
+            // Zero is a fixed point for xorshift
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform value in [0, 1)
+    pub fn uniform(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Normally distributed value (zero mean, unit standard deviation)
+    pub fn gaussian(&mut self) -> f32 {
+        // Box-Muller transform (u1 must not be zero)
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        sqrt(-2.0 * ln(u1)) * cos(2.0 * PI * u2)
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Faults injected by a FaultHal (the default injects nothing)
+pub struct FaultConfig {
+    /// Offset added to each laser reading (after scaling)
+    pub laser_offset: [LinearDimension; LASER_COUNT],
+    /// Scale error of each laser reading
+    pub laser_scale: [Dim; LASER_COUNT],
+    /// Standard deviation of the noise added to laser readings (mm)
+    pub laser_noise: LinearDimension,
+    /// Probability of a laser reading dropping out to max range
+    pub laser_dropout_max: Dim,
+    /// Probability of a laser reading dropping out to zero
+    pub laser_dropout_zero: Dim,
+    /// Laser max range (mm), used for dropouts and to clamp noisy readings
+    pub laser_max_range: LinearDimension,
+    /// Laser stuck at the first value it read
+    pub laser_stuck: Option<usize>,
+    /// Constant bias added to the IMU heading
+    pub imu_heading_bias: Angle,
+    /// Heading drift accumulated at every IMU read
+    pub imu_heading_drift: Angle,
+    /// Sensor latency, in reads (up to MAX_FAULT_LATENCY)
+    pub latency: usize,
+}
+
+impl FaultConfig {
+    pub fn new() -> Self {
+        FaultConfig {
+            laser_offset: [Mm(0.0); LASER_COUNT],
+            laser_scale: [1.0; LASER_COUNT],
+            laser_noise: Mm(0.0),
+            laser_dropout_max: 0.0,
+            laser_dropout_zero: 0.0,
+            laser_max_range: LASER_MAX_RANGE,
+            laser_stuck: None,
+            imu_heading_bias: Rad(0.0),
+            imu_heading_drift: Rad(0.0),
+            latency: 0,
+        }
+    }
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig::new()
+    }
+}
+
+/// Fixed capacity delay line
+struct DelayLine<T: Copy> {
+    values: [Option<T>; MAX_FAULT_LATENCY + 1],
+    next: usize,
+}
+
+impl<T: Copy> DelayLine<T> {
+    fn new() -> Self {
+        DelayLine {
+            values: [None; MAX_FAULT_LATENCY + 1],
+            next: 0,
+        }
+    }
+
+    /// Store a value and return the one stored `delay` pushes ago
+    /// (or the oldest one available)
+    fn push(&mut self, value: T, delay: usize) -> T {
+        let size = self.values.len();
+        let delay = if delay > MAX_FAULT_LATENCY {
+            MAX_FAULT_LATENCY
+        } else {
+            delay
+        };
+        self.values[self.next] = Some(value);
+        let mut result = value;
+        for d in 0..=delay {
+            match self.values[(self.next + size - d) % size] {
+                Some(v) => result = v,
+                None => break,
+            }
+        }
+        self.next = (self.next + 1) % size;
+        result
+    }
+}
+
+/// Delay a sample, making it look as fresh as the current one
+fn delayed<T: Copy>(
+    line: &mut DelayLine<Result<Timestamped<T>, HalError>>,
+    sample: Result<Timestamped<T>, HalError>,
+    delay: usize,
+) -> Result<Timestamped<T>, HalError> {
+    let result = line.push(sample, delay);
+    match (result, sample) {
+        (Ok(old), Ok(new)) => Ok(Timestamped {
+            timestamp: new.timestamp,
+            data: old.data,
+        }),
+        _ => result,
+    }
+}
+
+/// Device HAL decorator that injects sensor faults
+pub struct FaultHal<H: DeviceHal> {
+    hal: H,
+    config: FaultConfig,
+    rng: FaultRng,
+    heading_drift: Angle,
+    stuck_value: Option<LinearDimension>,
+    lasers: DelayLine<Result<Timestamped<LaserData>, HalError>>,
+    imu: DelayLine<Result<Timestamped<ImuData>, HalError>>,
+}
+
+impl<H: DeviceHal> FaultHal<H> {
+    pub fn new(hal: H, config: &FaultConfig, seed: u64) -> Self {
+        FaultHal {
+            hal,
+            config: *config,
+            rng: FaultRng::new(seed),
+            heading_drift: Rad(0.0),
+            stuck_value: None,
+            lasers: DelayLine::new(),
+            imu: DelayLine::new(),
+        }
+    }
+
+    pub fn hal(&self) -> &H {
+        &self.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        &mut self.hal
+    }
+
+    pub fn config(&self) -> &FaultConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: &FaultConfig) {
+        self.config = *config;
+        self.stuck_value = None;
+    }
+
+    fn laser_faults(&mut self, data: &mut LaserData) {
+        let max_range = self.config.laser_max_range;
+        for (i, reading) in data.iter_mut().enumerate() {
+            let mut value = *reading * self.config.laser_scale[i]
+                + self.config.laser_offset[i]
+                + self.config.laser_noise * self.rng.gaussian();
+            let dropout = self.rng.uniform();
+            if dropout < self.config.laser_dropout_max {
+                value = max_range;
+            } else if dropout < self.config.laser_dropout_max + self.config.laser_dropout_zero {
+                value = Mm(0.0);
+            }
+            *reading = value.max(Mm(0.0)).min(max_range);
+        }
+        if let Some(index) = self.config.laser_stuck {
+            if index < LASER_COUNT {
+                let value = *self.stuck_value.get_or_insert(data[index]);
+                data[index] = value;
+            }
+        }
+    }
+
+    fn imu_faults(&mut self, data: &mut ImuData) {
+        self.heading_drift += self.config.imu_heading_drift;
+        data.heading += self.config.imu_heading_bias + self.heading_drift;
+    }
+}
+
+impl<H: DeviceHal> DeviceHal for FaultHal<H> {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.heading_drift = Rad(0.0);
+        self.stuck_value = None;
+        self.lasers = DelayLine::new();
+        self.imu = DelayLine::new();
+        self.hal.init()
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.hal.now()
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        let mut result = self.hal.read_imu();
+        if let Ok(sample) = &mut result {
+            self.imu_faults(&mut sample.data);
+        }
+        delayed(&mut self.imu, result, self.config.latency)
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        let mut result = self.hal.read_lasers();
+        if let Ok(sample) = &mut result {
+            self.laser_faults(&mut sample.data);
+        }
+        delayed(&mut self.lasers, result, self.config.latency)
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.hal.read_encoders()
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.hal.read_battery()
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.hal.read_bumpers()
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.hal
+            .set_motor_power(back_left, back_right, front_left, front_right);
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.hal.poll()
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.hal.send(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.hal.load_calibration()
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.hal.store_calibration(calibration)
+    }
+}
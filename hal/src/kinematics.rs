@@ -0,0 +1,72 @@
+//! Skid steer kinematics (four fixed wheels, turning by speed difference)
+
+use crate::math::abs;
+use crate::safety::MotorPowers;
+use crate::{AngularVelocity, EncoderData, LinearVelocity, Mm};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// Body velocity on the ground plane
+pub struct Twist {
+    /// Forward speed
+    pub forward: LinearVelocity,
+    /// Yaw rate (positive clockwise)
+    pub yaw_rate: AngularVelocity,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Car geometry relevant to skid steering
+pub struct SkidSteer {
+    /// Distance between left and right wheel centers
+    pub track_width: Mm,
+    pub wheel_radius: Mm,
+    /// Wheel angular velocity at full motor power
+    pub max_wheel_velocity: AngularVelocity,
+}
+
+impl SkidSteer {
+    pub fn new(track_width: Mm, wheel_radius: Mm, max_wheel_velocity: AngularVelocity) -> Self {
+        SkidSteer {
+            track_width,
+            wheel_radius,
+            max_wheel_velocity,
+        }
+    }
+
+    /// Left and right wheel angular velocities needed for a twist
+    pub fn wheel_velocities(&self, twist: &Twist) -> (AngularVelocity, AngularVelocity) {
+        // Turning clockwise (right) the left wheels run on the outer side
+        let turn = twist.yaw_rate * self.track_width.0 / 2.0;
+        (
+            (twist.forward + turn) / self.wheel_radius.0,
+            (twist.forward - turn) / self.wheel_radius.0,
+        )
+    }
+
+    /// Motor powers for a twist
+    ///
+    /// When a wheel would need more than full power all wheels are scaled
+    /// down together, so that the turn ratio is preserved (the car slows
+    /// down instead of turning less).
+    pub fn motor_powers(&self, twist: &Twist) -> MotorPowers {
+        let (left, right) = self.wheel_velocities(twist);
+        let mut left = left / self.max_wheel_velocity;
+        let mut right = right / self.max_wheel_velocity;
+        let peak = abs(left).max(abs(right));
+        if peak > 1.0 {
+            left /= peak;
+            right /= peak;
+        }
+        [left, right, left, right]
+    }
+
+    /// Body twist from wheel velocities (for odometry)
+    pub fn twist(&self, encoders: &EncoderData) -> Twist {
+        let radius = self.wheel_radius.0;
+        let left = (encoders.back_left.velocity + encoders.front_left.velocity) / 2.0 * radius;
+        let right = (encoders.back_right.velocity + encoders.front_right.velocity) / 2.0 * radius;
+        Twist {
+            forward: (left + right) / 2.0,
+            yaw_rate: (left - right) / self.track_width.0,
+        }
+    }
+}
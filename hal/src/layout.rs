@@ -0,0 +1,115 @@
+use crate::math::{cos, sin};
+use crate::{Angle, Dim, LinearDimension, Mm, Rad, LASER_COUNT};
+use core::f32::consts::PI;
+
+/// Laser mounting height on the reference car (mm)
+pub const LASER_MOUNT_HEIGHT: LinearDimension = Mm(52.5);
+/// Laser mounting distance from the car center, towards the front (mm)
+pub const LASER_MOUNT_FRONT: LinearDimension = Mm(50.0);
+/// Laser minimum range (mm)
+pub const LASER_MIN_RANGE: LinearDimension = Mm(30.0);
+/// Laser maximum range (mm)
+pub const LASER_MAX_RANGE: LinearDimension = Mm(2000.0);
+/// Laser field of view (full cone angle)
+pub const LASER_FIELD_OF_VIEW: Angle = Rad(25.0 * PI / 180.0);
+
+#[derive(Clone, Copy, PartialEq)]
+/// Description of a single laser sensor
+///
+/// Offsets are relative to the car center, in the car frame
+/// (x left, y up, z front).
+pub struct LaserMount {
+    /// Mounting offset along x (mm)
+    pub offset_x: LinearDimension,
+    /// Mounting offset along y (mm)
+    pub offset_y: LinearDimension,
+    /// Mounting offset along z (mm)
+    pub offset_z: LinearDimension,
+    /// Beam yaw relative to the car heading (positive clockwise)
+    pub yaw: Angle,
+    /// Beam pitch (positive upwards)
+    pub pitch: Angle,
+    /// Minimum measurable distance
+    pub min_range: LinearDimension,
+    /// Maximum measurable distance
+    pub max_range: LinearDimension,
+    /// Beam field of view (full cone angle)
+    pub field_of_view: Angle,
+}
+
+impl LaserMount {
+    /// Unit vector of the beam direction in the car frame
+    pub fn direction(&self) -> [Dim; 3] {
+        [
+            -sin(self.yaw.0) * cos(self.pitch.0),
+            sin(self.pitch.0),
+            cos(self.yaw.0) * cos(self.pitch.0),
+        ]
+    }
+
+    /// Point at distance along the beam, in the car frame (mm)
+    pub fn point(&self, distance: LinearDimension) -> [LinearDimension; 3] {
+        let direction = self.direction();
+        [
+            self.offset_x + distance * direction[0],
+            self.offset_y + distance * direction[1],
+            self.offset_z + distance * direction[2],
+        ]
+    }
+
+    /// Clamp a distance to the measurable range
+    pub fn clamp(&self, distance: LinearDimension) -> LinearDimension {
+        distance.max(self.min_range).min(self.max_range)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Description of all laser sensors (same order as LaserData)
+pub struct LaserLayout {
+    pub lasers: [LaserMount; LASER_COUNT],
+}
+
+impl LaserLayout {
+    /// Layout of the reference car: all lasers on a single mount,
+    /// evenly spread over the front half from left to right
+    pub fn new() -> Self {
+        LaserLayout::fan(
+            Mm(0.0),
+            LASER_MOUNT_HEIGHT,
+            LASER_MOUNT_FRONT,
+            Rad(-PI / 2.0),
+            Rad(PI / 2.0),
+        )
+    }
+
+    /// All lasers on a single mount, evenly spread from yaw_start to yaw_end
+    pub fn fan(
+        offset_x: LinearDimension,
+        offset_y: LinearDimension,
+        offset_z: LinearDimension,
+        yaw_start: Angle,
+        yaw_end: Angle,
+    ) -> Self {
+        let mount = LaserMount {
+            offset_x,
+            offset_y,
+            offset_z,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            min_range: LASER_MIN_RANGE,
+            max_range: LASER_MAX_RANGE,
+            field_of_view: LASER_FIELD_OF_VIEW,
+        };
+        let mut lasers = [mount; LASER_COUNT];
+        for (i, laser) in lasers.iter_mut().enumerate() {
+            laser.yaw = yaw_start + ((yaw_end - yaw_start) * (i as f32 / (LASER_COUNT - 1) as f32));
+        }
+        LaserLayout { lasers }
+    }
+}
+
+impl Default for LaserLayout {
+    fn default() -> Self {
+        LaserLayout::new()
+    }
+}
@@ -1,17 +1,54 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+use core::fmt;
+
+pub mod calibration;
+pub mod fault;
+pub mod kinematics;
+pub mod layout;
+mod math;
+#[cfg(feature = "std")]
+pub mod mock;
+pub mod params;
+#[cfg(feature = "std")]
+pub mod record;
+pub mod safety;
+pub mod units;
+
+pub use units::{Deg, Mm, MmPerS2, Rad, M};
+
+#[cfg(test)]
+mod test;
+
 /// Number of laser sensors
 pub const LASER_COUNT: usize = 20;
 
 /// LinearDimension in mm
-pub type LinearDimension = f32;
+pub type LinearDimension = Mm;
 
 /// Time in ms
 pub type Time = f32;
 
+/// Monotonic clock reading in microseconds
+pub type Timestamp = u64;
+
+/// Time elapsed between two clock readings
+pub fn elapsed(from: Timestamp, to: Timestamp) -> Time {
+    to.saturating_sub(from) as Time / 1000.0
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Sensor data with its capture time
+pub struct Timestamped<T> {
+    pub timestamp: Timestamp,
+    pub data: T,
+}
+
 /// Acceleration in mm/s2
-pub type Acceleration = f32;
+pub type Acceleration = MmPerS2;
 
 /// Angle in radians, positive clockwise
-pub type Angle = f32;
+pub type Angle = Rad;
 
 /// Adimensional value (from -1 to 1 or from 0 to 1)
 pub type Dim = f32;
@@ -22,6 +59,36 @@ pub type LaserData = [LinearDimension; LASER_COUNT];
 /// Motor power, range from -1 to +1
 pub type MotorPower = Dim;
 
+/// Angular velocity in radians/s
+pub type AngularVelocity = f32;
+
+/// Linear velocity in mm/s
+pub type LinearVelocity = f32;
+
+/// Encoder ticks (wrapping counter, increasing when the wheel moves forward)
+pub type EncoderTicks = i32;
+
+/// Encoder ticks for a full wheel revolution
+pub const ENCODER_TICKS_PER_REVOLUTION: EncoderTicks = 360;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Data from a wheel encoder
+pub struct WheelEncoderData {
+    /// Wheel position
+    pub ticks: EncoderTicks,
+    /// Wheel angular velocity (positive forward)
+    pub velocity: AngularVelocity,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Data from all wheel encoders
+pub struct EncoderData {
+    pub back_left: WheelEncoderData,
+    pub back_right: WheelEncoderData,
+    pub front_left: WheelEncoderData,
+    pub front_right: WheelEncoderData,
+}
+
 #[derive(Clone, Copy)]
 /// Data from the IMU
 pub struct ImuData {
@@ -39,26 +106,137 @@ pub struct ImuData {
     pub acceleration_z: Acceleration,
 }
 
+/// Electric potential in V
+pub type Voltage = f32;
+
+/// Electric current in A
+pub type Current = f32;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Data from the battery monitor
+pub struct BatteryData {
+    /// Battery voltage (under load)
+    pub voltage: Voltage,
+    /// Current drawn from the battery
+    pub current: Current,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Data from the bumper switches (true when pressed)
+pub struct BumperData {
+    pub front: bool,
+    pub rear: bool,
+}
+
 pub const PROTOCOL_BUFFER_SIZE: usize = 256;
 pub type ProtocolBuffer = [u8; PROTOCOL_BUFFER_SIZE];
 
 pub fn new_protocol_buffer() -> ProtocolBuffer {
-    [0 as u8; PROTOCOL_BUFFER_SIZE]
+    [0u8; PROTOCOL_BUFFER_SIZE]
+}
+
+/// First byte of binary protocol frames (text frames start with a letter)
+///
+/// Binary frames never contain a line end before their own, so line based
+/// transports carry them like text; carriage returns are data in them.
+pub const BINARY_FRAME_MARKER: u8 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Device involved in a failure
+pub enum Sensor {
+    /// The IMU
+    Imu,
+    /// Laser sensor (with its index)
+    Laser(usize),
+    /// Motor drivers
+    Motors,
+    /// Wheel encoders
+    Encoders,
+    /// Battery monitor
+    Battery,
+    /// Bumper switches
+    Bumpers,
+    /// Persistent storage (for calibration data)
+    Storage,
+}
+
+impl fmt::Display for Sensor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sensor::Imu => write!(f, "IMU"),
+            Sensor::Laser(index) => write!(f, "laser {}", index),
+            Sensor::Motors => write!(f, "motors"),
+            Sensor::Encoders => write!(f, "encoders"),
+            Sensor::Battery => write!(f, "battery"),
+            Sensor::Bumpers => write!(f, "bumpers"),
+            Sensor::Storage => write!(f, "storage"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Device failures
+pub enum HalError {
+    /// Device has not completed its initialization
+    NotReady(Sensor),
+    /// Device did not answer in time
+    Timeout(Sensor),
+    /// Communication on the I2C bus failed
+    Bus(Sensor),
+}
+
+impl HalError {
+    /// Device involved in the failure
+    pub fn sensor(&self) -> Sensor {
+        match self {
+            HalError::NotReady(sensor) => *sensor,
+            HalError::Timeout(sensor) => *sensor,
+            HalError::Bus(sensor) => *sensor,
+        }
+    }
+}
+
+impl fmt::Display for HalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HalError::NotReady(sensor) => write!(f, "{} not ready", sensor),
+            HalError::Timeout(sensor) => write!(f, "{} timeout", sensor),
+            HalError::Bus(sensor) => write!(f, "{} I2C bus error", sensor),
+        }
+    }
 }
 
 /// Abstraction over physical devices
+///
+/// Implementations own their device state, so several of them (real or
+/// simulated) can live in the same process.
 pub trait DeviceHal {
     /// Initialize bot hardware
-    fn init() -> Result<(), ()>;
+    fn init(&mut self) -> Result<(), HalError>;
+
+    /// Read the monotonic clock
+    fn now(&mut self) -> Timestamp;
 
     /// Read IMU data
-    fn read_imu() -> Result<ImuData, ()>;
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError>;
 
     /// Read laser data
-    fn read_lasers() -> Result<LaserData, ()>;
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError>;
+
+    /// Read wheel encoders
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError>;
+
+    /// Read battery voltage and current
+    fn read_battery(&mut self) -> Result<BatteryData, HalError>;
+
+    /// Read bumper switches (None if the bot has none)
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        Ok(None)
+    }
 
     /// Set motor power
     fn set_motor_power(
+        &mut self,
         back_left: MotorPower,
         back_right: MotorPower,
         front_left: MotorPower,
@@ -66,8 +244,22 @@ pub trait DeviceHal {
     );
 
     /// Poll serial line for data
-    fn poll() -> Option<ProtocolBuffer>;
+    fn poll(&mut self) -> Option<ProtocolBuffer>;
 
     /// Send data on the serial line
-    fn send(data: ProtocolBuffer);
+    fn send(&mut self, data: ProtocolBuffer);
+
+    /// Read the calibration kept in persistent storage
+    /// (None if there is none, or if it cannot be read)
+    fn load_calibration(&mut self) -> Option<calibration::Calibration> {
+        None
+    }
+
+    /// Write a calibration to persistent storage
+    fn store_calibration(
+        &mut self,
+        _calibration: &calibration::Calibration,
+    ) -> Result<(), HalError> {
+        Err(HalError::NotReady(Sensor::Storage))
+    }
 }
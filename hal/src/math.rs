@@ -0,0 +1,61 @@
+//! Float functions, from std when available and from libm otherwise
+
+#[cfg(feature = "std")]
+pub fn sin(x: f32) -> f32 {
+    x.sin()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn sin(x: f32) -> f32 {
+    libm::sinf(x)
+}
+
+#[cfg(feature = "std")]
+pub fn cos(x: f32) -> f32 {
+    x.cos()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn cos(x: f32) -> f32 {
+    libm::cosf(x)
+}
+
+#[cfg(feature = "std")]
+pub fn sqrt(x: f32) -> f32 {
+    x.sqrt()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn sqrt(x: f32) -> f32 {
+    libm::sqrtf(x)
+}
+
+#[cfg(feature = "std")]
+pub fn ln(x: f32) -> f32 {
+    x.ln()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn ln(x: f32) -> f32 {
+    libm::logf(x)
+}
+
+#[cfg(feature = "std")]
+pub fn abs(x: f32) -> f32 {
+    x.abs()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn abs(x: f32) -> f32 {
+    libm::fabsf(x)
+}
+
+#[cfg(feature = "std")]
+pub fn atan2(y: f32, x: f32) -> f32 {
+    y.atan2(x)
+}
+
+#[cfg(not(feature = "std"))]
+pub fn atan2(y: f32, x: f32) -> f32 {
+    libm::atan2f(y, x)
+}
@@ -0,0 +1,298 @@
+//! Scriptable device HAL for unit testing bot logic
+//!
+//! Sensor readings and incoming data are scripted as a timeline: each entry
+//! becomes visible when the mock clock reaches its timestamp, and sensor
+//! readings then hold until the next entry. Motor commands and sent data
+//! are captured for assertions.
+
+use std::collections::VecDeque;
+
+use crate::calibration::Calibration;
+use crate::layout::LASER_MAX_RANGE;
+use crate::record::MotorCommand;
+use crate::{
+    BatteryData, BumperData, DeviceHal, EncoderData, HalError, ImuData, LaserData, MmPerS2,
+    MotorPower, ProtocolBuffer, Rad, Timestamp, Timestamped, WheelEncoderData, LASER_COUNT,
+};
+
+/// Battery voltage read before any battery entry (fully charged 2S LiPo)
+pub const MOCK_BATTERY_VOLTAGE: f32 = 8.4;
+
+/// Timeline of readings of a single device
+struct Track<T: Copy> {
+    current: Timestamped<Result<T, HalError>>,
+    pending: VecDeque<Timestamped<Result<T, HalError>>>,
+}
+
+impl<T: Copy> Track<T> {
+    fn new(initial: T) -> Self {
+        Track {
+            current: Timestamped {
+                timestamp: 0,
+                data: Ok(initial),
+            },
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Add an entry, keeping the timeline sorted
+    /// (entries with the same timestamp keep their order)
+    fn insert(&mut self, timestamp: Timestamp, data: Result<T, HalError>) {
+        let index = self
+            .pending
+            .iter()
+            .position(|entry| entry.timestamp > timestamp)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(index, Timestamped { timestamp, data });
+    }
+
+    /// Reading at time now (the last entry not after it)
+    fn read(&mut self, now: Timestamp) -> Timestamped<Result<T, HalError>> {
+        while let Some(entry) = self.pending.front() {
+            if entry.timestamp > now {
+                break;
+            }
+            self.current = *entry;
+            self.pending.pop_front();
+        }
+        self.current
+    }
+}
+
+/// Device HAL playing a scripted timeline
+///
+/// Before their first entry sensors read a bot standing still with nothing
+/// in range and a charged battery, so only what a test is about has to be
+/// scripted. The clock only moves when the test moves it (or by a fixed
+/// step at every clock read, see set_time_step).
+pub struct MockHal {
+    now: Timestamp,
+    time_step: Timestamp,
+    init_results: VecDeque<Result<(), HalError>>,
+    imu: Track<ImuData>,
+    lasers: Track<LaserData>,
+    encoders: Track<EncoderData>,
+    battery: Track<BatteryData>,
+    bumpers: Track<Option<BumperData>>,
+    incoming: VecDeque<Timestamped<ProtocolBuffer>>,
+    calibration: Option<Calibration>,
+
+    inits: usize,
+    motors: Vec<MotorCommand>,
+    sent: Vec<(Timestamp, ProtocolBuffer)>,
+}
+
+impl MockHal {
+    pub fn new() -> Self {
+        let wheel = WheelEncoderData {
+            ticks: 0,
+            velocity: 0.0,
+        };
+        MockHal {
+            now: 0,
+            time_step: 0,
+            init_results: VecDeque::new(),
+            imu: Track::new(ImuData {
+                heading: Rad(0.0),
+                pitch: Rad(0.0),
+                roll: Rad(0.0),
+                acceleration_x: MmPerS2(0.0),
+                acceleration_y: MmPerS2(0.0),
+                acceleration_z: MmPerS2(0.0),
+            }),
+            lasers: Track::new([LASER_MAX_RANGE; LASER_COUNT]),
+            encoders: Track::new(EncoderData {
+                back_left: wheel,
+                back_right: wheel,
+                front_left: wheel,
+                front_right: wheel,
+            }),
+            battery: Track::new(BatteryData {
+                voltage: MOCK_BATTERY_VOLTAGE,
+                current: 0.0,
+            }),
+            bumpers: Track::new(None),
+            incoming: VecDeque::new(),
+            calibration: None,
+            inits: 0,
+            motors: Vec::new(),
+            sent: Vec::new(),
+        }
+    }
+
+    /// Clock reading (without advancing it)
+    pub fn time(&self) -> Timestamp {
+        self.now
+    }
+
+    pub fn set_time(&mut self, now: Timestamp) {
+        self.now = now;
+    }
+
+    pub fn advance(&mut self, time: Timestamp) {
+        self.now += time;
+    }
+
+    /// Advance the clock by step at every clock read, before reading it
+    /// (zero to disable)
+    pub fn set_time_step(&mut self, step: Timestamp) {
+        self.time_step = step;
+    }
+
+    /// Result of the next init (inits not scripted succeed)
+    pub fn push_init(&mut self, result: Result<(), HalError>) {
+        self.init_results.push_back(result);
+    }
+
+    pub fn imu_at(&mut self, timestamp: Timestamp, data: Result<ImuData, HalError>) {
+        self.imu.insert(timestamp, data);
+    }
+
+    pub fn lasers_at(&mut self, timestamp: Timestamp, data: Result<LaserData, HalError>) {
+        self.lasers.insert(timestamp, data);
+    }
+
+    pub fn encoders_at(&mut self, timestamp: Timestamp, data: Result<EncoderData, HalError>) {
+        self.encoders.insert(timestamp, data);
+    }
+
+    pub fn battery_at(&mut self, timestamp: Timestamp, data: Result<BatteryData, HalError>) {
+        self.battery.insert(timestamp, data);
+    }
+
+    /// Bumper readings (None for a bot without bumpers, the default)
+    pub fn bumpers_at(
+        &mut self,
+        timestamp: Timestamp,
+        data: Result<Option<BumperData>, HalError>,
+    ) {
+        self.bumpers.insert(timestamp, data);
+    }
+
+    /// Data arriving on the serial line (polled once, from timestamp on)
+    pub fn incoming_at(&mut self, timestamp: Timestamp, data: ProtocolBuffer) {
+        let index = self
+            .incoming
+            .iter()
+            .position(|entry| entry.timestamp > timestamp)
+            .unwrap_or(self.incoming.len());
+        self.incoming.insert(index, Timestamped { timestamp, data });
+    }
+
+    /// Calibration in the mock persistent storage
+    pub fn calibration(&self) -> Option<Calibration> {
+        self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: Option<Calibration>) {
+        self.calibration = calibration;
+    }
+
+    /// Number of init calls
+    pub fn inits(&self) -> usize {
+        self.inits
+    }
+
+    /// Motor commands, in order
+    pub fn motor_commands(&self) -> &[MotorCommand] {
+        &self.motors
+    }
+
+    /// Last motor command (if any)
+    pub fn last_motor_command(&self) -> Option<MotorCommand> {
+        self.motors.last().copied()
+    }
+
+    /// Data sent, in order
+    pub fn sent(&self) -> &[(Timestamp, ProtocolBuffer)] {
+        &self.sent
+    }
+
+    /// Forget captured motor commands and sent data
+    pub fn clear_captured(&mut self) {
+        self.motors.clear();
+        self.sent.clear();
+    }
+}
+
+impl Default for MockHal {
+    fn default() -> Self {
+        MockHal::new()
+    }
+}
+
+/// Timestamp a reading with the time of its timeline entry
+fn sample<T>(reading: Timestamped<Result<T, HalError>>) -> Result<Timestamped<T>, HalError> {
+    let timestamp = reading.timestamp;
+    reading.data.map(|data| Timestamped { timestamp, data })
+}
+
+impl DeviceHal for MockHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.inits += 1;
+        self.init_results.pop_front().unwrap_or(Ok(()))
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.now += self.time_step;
+        self.now
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        sample(self.imu.read(self.now))
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        sample(self.lasers.read(self.now))
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.encoders.read(self.now).data
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.battery.read(self.now).data
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.bumpers.read(self.now).data
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.motors.push(MotorCommand {
+            timestamp: self.now,
+            back_left,
+            back_right,
+            front_left,
+            front_right,
+        });
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        match self.incoming.front() {
+            Some(entry) if entry.timestamp <= self.now => {
+                self.incoming.pop_front().map(|entry| entry.data)
+            }
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.sent.push((self.now, data));
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.calibration
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.calibration = Some(*calibration);
+        Ok(())
+    }
+}
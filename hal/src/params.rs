@@ -0,0 +1,142 @@
+//! Tunable parameters
+//!
+//! Controllers register their gains and thresholds, each with a range, so
+//! that they can be read and changed at runtime (over the protocol) instead
+//! of being constants that need reflashing to be tuned.
+
+use crate::Dim;
+use core::fmt;
+
+/// Maximum number of registered parameters
+pub const PARAMS_MAX_COUNT: usize = 32;
+
+/// Parameter identifier (unique in a registry)
+pub type ParamId = usize;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// A registered parameter
+pub struct Param {
+    pub id: ParamId,
+    pub name: &'static str,
+    pub value: Dim,
+    /// Lowest allowed value
+    pub min: Dim,
+    /// Highest allowed value
+    pub max: Dim,
+}
+
+impl Param {
+    /// Value clamped to the parameter range (invalid values mean unchanged)
+    fn clamp(&self, value: Dim) -> Dim {
+        if value.is_nan() {
+            self.value
+        } else {
+            value.max(self.min).min(self.max)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Errors registering or changing parameters
+pub enum ParamError {
+    /// No room for more parameters
+    Full,
+    /// Identifier already registered
+    Duplicate(ParamId),
+    /// Identifier not registered
+    Unknown(ParamId),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamError::Full => write!(f, "too many parameters"),
+            ParamError::Duplicate(id) => write!(f, "parameter {} already registered", id),
+            ParamError::Unknown(id) => write!(f, "unknown parameter {}", id),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Fixed capacity set of parameters, in registration order
+pub struct ParamRegistry {
+    params: [Option<Param>; PARAMS_MAX_COUNT],
+    count: usize,
+}
+
+impl ParamRegistry {
+    pub fn new() -> Self {
+        ParamRegistry {
+            params: [None; PARAMS_MAX_COUNT],
+            count: 0,
+        }
+    }
+
+    /// Number of registered parameters
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Register a parameter (its value is clamped to [min, max])
+    pub fn register(
+        &mut self,
+        id: ParamId,
+        name: &'static str,
+        value: Dim,
+        min: Dim,
+        max: Dim,
+    ) -> Result<(), ParamError> {
+        if self.get(id).is_some() {
+            return Err(ParamError::Duplicate(id));
+        }
+        if self.count == PARAMS_MAX_COUNT {
+            return Err(ParamError::Full);
+        }
+        let mut param = Param {
+            id,
+            name,
+            value: min,
+            min,
+            max,
+        };
+        param.value = param.clamp(value);
+        self.params[self.count] = Some(param);
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, id: ParamId) -> Option<&Param> {
+        self.iter().find(|param| param.id == id)
+    }
+
+    /// Current value of a parameter
+    pub fn value(&self, id: ParamId) -> Option<Dim> {
+        self.get(id).map(|param| param.value)
+    }
+
+    /// Change a parameter, Ok is the value actually set (clamped to its range)
+    pub fn set(&mut self, id: ParamId, value: Dim) -> Result<Dim, ParamError> {
+        for param in self.params[..self.count].iter_mut().flatten() {
+            if param.id == id {
+                param.value = param.clamp(value);
+                return Ok(param.value);
+            }
+        }
+        Err(ParamError::Unknown(id))
+    }
+
+    /// Registered parameters, in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &Param> {
+        self.params[..self.count].iter().flatten()
+    }
+}
+
+impl Default for ParamRegistry {
+    fn default() -> Self {
+        ParamRegistry::new()
+    }
+}
@@ -0,0 +1,729 @@
+//! Recording and replay of everything a bot exchanges with its devices
+//!
+//! The log is a sequence of records, each made of a tag byte, the device
+//! clock (little endian u64) and a payload that depends on the tag.
+//! Numbers are little endian, protocol buffers are stored up to their
+//! end of line.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::calibration::{Calibration, LaserCalibration};
+use crate::{
+    new_protocol_buffer, BatteryData, BumperData, DeviceHal, EncoderData, HalError, ImuData,
+    LaserData, Mm, MmPerS2, MotorPower, ProtocolBuffer, Rad, Sensor, Timestamp, Timestamped,
+    WheelEncoderData, LASER_COUNT,
+};
+
+const TAG_INIT: u8 = 1;
+const TAG_NOW: u8 = 2;
+const TAG_IMU: u8 = 3;
+const TAG_LASERS: u8 = 4;
+const TAG_ENCODERS: u8 = 5;
+const TAG_BATTERY: u8 = 6;
+const TAG_MOTORS: u8 = 7;
+const TAG_POLL: u8 = 8;
+const TAG_SEND: u8 = 9;
+const TAG_LOAD_CALIBRATION: u8 = 10;
+const TAG_STORE_CALIBRATION: u8 = 11;
+const TAG_BUMPERS: u8 = 12;
+
+const RESULT_OK: u8 = 0;
+const ERROR_NOT_READY: u8 = 1;
+const ERROR_TIMEOUT: u8 = 2;
+const ERROR_BUS: u8 = 3;
+
+const SENSOR_IMU: u8 = 0;
+const SENSOR_LASER: u8 = 1;
+const SENSOR_MOTORS: u8 = 2;
+const SENSOR_ENCODERS: u8 = 3;
+const SENSOR_BATTERY: u8 = 4;
+const SENSOR_STORAGE: u8 = 5;
+const SENSOR_BUMPERS: u8 = 6;
+
+const CALIBRATION_NONE: u8 = 0;
+const CALIBRATION_SOME: u8 = 1;
+
+const BUMPERS_NONE: u8 = 0;
+const BUMPER_FRONT: u8 = 1;
+const BUMPER_REAR: u8 = 2;
+const BUMPERS_SOME: u8 = 4;
+
+const CODE_END: u8 = b'\n';
+
+fn push_u8(out: &mut Vec<u8>, value: u8) {
+    out.push(value);
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_f32(out: &mut Vec<u8>, value: f32) {
+    out.extend_from_slice(&value.to_bits().to_le_bytes());
+}
+
+fn push_error(out: &mut Vec<u8>, error: &HalError) {
+    let (kind, sensor) = match error {
+        HalError::NotReady(sensor) => (ERROR_NOT_READY, sensor),
+        HalError::Timeout(sensor) => (ERROR_TIMEOUT, sensor),
+        HalError::Bus(sensor) => (ERROR_BUS, sensor),
+    };
+    push_u8(out, kind);
+    match sensor {
+        Sensor::Imu => push_u8(out, SENSOR_IMU),
+        Sensor::Laser(index) => {
+            push_u8(out, SENSOR_LASER);
+            push_u8(out, *index as u8);
+        }
+        Sensor::Motors => push_u8(out, SENSOR_MOTORS),
+        Sensor::Encoders => push_u8(out, SENSOR_ENCODERS),
+        Sensor::Battery => push_u8(out, SENSOR_BATTERY),
+        Sensor::Storage => push_u8(out, SENSOR_STORAGE),
+        Sensor::Bumpers => push_u8(out, SENSOR_BUMPERS),
+    }
+}
+
+/// Payload of a fallible sample: result code, then timestamp and data if ok
+fn push_result<T, F: Fn(&mut Vec<u8>, &T)>(
+    out: &mut Vec<u8>,
+    result: &Result<T, HalError>,
+    push_data: F,
+) {
+    match result {
+        Ok(data) => {
+            push_u8(out, RESULT_OK);
+            push_data(out, data);
+        }
+        Err(error) => push_error(out, error),
+    }
+}
+
+fn push_imu(out: &mut Vec<u8>, sample: &Timestamped<ImuData>) {
+    push_u64(out, sample.timestamp);
+    let data = &sample.data;
+    for value in [
+        data.heading.0,
+        data.pitch.0,
+        data.roll.0,
+        data.acceleration_x.0,
+        data.acceleration_y.0,
+        data.acceleration_z.0,
+    ]
+    .iter()
+    {
+        push_f32(out, *value);
+    }
+}
+
+fn push_lasers(out: &mut Vec<u8>, sample: &Timestamped<LaserData>) {
+    push_u64(out, sample.timestamp);
+    for value in sample.data.iter() {
+        push_f32(out, value.0);
+    }
+}
+
+fn push_encoders(out: &mut Vec<u8>, data: &EncoderData) {
+    for wheel in [
+        &data.back_left,
+        &data.back_right,
+        &data.front_left,
+        &data.front_right,
+    ]
+    .iter()
+    {
+        push_i32(out, wheel.ticks);
+        push_f32(out, wheel.velocity);
+    }
+}
+
+fn push_battery(out: &mut Vec<u8>, data: &BatteryData) {
+    push_f32(out, data.voltage);
+    push_f32(out, data.current);
+}
+
+/// Bumpers are a single byte of flags (zero when the bot has none)
+fn push_bumpers(out: &mut Vec<u8>, data: &Option<BumperData>) {
+    match data {
+        Some(data) => {
+            let mut flags = BUMPERS_SOME;
+            if data.front {
+                flags |= BUMPER_FRONT;
+            }
+            if data.rear {
+                flags |= BUMPER_REAR;
+            }
+            push_u8(out, flags);
+        }
+        None => push_u8(out, BUMPERS_NONE),
+    }
+}
+
+fn push_calibration(out: &mut Vec<u8>, calibration: &Calibration) {
+    for laser in calibration.lasers.iter() {
+        push_f32(out, laser.offset.0);
+        push_f32(out, laser.scale);
+    }
+    push_f32(out, calibration.imu_heading_bias.0);
+}
+
+fn push_buffer(out: &mut Vec<u8>, data: &ProtocolBuffer) {
+    for code in data.iter() {
+        push_u8(out, *code);
+        if *code == CODE_END {
+            break;
+        }
+    }
+}
+
+/// Reader over a recorded log
+struct LogReader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+fn invalid_log() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid HAL log")
+}
+
+impl<'a> LogReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.index >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.index + count > self.data.len() {
+            return Err(invalid_log());
+        }
+        let bytes = &self.data[self.index..self.index + count];
+        self.index += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(f32::from_bits(u32::from_le_bytes(bytes)))
+    }
+
+    fn error(&mut self, kind: u8) -> io::Result<HalError> {
+        let sensor = match self.u8()? {
+            SENSOR_IMU => Sensor::Imu,
+            SENSOR_LASER => Sensor::Laser(self.u8()? as usize),
+            SENSOR_MOTORS => Sensor::Motors,
+            SENSOR_ENCODERS => Sensor::Encoders,
+            SENSOR_BATTERY => Sensor::Battery,
+            SENSOR_STORAGE => Sensor::Storage,
+            SENSOR_BUMPERS => Sensor::Bumpers,
+            _ => return Err(invalid_log()),
+        };
+        match kind {
+            ERROR_NOT_READY => Ok(HalError::NotReady(sensor)),
+            ERROR_TIMEOUT => Ok(HalError::Timeout(sensor)),
+            ERROR_BUS => Ok(HalError::Bus(sensor)),
+            _ => Err(invalid_log()),
+        }
+    }
+
+    fn result<T, F: Fn(&mut Self) -> io::Result<T>>(
+        &mut self,
+        read_data: F,
+    ) -> io::Result<Result<T, HalError>> {
+        match self.u8()? {
+            RESULT_OK => Ok(Ok(read_data(self)?)),
+            kind => Ok(Err(self.error(kind)?)),
+        }
+    }
+
+    fn imu(&mut self) -> io::Result<Timestamped<ImuData>> {
+        let timestamp = self.u64()?;
+        Ok(Timestamped {
+            timestamp,
+            data: ImuData {
+                heading: Rad(self.f32()?),
+                pitch: Rad(self.f32()?),
+                roll: Rad(self.f32()?),
+                acceleration_x: MmPerS2(self.f32()?),
+                acceleration_y: MmPerS2(self.f32()?),
+                acceleration_z: MmPerS2(self.f32()?),
+            },
+        })
+    }
+
+    fn lasers(&mut self) -> io::Result<Timestamped<LaserData>> {
+        let timestamp = self.u64()?;
+        let mut data = [Mm(0.0); LASER_COUNT];
+        for value in data.iter_mut() {
+            *value = Mm(self.f32()?);
+        }
+        Ok(Timestamped { timestamp, data })
+    }
+
+    fn wheel_encoder(&mut self) -> io::Result<WheelEncoderData> {
+        Ok(WheelEncoderData {
+            ticks: self.i32()?,
+            velocity: self.f32()?,
+        })
+    }
+
+    fn encoders(&mut self) -> io::Result<EncoderData> {
+        Ok(EncoderData {
+            back_left: self.wheel_encoder()?,
+            back_right: self.wheel_encoder()?,
+            front_left: self.wheel_encoder()?,
+            front_right: self.wheel_encoder()?,
+        })
+    }
+
+    fn battery(&mut self) -> io::Result<BatteryData> {
+        Ok(BatteryData {
+            voltage: self.f32()?,
+            current: self.f32()?,
+        })
+    }
+
+    fn bumpers(&mut self) -> io::Result<Option<BumperData>> {
+        let flags = self.u8()?;
+        if flags == BUMPERS_NONE {
+            Ok(None)
+        } else if flags & !(BUMPERS_SOME | BUMPER_FRONT | BUMPER_REAR) != 0 {
+            Err(invalid_log())
+        } else {
+            Ok(Some(BumperData {
+                front: flags & BUMPER_FRONT != 0,
+                rear: flags & BUMPER_REAR != 0,
+            }))
+        }
+    }
+
+    fn calibration(&mut self) -> io::Result<Calibration> {
+        let mut calibration = Calibration::new();
+        for laser in calibration.lasers.iter_mut() {
+            *laser = LaserCalibration {
+                offset: Mm(self.f32()?),
+                scale: self.f32()?,
+            };
+        }
+        calibration.imu_heading_bias = Rad(self.f32()?);
+        Ok(calibration)
+    }
+
+    fn loaded_calibration(&mut self) -> io::Result<Option<Calibration>> {
+        match self.u8()? {
+            CALIBRATION_NONE => Ok(None),
+            CALIBRATION_SOME => Ok(Some(self.calibration()?)),
+            _ => Err(invalid_log()),
+        }
+    }
+
+    fn buffer(&mut self) -> io::Result<ProtocolBuffer> {
+        let mut buffer = new_protocol_buffer();
+        for code in buffer.iter_mut() {
+            *code = self.u8()?;
+            if *code == CODE_END {
+                break;
+            }
+        }
+        Ok(buffer)
+    }
+}
+
+/// Device HAL decorator that records every device interaction to a log
+pub struct RecordingHal<H: DeviceHal, W: Write> {
+    hal: H,
+    writer: W,
+    record: Vec<u8>,
+    error: Option<io::Error>,
+    /// Last clock reading (the timestamp of the records that follow it)
+    now: Timestamp,
+}
+
+impl<H: DeviceHal, W: Write> RecordingHal<H, W> {
+    pub fn new(hal: H, writer: W) -> Self {
+        RecordingHal {
+            hal,
+            writer,
+            record: Vec::new(),
+            error: None,
+            now: 0,
+        }
+    }
+
+    pub fn hal(&self) -> &H {
+        &self.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        &mut self.hal
+    }
+
+    /// First write error (recording stops after it)
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Flush the log and give back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn start_record(&mut self, tag: u8, timestamp: Timestamp) {
+        self.record.clear();
+        push_u8(&mut self.record, tag);
+        push_u64(&mut self.record, timestamp);
+    }
+
+    fn write_record(&mut self) {
+        if self.error.is_none() {
+            if let Err(error) = self.writer.write_all(&self.record) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+impl<H: DeviceHal, W: Write> DeviceHal for RecordingHal<H, W> {
+    fn init(&mut self) -> Result<(), HalError> {
+        let result = self.hal.init();
+        self.start_record(TAG_INIT, self.now);
+        push_result(&mut self.record, &result, |_, _| {});
+        self.write_record();
+        result
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.now = self.hal.now();
+        self.start_record(TAG_NOW, self.now);
+        self.write_record();
+        self.now
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        let result = self.hal.read_imu();
+        self.start_record(TAG_IMU, self.now);
+        push_result(&mut self.record, &result, push_imu);
+        self.write_record();
+        result
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        let result = self.hal.read_lasers();
+        self.start_record(TAG_LASERS, self.now);
+        push_result(&mut self.record, &result, push_lasers);
+        self.write_record();
+        result
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        let result = self.hal.read_encoders();
+        self.start_record(TAG_ENCODERS, self.now);
+        push_result(&mut self.record, &result, push_encoders);
+        self.write_record();
+        result
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        let result = self.hal.read_battery();
+        self.start_record(TAG_BATTERY, self.now);
+        push_result(&mut self.record, &result, push_battery);
+        self.write_record();
+        result
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        let result = self.hal.read_bumpers();
+        self.start_record(TAG_BUMPERS, self.now);
+        push_result(&mut self.record, &result, push_bumpers);
+        self.write_record();
+        result
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.hal
+            .set_motor_power(back_left, back_right, front_left, front_right);
+        self.start_record(TAG_MOTORS, self.now);
+        for power in [back_left, back_right, front_left, front_right].iter() {
+            push_f32(&mut self.record, *power);
+        }
+        self.write_record();
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        let result = self.hal.poll();
+        // Empty polls are not worth recording
+        if let Some(data) = &result {
+            self.start_record(TAG_POLL, self.now);
+            push_buffer(&mut self.record, data);
+            self.write_record();
+        }
+        result
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.start_record(TAG_SEND, self.now);
+        push_buffer(&mut self.record, &data);
+        self.write_record();
+        self.hal.send(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        let result = self.hal.load_calibration();
+        self.start_record(TAG_LOAD_CALIBRATION, self.now);
+        match &result {
+            Some(calibration) => {
+                push_u8(&mut self.record, CALIBRATION_SOME);
+                push_calibration(&mut self.record, calibration);
+            }
+            None => push_u8(&mut self.record, CALIBRATION_NONE),
+        }
+        self.write_record();
+        result
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        let result = self.hal.store_calibration(calibration);
+        self.start_record(TAG_STORE_CALIBRATION, self.now);
+        push_calibration(&mut self.record, calibration);
+        push_result(&mut self.record, &result, |_, _| {});
+        self.write_record();
+        result
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Motor command (recorded or issued during replay)
+pub struct MotorCommand {
+    pub timestamp: Timestamp,
+    pub back_left: MotorPower,
+    pub back_right: MotorPower,
+    pub front_left: MotorPower,
+    pub front_right: MotorPower,
+}
+
+/// Device HAL feeding a recorded log back to the bot
+///
+/// Each kind of read is replayed in its recorded order, independently from
+/// the others, so a bot whose logic changed still gets a consistent stream.
+/// Motor commands and sent data are collected to be compared with the
+/// recorded ones.
+pub struct ReplayHal {
+    inits: VecDeque<Result<(), HalError>>,
+    clock: VecDeque<Timestamp>,
+    imu: VecDeque<Result<Timestamped<ImuData>, HalError>>,
+    lasers: VecDeque<Result<Timestamped<LaserData>, HalError>>,
+    encoders: VecDeque<Result<EncoderData, HalError>>,
+    battery: VecDeque<Result<BatteryData, HalError>>,
+    bumpers: VecDeque<Result<Option<BumperData>, HalError>>,
+    incoming: VecDeque<(Timestamp, ProtocolBuffer)>,
+    loaded_calibrations: VecDeque<Option<Calibration>>,
+    stored_calibrations: VecDeque<Result<(), HalError>>,
+    now: Timestamp,
+
+    recorded_motors: Vec<MotorCommand>,
+    recorded_sent: Vec<(Timestamp, ProtocolBuffer)>,
+    motors: Vec<MotorCommand>,
+    sent: Vec<(Timestamp, ProtocolBuffer)>,
+}
+
+impl ReplayHal {
+    /// Load a log written by a RecordingHal
+    pub fn new<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut hal = ReplayHal {
+            inits: VecDeque::new(),
+            clock: VecDeque::new(),
+            imu: VecDeque::new(),
+            lasers: VecDeque::new(),
+            encoders: VecDeque::new(),
+            battery: VecDeque::new(),
+            bumpers: VecDeque::new(),
+            incoming: VecDeque::new(),
+            loaded_calibrations: VecDeque::new(),
+            stored_calibrations: VecDeque::new(),
+            now: 0,
+            recorded_motors: Vec::new(),
+            recorded_sent: Vec::new(),
+            motors: Vec::new(),
+            sent: Vec::new(),
+        };
+
+        let mut log = LogReader {
+            data: &data,
+            index: 0,
+        };
+        while !log.is_empty() {
+            let tag = log.u8()?;
+            let timestamp = log.u64()?;
+            match tag {
+                TAG_INIT => hal.inits.push_back(log.result(|_| Ok(()))?),
+                TAG_NOW => hal.clock.push_back(timestamp),
+                TAG_IMU => hal.imu.push_back(log.result(LogReader::imu)?),
+                TAG_LASERS => hal.lasers.push_back(log.result(LogReader::lasers)?),
+                TAG_ENCODERS => hal.encoders.push_back(log.result(LogReader::encoders)?),
+                TAG_BATTERY => hal.battery.push_back(log.result(LogReader::battery)?),
+                TAG_BUMPERS => hal.bumpers.push_back(log.result(LogReader::bumpers)?),
+                TAG_MOTORS => hal.recorded_motors.push(MotorCommand {
+                    timestamp,
+                    back_left: log.f32()?,
+                    back_right: log.f32()?,
+                    front_left: log.f32()?,
+                    front_right: log.f32()?,
+                }),
+                TAG_POLL => hal.incoming.push_back((timestamp, log.buffer()?)),
+                TAG_SEND => hal.recorded_sent.push((timestamp, log.buffer()?)),
+                TAG_LOAD_CALIBRATION => {
+                    let calibration = log.loaded_calibration()?;
+                    hal.loaded_calibrations.push_back(calibration);
+                }
+                TAG_STORE_CALIBRATION => {
+                    log.calibration()?;
+                    let result = log.result(|_| Ok(()))?;
+                    hal.stored_calibrations.push_back(result);
+                }
+                _ => return Err(invalid_log()),
+            }
+        }
+        Ok(hal)
+    }
+
+    /// True when all recorded clock and sensor reads have been replayed
+    pub fn is_finished(&self) -> bool {
+        self.clock.is_empty()
+            && self.imu.is_empty()
+            && self.lasers.is_empty()
+            && self.encoders.is_empty()
+            && self.battery.is_empty()
+            && self.bumpers.is_empty()
+    }
+
+    /// Motor commands issued by the recorded bot
+    pub fn recorded_motor_commands(&self) -> &[MotorCommand] {
+        &self.recorded_motors
+    }
+
+    /// Data sent by the recorded bot
+    pub fn recorded_sent(&self) -> &[(Timestamp, ProtocolBuffer)] {
+        &self.recorded_sent
+    }
+
+    /// Motor commands issued during replay
+    pub fn motor_commands(&self) -> &[MotorCommand] {
+        &self.motors
+    }
+
+    /// Data sent during replay
+    pub fn sent(&self) -> &[(Timestamp, ProtocolBuffer)] {
+        &self.sent
+    }
+}
+
+impl DeviceHal for ReplayHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.inits.pop_front().unwrap_or(Ok(()))
+    }
+
+    fn now(&mut self) -> Timestamp {
+        if let Some(now) = self.clock.pop_front() {
+            self.now = now;
+        }
+        self.now
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        self.imu
+            .pop_front()
+            .unwrap_or(Err(HalError::NotReady(Sensor::Imu)))
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        self.lasers
+            .pop_front()
+            .unwrap_or(Err(HalError::NotReady(Sensor::Laser(0))))
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.encoders
+            .pop_front()
+            .unwrap_or(Err(HalError::NotReady(Sensor::Encoders)))
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.battery
+            .pop_front()
+            .unwrap_or(Err(HalError::NotReady(Sensor::Battery)))
+    }
+
+    /// Logs without bumper reads replay a bot without bumpers
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.bumpers.pop_front().unwrap_or(Ok(None))
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.motors.push(MotorCommand {
+            timestamp: self.now,
+            back_left,
+            back_right,
+            front_left,
+            front_right,
+        });
+    }
+
+    /// Incoming data is replayed once the clock reaches its recorded time
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        match self.incoming.front() {
+            Some((timestamp, _)) if *timestamp <= self.now => {
+                self.incoming.pop_front().map(|(_, data)| data)
+            }
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.sent.push((self.now, data));
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.loaded_calibrations.pop_front().unwrap_or(None)
+    }
+
+    fn store_calibration(&mut self, _calibration: &Calibration) -> Result<(), HalError> {
+        self.stored_calibrations
+            .pop_front()
+            .unwrap_or(Err(HalError::NotReady(Sensor::Storage)))
+    }
+}
@@ -0,0 +1,313 @@
+use crate::calibration::Calibration;
+use crate::{
+    elapsed, BatteryData, BumperData, DeviceHal, Dim, EncoderData, HalError, ImuData, LaserData,
+    MotorPower, ProtocolBuffer, Time, Timestamp, Timestamped,
+};
+use core::fmt;
+
+/// Default motor watchdog timeout (ms)
+pub const MOTOR_TIMEOUT: Time = 250.0;
+/// Default maximum motor power change per second
+pub const MOTOR_MAX_SLEW_RATE: Dim = 4.0;
+
+/// Number of motors (back left, back right, front left, front right)
+pub const MOTOR_COUNT: usize = 4;
+
+/// Power of all motors (back left, back right, front left, front right)
+pub type MotorPowers = [MotorPower; MOTOR_COUNT];
+
+/// Clamp a motor power to [-1, 1] (invalid values mean stop)
+pub fn clamp_motor_power(power: MotorPower) -> MotorPower {
+    if power.is_nan() {
+        0.0
+    } else {
+        power.clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Reasons for the safety layer to override motor commands
+pub enum MotorIntervention {
+    /// No command arrived in time, motors stopped
+    Timeout,
+    /// Commanded power out of range
+    Clamp,
+    /// Commanded power changing too fast
+    SlewRate,
+}
+
+impl fmt::Display for MotorIntervention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MotorIntervention::Timeout => write!(f, "motor watchdog timeout, motors stopped"),
+            MotorIntervention::Clamp => write!(f, "motor power clamped to [-1, 1]"),
+            MotorIntervention::SlewRate => write!(f, "motor power slew rate limited"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct MotorSafetyConfig {
+    /// Time after the last command before stopping the motors (ms)
+    pub timeout: Time,
+    /// Maximum power change per second, for each motor
+    pub max_slew_rate: Dim,
+}
+
+impl MotorSafetyConfig {
+    pub fn new() -> Self {
+        MotorSafetyConfig {
+            timeout: MOTOR_TIMEOUT,
+            max_slew_rate: MOTOR_MAX_SLEW_RATE,
+        }
+    }
+}
+
+impl Default for MotorSafetyConfig {
+    fn default() -> Self {
+        MotorSafetyConfig::new()
+    }
+}
+
+/// Safety layer between motor commands and the motors
+///
+/// Commands set a target power; each update moves the actual power towards
+/// it at a limited rate, and stops everything if commands stop arriving.
+/// Each intervention is reported once, when it starts.
+pub struct MotorSafety {
+    config: MotorSafetyConfig,
+    target: MotorPowers,
+    output: MotorPowers,
+    last_command: Timestamp,
+    /// None until the first command or update (the clock need not start at 0)
+    last_update: Option<Timestamp>,
+    clamping: bool,
+    slew_limiting: bool,
+    timeout_pending: bool,
+    clamp_pending: bool,
+    slew_pending: bool,
+}
+
+impl MotorSafety {
+    pub fn new(config: &MotorSafetyConfig) -> Self {
+        MotorSafety {
+            config: *config,
+            target: [0.0; MOTOR_COUNT],
+            output: [0.0; MOTOR_COUNT],
+            last_command: 0,
+            last_update: None,
+            clamping: false,
+            slew_limiting: false,
+            timeout_pending: false,
+            clamp_pending: false,
+            slew_pending: false,
+        }
+    }
+
+    pub fn config(&self) -> &MotorSafetyConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: &MotorSafetyConfig) {
+        self.config = *config;
+    }
+
+    /// Last commanded power (after clamping)
+    pub fn target(&self) -> MotorPowers {
+        self.target
+    }
+
+    /// Power actually applied to the motors
+    pub fn output(&self) -> MotorPowers {
+        self.output
+    }
+
+    /// Set the target power (this also feeds the watchdog)
+    pub fn command(&mut self, powers: MotorPowers, now: Timestamp) {
+        let mut clamping = false;
+        for (target, power) in self.target.iter_mut().zip(powers.iter()) {
+            *target = clamp_motor_power(*power);
+            clamping |= *target != *power;
+        }
+        if clamping && !self.clamping {
+            self.clamp_pending = true;
+        }
+        self.clamping = clamping;
+        self.last_command = now;
+        self.last_update.get_or_insert(now);
+    }
+
+    /// Stop immediately (no slew rate limit)
+    pub fn stop(&mut self) {
+        self.target = [0.0; MOTOR_COUNT];
+        self.output = [0.0; MOTOR_COUNT];
+        self.slew_limiting = false;
+    }
+
+    /// Compute the power to apply to the motors now
+    pub fn update(&mut self, now: Timestamp) -> MotorPowers {
+        let dt = self
+            .last_update
+            .map_or(0.0, |last_update| elapsed(last_update, now) / 1000.0);
+        self.last_update = Some(now);
+
+        if self.target != [0.0; MOTOR_COUNT]
+            && elapsed(self.last_command, now) > self.config.timeout
+        {
+            self.stop();
+            self.timeout_pending = true;
+        }
+
+        let max_step = self.config.max_slew_rate * dt;
+        let mut slew_limiting = false;
+        for i in 0..MOTOR_COUNT {
+            let step = self.target[i] - self.output[i];
+            if step > max_step {
+                self.output[i] += max_step;
+                slew_limiting = true;
+            } else if step < -max_step {
+                self.output[i] -= max_step;
+                slew_limiting = true;
+            } else {
+                self.output[i] = self.target[i];
+            }
+        }
+        if slew_limiting && !self.slew_limiting {
+            self.slew_pending = true;
+        }
+        self.slew_limiting = slew_limiting;
+
+        self.output
+    }
+
+    /// Next intervention that has not been reported yet
+    pub fn take_intervention(&mut self) -> Option<MotorIntervention> {
+        if self.timeout_pending {
+            self.timeout_pending = false;
+            Some(MotorIntervention::Timeout)
+        } else if self.clamp_pending {
+            self.clamp_pending = false;
+            Some(MotorIntervention::Clamp)
+        } else if self.slew_pending {
+            self.slew_pending = false;
+            Some(MotorIntervention::SlewRate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Device HAL decorator that puts a MotorSafety in front of the motors
+///
+/// The watchdog and the slew rate limiter run at every clock reading, so
+/// the motors stop even when the controller stops commanding them. Motor
+/// commands feed the watchdog, and are ramped from the next clock reading.
+pub struct SafeHal<H: DeviceHal> {
+    hal: H,
+    safety: MotorSafety,
+    now: Timestamp,
+}
+
+impl<H: DeviceHal> SafeHal<H> {
+    pub fn new(hal: H, config: &MotorSafetyConfig) -> Self {
+        SafeHal {
+            hal,
+            safety: MotorSafety::new(config),
+            now: 0,
+        }
+    }
+
+    pub fn hal(&self) -> &H {
+        &self.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        &mut self.hal
+    }
+
+    pub fn safety(&self) -> &MotorSafety {
+        &self.safety
+    }
+
+    pub fn set_config(&mut self, config: &MotorSafetyConfig) {
+        self.safety.set_config(config);
+    }
+
+    /// Next intervention that has not been reported yet
+    pub fn take_intervention(&mut self) -> Option<MotorIntervention> {
+        self.safety.take_intervention()
+    }
+
+    /// Stop the motors immediately (no slew rate limit)
+    pub fn stop_motors(&mut self) {
+        self.safety.stop();
+        self.hal.set_motor_power(0.0, 0.0, 0.0, 0.0);
+    }
+
+    fn update_motors(&mut self) {
+        let power = self.safety.update(self.now);
+        self.hal
+            .set_motor_power(power[0], power[1], power[2], power[3]);
+    }
+}
+
+impl<H: DeviceHal> DeviceHal for SafeHal<H> {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.safety.stop();
+        self.hal.init()
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.now = self.hal.now();
+        self.update_motors();
+        self.now
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        self.hal.read_imu()
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        self.hal.read_lasers()
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.hal.read_encoders()
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.hal.read_battery()
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.hal.read_bumpers()
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.safety
+            .command([back_left, back_right, front_left, front_right], self.now);
+        self.update_motors();
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.hal.poll()
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.hal.send(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.hal.load_calibration()
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.hal.store_calibration(calibration)
+    }
+}
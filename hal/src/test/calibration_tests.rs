@@ -0,0 +1,172 @@
+use crate::calibration::*;
+use crate::fault::*;
+use crate::layout::LaserLayout;
+use crate::math::cos;
+use crate::*;
+
+/// Device facing a flat wall straight ahead, with calibration storage
+struct WallHal {
+    layout: LaserLayout,
+    distance: LinearDimension,
+    heading: Angle,
+    stored: Option<Calibration>,
+}
+
+impl WallHal {
+    fn new(distance: LinearDimension) -> Self {
+        WallHal {
+            layout: LaserLayout::new(),
+            distance,
+            heading: Rad(0.0),
+            stored: None,
+        }
+    }
+}
+
+impl DeviceHal for WallHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        Ok(())
+    }
+    fn now(&mut self) -> Timestamp {
+        0
+    }
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        Ok(Timestamped {
+            timestamp: 0,
+            data: ImuData {
+                heading: self.heading,
+                pitch: Rad(0.0),
+                roll: Rad(0.0),
+                acceleration_x: MmPerS2(0.0),
+                acceleration_y: MmPerS2(0.0),
+                acceleration_z: MmPerS2(0.0),
+            },
+        })
+    }
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        let mut data = [Mm(0.0); LASER_COUNT];
+        for (value, laser) in data.iter_mut().zip(self.layout.lasers.iter()) {
+            *value = wall_distance(laser, self.distance, Rad(0.0)).unwrap_or(laser.max_range);
+        }
+        Ok(Timestamped { timestamp: 0, data })
+    }
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        Err(HalError::NotReady(Sensor::Encoders))
+    }
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        Err(HalError::NotReady(Sensor::Battery))
+    }
+    fn set_motor_power(&mut self, _: MotorPower, _: MotorPower, _: MotorPower, _: MotorPower) {}
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        None
+    }
+    fn send(&mut self, _: ProtocolBuffer) {}
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.stored
+    }
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.stored = Some(*calibration);
+        Ok(())
+    }
+}
+
+fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() <= tolerance, "{} is not close to {}", a, b);
+}
+
+fn biased_config() -> FaultConfig {
+    let mut config = FaultConfig::new();
+    for i in 0..LASER_COUNT {
+        config.laser_offset[i] = Mm(i as f32 - 10.0);
+        config.laser_scale[i] = 1.0 + (i as f32 - 10.0) / 200.0;
+    }
+    config.imu_heading_bias = Rad(0.1);
+    config
+}
+
+#[test]
+fn it_finds_the_wall() {
+    let layout = LaserLayout::new();
+    let center = &layout.lasers[LASER_COUNT / 2];
+    let expected = wall_distance(center, Mm(500.0), Rad(0.0)).unwrap();
+    assert_close(expected.0, (500.0 - center.offset_z.0) / cos(center.yaw.0), 0.01);
+    // Lasers pointing sideways cannot see it
+    assert!(wall_distance(&layout.lasers[0], Mm(500.0), Rad(0.0)).is_none());
+}
+
+#[test]
+fn it_calibrates_biased_lasers() {
+    let config = biased_config();
+    let mut hal = FaultHal::new(WallHal::new(Mm(300.0)), &config, 1);
+    let layout = LaserLayout::new();
+    let mut calibrator = LaserCalibrator::new();
+    for distance in [300.0, 600.0, 900.0].iter() {
+        hal.hal_mut().distance = Mm(*distance);
+        let raw = hal.read_lasers().unwrap().data;
+        calibrator.add_wall_sample(&layout, Mm(*distance), Rad(0.0), &raw);
+    }
+
+    let mut calibration = Calibration::new();
+    assert!(calibrator.update(&mut calibration) > 0);
+    for i in 0..LASER_COUNT {
+        if calibrator.samples(i) == 0 {
+            assert_eq!(calibration.lasers[i], LaserCalibration::new());
+            continue;
+        }
+        let laser = calibration.lasers[i];
+        // Calibration inverts the injected bias
+        let raw = Mm(500.0) * config.laser_scale[i] + config.laser_offset[i];
+        assert_close(laser.apply(raw).0, 500.0, 0.5);
+    }
+}
+
+#[test]
+fn it_calibrates_imu_bias() {
+    let mut config = FaultConfig::new();
+    config.imu_heading_bias = Rad(-0.2);
+    let mut hal = FaultHal::new(WallHal::new(Mm(300.0)), &config, 1);
+    hal.hal_mut().heading = Rad(-3.0);
+
+    let mut calibrator = ImuCalibrator::new();
+    assert!(calibrator.heading_bias().is_none());
+    for _ in 0..10 {
+        calibrator.add_sample(&hal.read_imu().unwrap().data);
+    }
+    // Biased heading wraps around past -PI
+    let bias = calibrator.heading_bias().unwrap();
+    assert_close(bias.0, 2.0 * core::f32::consts::PI - 3.2, 0.001);
+
+    let mut calibration = Calibration::new();
+    assert!(calibrator.update(&mut calibration));
+    let mut data = hal.read_imu().unwrap().data;
+    calibration.apply_imu(&mut data);
+    assert_close(data.heading.0, 0.0, 0.001);
+}
+
+#[test]
+fn it_applies_stored_calibration() {
+    let config = biased_config();
+    let mut stored = Calibration::new();
+    for i in 0..LASER_COUNT {
+        stored.lasers[i] = LaserCalibration {
+            offset: -config.laser_offset[i] / config.laser_scale[i],
+            scale: 1.0 / config.laser_scale[i],
+        };
+    }
+    stored.imu_heading_bias = config.imu_heading_bias;
+
+    let mut wall = WallHal::new(Mm(400.0));
+    wall.store_calibration(&stored).unwrap();
+    let faulty = FaultHal::new(wall, &config, 1);
+    let mut hal = CalibratedHal::new(faulty, &Calibration::new());
+    hal.init().unwrap();
+    assert_eq!(*hal.calibration(), stored);
+
+    let sample = hal.read_lasers().unwrap();
+    let raw = hal.raw_lasers().unwrap();
+    let index = LASER_COUNT / 2 + 1;
+    let expected = wall_distance(&LaserLayout::new().lasers[index], Mm(400.0), Rad(0.0));
+    assert_close(sample.data[index].0, expected.unwrap().0, 0.5);
+    assert!(raw.data[index] != sample.data[index]);
+    assert_close(hal.read_imu().unwrap().data.heading.0, 0.0, 0.001);
+}
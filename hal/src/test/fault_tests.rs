@@ -0,0 +1,105 @@
+use crate::fault::*;
+use crate::*;
+
+/// Device returning constant readings
+struct ConstantHal {
+    reads: usize,
+}
+
+impl DeviceHal for ConstantHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        Ok(())
+    }
+    fn now(&mut self) -> Timestamp {
+        self.reads as Timestamp * 1000
+    }
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        Ok(Timestamped {
+            timestamp: self.now(),
+            data: ImuData {
+                heading: Rad(0.0),
+                pitch: Rad(0.0),
+                roll: Rad(0.0),
+                acceleration_x: MmPerS2(0.0),
+                acceleration_y: MmPerS2(0.0),
+                acceleration_z: MmPerS2(0.0),
+            },
+        })
+    }
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        self.reads += 1;
+        Ok(Timestamped {
+            timestamp: self.now(),
+            data: [Mm(self.reads as f32 * 100.0); LASER_COUNT],
+        })
+    }
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        Err(HalError::NotReady(Sensor::Encoders))
+    }
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        Err(HalError::NotReady(Sensor::Battery))
+    }
+    fn set_motor_power(&mut self, _: MotorPower, _: MotorPower, _: MotorPower, _: MotorPower) {}
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        None
+    }
+    fn send(&mut self, _: ProtocolBuffer) {}
+}
+
+fn fault_hal(config: &FaultConfig, seed: u64) -> FaultHal<ConstantHal> {
+    FaultHal::new(ConstantHal { reads: 0 }, config, seed)
+}
+
+#[test]
+fn it_passes_data_without_faults() {
+    let mut hal = fault_hal(&FaultConfig::new(), 1);
+    assert_eq!(hal.read_lasers().unwrap().data, [Mm(100.0); LASER_COUNT]);
+    assert_eq!(hal.read_lasers().unwrap().data, [Mm(200.0); LASER_COUNT]);
+}
+
+#[test]
+fn it_is_reproducible() {
+    let mut config = FaultConfig::new();
+    config.laser_noise = Mm(20.0);
+    config.laser_dropout_max = 0.1;
+    config.laser_dropout_zero = 0.1;
+    let mut hal1 = fault_hal(&config, 42);
+    let mut hal2 = fault_hal(&config, 42);
+    for _ in 0..10 {
+        assert_eq!(hal1.read_lasers().unwrap().data, hal2.read_lasers().unwrap().data);
+    }
+}
+
+#[test]
+fn it_injects_faults() {
+    let mut config = FaultConfig::new();
+    config.laser_stuck = Some(3);
+    config.imu_heading_bias = Rad(0.5);
+    config.imu_heading_drift = Rad(0.25);
+    config.latency = 2;
+    let mut hal = fault_hal(&config, 7);
+
+    // Stale data until latency is filled
+    assert_eq!(hal.read_lasers().unwrap().data[0], Mm(100.0));
+    assert_eq!(hal.read_lasers().unwrap().data[0], Mm(100.0));
+    let data = hal.read_lasers().unwrap().data;
+    assert_eq!(data[0], Mm(100.0));
+    assert_eq!(data[3], Mm(100.0));
+    let sample = hal.read_lasers().unwrap();
+    assert_eq!(sample.timestamp, 4000);
+    assert_eq!(sample.data[0], Mm(200.0));
+    assert_eq!(sample.data[3], Mm(100.0));
+
+    hal.read_imu().unwrap();
+    hal.read_imu().unwrap();
+    assert_eq!(hal.read_imu().unwrap().data.heading, Rad(0.75));
+    assert_eq!(hal.read_imu().unwrap().data.heading, Rad(1.0));
+}
+
+#[test]
+fn it_drops_out_readings() {
+    let mut config = FaultConfig::new();
+    config.laser_dropout_zero = 1.0;
+    let mut hal = fault_hal(&config, 3);
+    assert_eq!(hal.read_lasers().unwrap().data, [Mm(0.0); LASER_COUNT]);
+}
@@ -0,0 +1,58 @@
+use crate::kinematics::*;
+use crate::*;
+
+fn car() -> SkidSteer {
+    SkidSteer::new(Mm(100.0), Mm(25.0), 10.0)
+}
+
+fn encoders(left: AngularVelocity, right: AngularVelocity) -> EncoderData {
+    let wheel = |velocity| WheelEncoderData { ticks: 0, velocity };
+    EncoderData {
+        back_left: wheel(left),
+        back_right: wheel(right),
+        front_left: wheel(left),
+        front_right: wheel(right),
+    }
+}
+
+#[test]
+fn it_drives_straight() {
+    let twist = Twist {
+        forward: 125.0,
+        yaw_rate: 0.0,
+    };
+    assert_eq!(car().motor_powers(&twist), [0.5, 0.5, 0.5, 0.5]);
+}
+
+#[test]
+fn it_turns_right_with_positive_yaw_rate() {
+    let twist = Twist {
+        forward: 0.0,
+        yaw_rate: 2.5,
+    };
+    assert_eq!(car().wheel_velocities(&twist), (5.0, -5.0));
+    assert_eq!(car().motor_powers(&twist), [0.5, -0.5, 0.5, -0.5]);
+}
+
+#[test]
+fn it_saturates_preserving_turn_ratio() {
+    let twist = Twist {
+        forward: 500.0,
+        yaw_rate: 5.0,
+    };
+    // Unsaturated powers would be 3 and 1
+    let powers = car().motor_powers(&twist);
+    assert_eq!(powers[0], 1.0);
+    assert!((powers[1] - 1.0 / 3.0).abs() < 0.0001);
+    assert_eq!(powers[0], powers[2]);
+    assert_eq!(powers[1], powers[3]);
+}
+
+#[test]
+fn it_computes_twist_from_wheel_velocities() {
+    let twist = car().twist(&encoders(6.0, 2.0));
+    assert_eq!(twist.forward, 100.0);
+    assert_eq!(twist.yaw_rate, 1.0);
+    let (left, right) = car().wheel_velocities(&twist);
+    assert_eq!((left, right), (6.0, 2.0));
+}
@@ -0,0 +1,31 @@
+use crate::layout::*;
+use crate::{Mm, Rad, LASER_COUNT};
+use core::f32::consts::FRAC_PI_2;
+
+fn check_direction(d1: [f32; 3], d2: [f32; 3]) {
+    for i in 0..3 {
+        assert!((d1[i] - d2[i]).abs() < 0.001, "{:?} != {:?}", d1, d2);
+    }
+}
+
+fn check_point(p1: [Mm; 3], p2: [f32; 3]) {
+    check_direction([p1[0].0, p1[1].0, p1[2].0], p2);
+}
+
+#[test]
+fn it_spreads_lasers_from_left_to_right() {
+    let layout = LaserLayout::fan(Mm(0.0), Mm(10.0), Mm(20.0), Rad(-FRAC_PI_2), Rad(FRAC_PI_2));
+    // Leftmost laser points along +x, rightmost along -x
+    check_point(layout.lasers[0].point(Mm(100.0)), [100.0, 10.0, 20.0]);
+    check_point(layout.lasers[LASER_COUNT - 1].point(Mm(100.0)), [-100.0, 10.0, 20.0]);
+}
+
+#[test]
+fn it_handles_pitch_and_range() {
+    let mut laser = LaserLayout::new().lasers[0];
+    laser.yaw = Rad(0.0);
+    laser.pitch = Rad(FRAC_PI_2);
+    check_direction(laser.direction(), [0.0, 1.0, 0.0]);
+    assert_eq!(laser.clamp(Mm(0.0)), laser.min_range);
+    assert_eq!(laser.clamp(Mm(100000.0)), laser.max_range);
+}
@@ -0,0 +1,73 @@
+use crate::mock::*;
+use crate::*;
+
+fn buffer_from_str(s: &str) -> ProtocolBuffer {
+    let mut buffer = new_protocol_buffer();
+    buffer[..s.len()].copy_from_slice(s.as_bytes());
+    buffer
+}
+
+#[test]
+fn it_plays_the_timeline() {
+    let mut hal = MockHal::new();
+    hal.lasers_at(2000, Ok([Mm(200.0); LASER_COUNT]));
+    hal.lasers_at(1000, Ok([Mm(100.0); LASER_COUNT]));
+    hal.lasers_at(3000, Err(HalError::Timeout(Sensor::Laser(4))));
+
+    let sample = hal.read_lasers().unwrap();
+    assert_eq!(sample.timestamp, 0);
+    assert_eq!(sample.data[0], layout::LASER_MAX_RANGE);
+
+    hal.set_time(1500);
+    let sample = hal.read_lasers().unwrap();
+    assert_eq!(sample.timestamp, 1000);
+    assert_eq!(sample.data[0], Mm(100.0));
+
+    hal.advance(500);
+    assert_eq!(hal.read_lasers().unwrap().data[0], Mm(200.0));
+    assert!(hal.read_imu().is_ok());
+    assert!(hal.read_encoders().is_ok());
+    assert_eq!(hal.read_battery().unwrap().voltage, MOCK_BATTERY_VOLTAGE);
+
+    hal.advance(1000);
+    assert_eq!(
+        hal.read_lasers().err(),
+        Some(HalError::Timeout(Sensor::Laser(4)))
+    );
+}
+
+#[test]
+fn it_delivers_incoming_data_in_time() {
+    let mut hal = MockHal::new();
+    hal.set_time_step(1000);
+    hal.incoming_at(2000, buffer_from_str("PAUSE\n"));
+    hal.incoming_at(1000, buffer_from_str("RESET\n"));
+
+    assert_eq!(hal.now(), 1000);
+    assert_eq!(hal.poll(), Some(buffer_from_str("RESET\n")));
+    assert_eq!(hal.poll(), None);
+    assert_eq!(hal.now(), 2000);
+    assert_eq!(hal.poll(), Some(buffer_from_str("PAUSE\n")));
+    assert_eq!(hal.poll(), None);
+}
+
+#[test]
+fn it_captures_outputs() {
+    let mut hal = MockHal::new();
+    hal.push_init(Err(HalError::NotReady(Sensor::Imu)));
+    assert!(hal.init().is_err());
+    assert!(hal.init().is_ok());
+    assert_eq!(hal.inits(), 2);
+
+    hal.set_time(500);
+    hal.set_motor_power(0.5, -0.5, 0.25, -0.25);
+    hal.send(buffer_from_str("LOG:hello\n"));
+    let command = hal.last_motor_command().unwrap();
+    assert_eq!(command.timestamp, 500);
+    assert_eq!(command.back_right, -0.5);
+    assert_eq!(hal.sent(), &[(500, buffer_from_str("LOG:hello\n"))][..]);
+
+    hal.clear_captured();
+    assert!(hal.motor_commands().is_empty());
+    assert!(hal.sent().is_empty());
+}
@@ -0,0 +1,11 @@
+mod calibration_tests;
+mod fault_tests;
+mod kinematics_tests;
+mod layout_tests;
+#[cfg(feature = "std")]
+mod mock_tests;
+mod params_tests;
+#[cfg(feature = "std")]
+mod record_tests;
+mod safety_tests;
+mod units_tests;
@@ -0,0 +1,43 @@
+use crate::params::*;
+
+#[test]
+fn it_registers_params() {
+    let mut params = ParamRegistry::new();
+    assert!(params.is_empty());
+    params.register(3, "gain", 0.5, 0.0, 1.0).unwrap();
+    params.register(1, "threshold", 20.0, 0.0, 10.0).unwrap();
+    assert_eq!(params.len(), 2);
+    assert_eq!(params.value(3), Some(0.5));
+    assert_eq!(params.value(1), Some(10.0));
+    assert_eq!(params.value(2), None);
+    assert_eq!(
+        params.register(3, "other", 0.0, 0.0, 1.0),
+        Err(ParamError::Duplicate(3))
+    );
+    let ids: Vec<ParamId> = params.iter().map(|param| param.id).collect();
+    assert_eq!(ids, vec![3, 1]);
+}
+
+#[test]
+fn it_sets_params_in_range() {
+    let mut params = ParamRegistry::new();
+    params.register(0, "gain", 0.5, -1.0, 1.0).unwrap();
+    assert_eq!(params.set(0, 0.25), Ok(0.25));
+    assert_eq!(params.set(0, 3.0), Ok(1.0));
+    assert_eq!(params.set(0, -3.0), Ok(-1.0));
+    assert_eq!(params.set(0, f32::NAN), Ok(-1.0));
+    assert_eq!(params.set(1, 0.0), Err(ParamError::Unknown(1)));
+    assert_eq!(params.get(0).unwrap().name, "gain");
+}
+
+#[test]
+fn it_limits_param_count() {
+    let mut params = ParamRegistry::new();
+    for id in 0..PARAMS_MAX_COUNT {
+        params.register(id, "param", 0.0, 0.0, 1.0).unwrap();
+    }
+    assert_eq!(
+        params.register(PARAMS_MAX_COUNT, "param", 0.0, 0.0, 1.0),
+        Err(ParamError::Full)
+    );
+}
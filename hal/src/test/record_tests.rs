@@ -0,0 +1,199 @@
+use crate::record::*;
+use crate::*;
+
+/// Device with a clock moving at every laser read, one incoming command
+/// and a failing IMU
+struct CountingHal {
+    clock: Timestamp,
+    clock_reads: usize,
+    incoming: Option<ProtocolBuffer>,
+}
+
+impl DeviceHal for CountingHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        Ok(())
+    }
+    fn now(&mut self) -> Timestamp {
+        self.clock_reads += 1;
+        self.clock
+    }
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        Err(HalError::Timeout(Sensor::Imu))
+    }
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        let mut data = [Mm(0.0); LASER_COUNT];
+        for (i, distance) in data.iter_mut().enumerate() {
+            *distance = Mm(i as f32 * 10.5);
+        }
+        self.clock += 250;
+        Ok(Timestamped {
+            timestamp: self.clock,
+            data,
+        })
+    }
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        let wheel = WheelEncoderData {
+            ticks: -42,
+            velocity: 3.5,
+        };
+        Ok(EncoderData {
+            back_left: wheel,
+            back_right: wheel,
+            front_left: wheel,
+            front_right: wheel,
+        })
+    }
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        Err(HalError::Bus(Sensor::Laser(7)))
+    }
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        Ok(Some(BumperData {
+            front: true,
+            rear: false,
+        }))
+    }
+    fn set_motor_power(&mut self, _: MotorPower, _: MotorPower, _: MotorPower, _: MotorPower) {}
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.incoming.take()
+    }
+    fn send(&mut self, _: ProtocolBuffer) {}
+}
+
+fn buffer_from_str(s: &str) -> ProtocolBuffer {
+    let mut buffer = new_protocol_buffer();
+    buffer[..s.len()].copy_from_slice(s.as_bytes());
+    buffer
+}
+
+fn exercise<H: DeviceHal>(hal: &mut H) {
+    hal.init().unwrap();
+    hal.now();
+    while let Some(data) = hal.poll() {
+        hal.send(data);
+    }
+    hal.read_lasers().unwrap();
+    hal.set_motor_power(0.5, -0.5, 1.0, -1.0);
+    let _ = hal.read_imu();
+    hal.read_encoders().unwrap();
+    let _ = hal.read_battery();
+    hal.read_bumpers().unwrap();
+}
+
+#[test]
+fn it_replays_recordings() {
+    let mut recording = RecordingHal::new(
+        CountingHal {
+            clock: 0,
+            clock_reads: 0,
+            incoming: Some(buffer_from_str("START\n")),
+        },
+        Vec::new(),
+    );
+    exercise(&mut recording);
+    let lasers = recording.hal_mut().read_lasers().unwrap().data;
+    let log = recording.finish().unwrap();
+
+    let mut replay = ReplayHal::new(&log[..]).unwrap();
+    assert_eq!(replay.now(), 0);
+    assert!(replay.poll().unwrap()[..6] == *b"START\n");
+    assert!(replay.poll().is_none());
+    let sample = replay.read_lasers().unwrap();
+    assert_eq!(sample.timestamp, 250);
+    assert_eq!(sample.data, lasers);
+    assert_eq!(
+        replay.read_imu().err(),
+        Some(HalError::Timeout(Sensor::Imu))
+    );
+    assert_eq!(replay.read_encoders().unwrap().front_left.ticks, -42);
+    assert_eq!(
+        replay.read_battery().err(),
+        Some(HalError::Bus(Sensor::Laser(7)))
+    );
+    let bumpers = replay.read_bumpers().unwrap().unwrap();
+    assert!(bumpers.front && !bumpers.rear);
+    assert!(replay.is_finished());
+    assert_eq!(replay.read_bumpers(), Ok(None));
+
+    let motors = replay.recorded_motor_commands();
+    assert_eq!(motors.len(), 1);
+    assert_eq!(motors[0].timestamp, 0);
+    assert_eq!(motors[0].back_right, -0.5);
+    assert_eq!(replay.recorded_sent().len(), 1);
+}
+
+#[test]
+fn it_collects_replayed_outputs() {
+    let mut recording = RecordingHal::new(
+        CountingHal {
+            clock: 0,
+            clock_reads: 0,
+            incoming: None,
+        },
+        Vec::new(),
+    );
+    exercise(&mut recording);
+    let log = recording.finish().unwrap();
+
+    let mut replay = ReplayHal::new(&log[..]).unwrap();
+    exercise(&mut replay);
+    let replayed = replay.motor_commands();
+    let recorded = replay.recorded_motor_commands();
+    assert_eq!(replayed.len(), recorded.len());
+    assert_eq!(replayed[0].front_left, recorded[0].front_left);
+    assert_eq!(replayed[0].front_right, recorded[0].front_right);
+    assert!(replay.sent().is_empty());
+}
+
+#[test]
+fn it_rejects_truncated_logs() {
+    let mut recording = RecordingHal::new(
+        CountingHal {
+            clock: 0,
+            clock_reads: 0,
+            incoming: None,
+        },
+        Vec::new(),
+    );
+    exercise(&mut recording);
+    let log = recording.finish().unwrap();
+    assert!(ReplayHal::new(&log[..log.len() - 1]).is_err());
+}
+
+#[test]
+fn it_reads_the_clock_once_per_tick() {
+    let mut recording = RecordingHal::new(
+        CountingHal {
+            clock: 0,
+            clock_reads: 0,
+            incoming: None,
+        },
+        Vec::new(),
+    );
+    exercise(&mut recording);
+    assert_eq!(recording.hal_mut().clock_reads, 1);
+}
+
+#[test]
+fn it_replays_commands_at_their_recorded_time() {
+    let mut recording = RecordingHal::new(
+        CountingHal {
+            clock: 0,
+            clock_reads: 0,
+            incoming: None,
+        },
+        Vec::new(),
+    );
+    recording.now();
+    recording.read_lasers().unwrap();
+    recording.hal_mut().incoming = Some(buffer_from_str("STOP\n"));
+    recording.now();
+    recording.poll().unwrap();
+    let log = recording.finish().unwrap();
+
+    let mut replay = ReplayHal::new(&log[..]).unwrap();
+    assert_eq!(replay.now(), 0);
+    assert!(replay.poll().is_none());
+    replay.read_lasers().unwrap();
+    assert_eq!(replay.now(), 250);
+    assert!(replay.poll().unwrap()[..5] == *b"STOP\n");
+}
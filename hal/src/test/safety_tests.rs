@@ -0,0 +1,79 @@
+use crate::safety::*;
+
+/// Milliseconds to timestamp
+fn ms(time: u64) -> crate::Timestamp {
+    time * 1000
+}
+
+#[test]
+fn it_limits_slew_rate() {
+    let mut safety = MotorSafety::new(&MotorSafetyConfig::new());
+    safety.update(ms(0));
+    safety.command([1.0, 1.0, -1.0, 0.1], ms(0));
+    assert_eq!(safety.update(ms(100)), [0.4, 0.4, -0.4, 0.1]);
+    assert_eq!(
+        safety.take_intervention(),
+        Some(MotorIntervention::SlewRate)
+    );
+    assert_eq!(safety.take_intervention(), None);
+    safety.command([1.0, 1.0, -1.0, 0.1], ms(100));
+    assert_eq!(safety.update(ms(200)), [0.8, 0.8, -0.8, 0.1]);
+    assert_eq!(safety.take_intervention(), None);
+    safety.command([1.0, 1.0, -1.0, 0.1], ms(200));
+    assert_eq!(safety.update(ms(300)), [1.0, 1.0, -1.0, 0.1]);
+}
+
+#[test]
+fn it_limits_slew_rate_from_a_late_start() {
+    let start = ms(3_600_000);
+    let mut safety = MotorSafety::new(&MotorSafetyConfig::new());
+    safety.command([1.0, 1.0, -1.0, 0.1], start);
+    assert_eq!(safety.update(start + ms(100)), [0.4, 0.4, -0.4, 0.1]);
+
+    // Updates before any command do not ramp either
+    let mut safety = MotorSafety::new(&MotorSafetyConfig::new());
+    safety.update(start);
+    safety.command([1.0, 1.0, -1.0, 0.1], start + ms(50));
+    assert_eq!(safety.update(start + ms(100)), [0.4, 0.4, -0.4, 0.1]);
+}
+
+#[test]
+fn it_clamps_power() {
+    let mut safety = MotorSafety::new(&MotorSafetyConfig::new());
+    safety.command([2.0, -3.0, 0.5, f32::NAN], ms(0));
+    assert_eq!(safety.target(), [1.0, -1.0, 0.5, 0.0]);
+    assert_eq!(safety.take_intervention(), Some(MotorIntervention::Clamp));
+    safety.command([2.0, -3.0, 0.5, 0.0], ms(10));
+    assert_eq!(safety.take_intervention(), None);
+}
+
+#[test]
+fn it_stops_on_timeout() {
+    let mut safety = MotorSafety::new(&MotorSafetyConfig::new());
+    safety.update(ms(0));
+    safety.command([0.2, 0.2, 0.2, 0.2], ms(0));
+    assert_eq!(safety.update(ms(200)), [0.2, 0.2, 0.2, 0.2]);
+    assert_eq!(safety.take_intervention(), None);
+    assert_eq!(safety.update(ms(300)), [0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(safety.take_intervention(), Some(MotorIntervention::Timeout));
+    assert_eq!(safety.update(ms(400)), [0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(safety.take_intervention(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn it_stops_on_timeout_without_commands() {
+    use crate::mock::MockHal;
+    use crate::DeviceHal;
+
+    let mut hal = SafeHal::new(MockHal::new(), &MotorSafetyConfig::new());
+    hal.hal_mut().set_time_step(ms(100));
+    hal.now();
+    hal.set_motor_power(0.2, 0.2, 0.2, 0.2);
+    // Only the clock is read: the watchdog still runs
+    for _ in 0..3 {
+        hal.now();
+    }
+    assert_eq!(hal.hal().last_motor_command().unwrap().back_left, 0.0);
+    assert_eq!(hal.take_intervention(), Some(MotorIntervention::Timeout));
+}
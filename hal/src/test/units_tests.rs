@@ -0,0 +1,20 @@
+use crate::units::*;
+use core::f32::consts::PI;
+
+#[test]
+fn it_converts_units() {
+    assert_eq!(Mm(1500.0).to_m(), M(1.5));
+    assert_eq!(M(0.25).to_mm(), Mm(250.0));
+    assert!((Deg(180.0).to_rad().0 - PI).abs() < 0.0001);
+    assert!((Rad(PI / 2.0).to_deg().0 - 90.0).abs() < 0.0001);
+}
+
+#[test]
+fn it_does_arithmetic_within_a_unit() {
+    let mut length = Mm(100.0) + Mm(50.0) - Mm(30.0);
+    length += Mm(10.0);
+    assert_eq!(length, Mm(130.0));
+    assert_eq!(-length * 2.0, Mm(-260.0));
+    assert_eq!(Mm(-5.0).abs().max(Mm(3.0)), Mm(5.0));
+    assert_eq!(M(3.0) / M(1.5), 2.0);
+}
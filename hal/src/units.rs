@@ -0,0 +1,135 @@
+//! Physical units
+//!
+//! Each unit is a distinct type, so that mixing them up is a compile error.
+//! Conversions between units are explicit; the raw value is the `.0` field.
+
+use core::f32::consts::PI;
+use core::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+macro_rules! unit {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default)]
+        pub struct $name(pub f32);
+
+        impl $name {
+            pub fn abs(self) -> Self {
+                if self.0 < 0.0 {
+                    $name(-self.0)
+                } else {
+                    self
+                }
+            }
+
+            pub fn max(self, other: Self) -> Self {
+                $name(self.0.max(other.0))
+            }
+
+            pub fn min(self, other: Self) -> Self {
+                $name(self.0.min(other.0))
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, other: Self) -> Self {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                $name(self.0 - other.0)
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+            fn neg(self) -> Self {
+                $name(-self.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                self.0 -= other.0;
+            }
+        }
+
+        /// Scaling
+        impl Mul<f32> for $name {
+            type Output = Self;
+            fn mul(self, factor: f32) -> Self {
+                $name(self.0 * factor)
+            }
+        }
+
+        /// Scaling
+        impl Div<f32> for $name {
+            type Output = Self;
+            fn div(self, factor: f32) -> Self {
+                $name(self.0 / factor)
+            }
+        }
+
+        /// Ratio between two values (adimensional)
+        impl Div for $name {
+            type Output = f32;
+            fn div(self, other: Self) -> f32 {
+                self.0 / other.0
+            }
+        }
+    };
+}
+
+unit!(
+    /// Length in millimetres
+    Mm
+);
+unit!(
+    /// Length in metres
+    M
+);
+unit!(
+    /// Angle in radians
+    Rad
+);
+unit!(
+    /// Angle in degrees
+    Deg
+);
+unit!(
+    /// Acceleration in mm/s2
+    MmPerS2
+);
+
+impl Mm {
+    pub fn to_m(self) -> M {
+        M(self.0 / 1000.0)
+    }
+}
+
+impl M {
+    pub fn to_mm(self) -> Mm {
+        Mm(self.0 * 1000.0)
+    }
+}
+
+impl Rad {
+    pub fn to_deg(self) -> Deg {
+        Deg(self.0 * 180.0 / PI)
+    }
+}
+
+impl Deg {
+    pub fn to_rad(self) -> Rad {
+        Rad(self.0 * PI / 180.0)
+    }
+}
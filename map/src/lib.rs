@@ -2,7 +2,11 @@ use nalgebra::RealField;
 use nalgebra::{UnitQuaternion, Vector3, Isometry3};
 use vek::{Vec3,Quaternion};
 
+use hal::kinematics::SkidSteer;
+use hal::layout::LaserLayout;
+use hal::{Rad, M};
 use protocol::map::{Map,MapSectionShape};
+use std::ops::{Add, Mul, Sub};
 
 pub type V3 = Vec3<f32>;
 pub type Q = Quaternion<f32>;
@@ -38,28 +42,32 @@ impl RotationComponents for NaQ {
     }
 }
 
-pub const CAR_LENGTH: f32 = 0.2;
-pub const CAR_WIDTH: f32 = 0.15;
+pub const CAR_LENGTH: M = M(0.2);
+pub const CAR_WIDTH: M = M(0.15);
 pub const CAR_MASS: f32 = 0.8;
 
-pub const CAR_WHEEL_RADIUS: f32 = 0.035;
-pub const CAR_WHEEL_THICKNESS: f32 = 0.02;
+pub const CAR_WHEEL_RADIUS: M = M(0.035);
+pub const CAR_WHEEL_THICKNESS: M = M(0.02);
 pub const CAR_WHEEL_MASS: f32 = 0.1;
 
-pub const CAR_WHEEL_SPACE: f32 = 0.005;
+pub const CAR_WHEEL_SPACE: M = M(0.005);
 
-pub const MAP_WALL_H: f32 = 0.15;
-pub const MAP_WALL_THICKNESS: f32 = 0.01;
-pub const MAP_FLOOR_THICKNESS: f32 = 0.01;
+pub const CAR_MOTOR_MAX_RPM: f32 = 220.0 * 3.0;
+
+pub const MAP_WALL_H: M = M(0.15);
+pub const MAP_WALL_THICKNESS: M = M(0.01);
+pub const MAP_FLOOR_THICKNESS: M = M(0.01);
 
 #[derive(Clone, Copy)]
 pub struct Car {
-    pub length: f32,
-    pub width: f32,
+    pub length: M,
+    pub width: M,
     pub mass: f32,
-    pub wheel_radius: f32,
-    pub wheel_thickness: f32,
+    pub wheel_radius: M,
+    pub wheel_thickness: M,
     pub wheel_mass: f32,
+    pub motor_max_rpm: f32,
+    pub lasers: LaserLayout,
 
     pub position: V3,
     pub rotation: Q,
@@ -74,54 +82,90 @@ impl Car {
             wheel_radius: CAR_WHEEL_RADIUS,
             wheel_thickness: CAR_WHEEL_THICKNESS,
             wheel_mass: CAR_WHEEL_MASS,
+            motor_max_rpm: CAR_MOTOR_MAX_RPM,
+            lasers: LaserLayout::new(),
 
             position: V3::zero(),
             rotation: Q::zero(),
         }
     }
 
-    pub fn body_l(&self) -> f32 {
+    pub fn body_l(&self) -> M {
         self.length
     }
-    pub fn body_h(&self) -> f32 {
+    pub fn body_h(&self) -> M {
         self.wheel_radius * 2.0
     }
-    pub fn body_w(&self) -> f32 {
+    pub fn body_w(&self) -> M {
         self.width - ((self.wheel_thickness + CAR_WHEEL_SPACE) * 2.0)
     }
 
     pub fn body_position(&self) -> Vector3<f32> {
-        Vector3::new(0.0, self.wheel_radius / 2.0, 0.0)
+        Vector3::new(0.0, (self.wheel_radius / 2.0).0, 0.0)
     }
 
-    pub fn laser_position(&self) -> Vector3<f32> {
+    /// Laser position (m, car frame)
+    pub fn laser_position(&self, index: usize) -> Vector3<f32> {
+        let laser = &self.lasers.lasers[index];
         Vector3::new(
-            0.0,
-            self.body_position().z + (self.body_h() * 3.0 / 4.0),
-            self.body_l() * 1.0 / 4.0,
+            laser.offset_x.to_m().0,
+            laser.offset_y.to_m().0,
+            laser.offset_z.to_m().0,
         )
     }
 
-    pub fn wheel_x(&self) -> f32 {
+    /// Laser beam direction (car frame)
+    pub fn laser_direction(&self, index: usize) -> Vector3<f32> {
+        let direction = self.lasers.lasers[index].direction();
+        Vector3::new(direction[0], direction[1], direction[2])
+    }
+
+    pub fn wheel_x(&self) -> M {
         (self.width - self.wheel_thickness) / 2.0
     }
-    pub fn wheel_y(&self) -> f32 {
-        0.0
+    pub fn wheel_y(&self) -> M {
+        M(0.0)
     }
-    pub fn wheel_z(&self) -> f32 {
+    pub fn wheel_z(&self) -> M {
         (self.length / 2.0) - self.wheel_radius
     }
+
+    /// Distance between left and right wheel centers
+    pub fn track_width(&self) -> M {
+        self.wheel_x() * 2.0
+    }
+
+    /// Wheel angular velocity at full motor power (rad/s)
+    pub fn max_wheel_velocity(&self) -> f32 {
+        (360.0 * self.motor_max_rpm / 60.0).to_radians()
+    }
+
+    /// Skid steer kinematics of this car
+    pub fn kinematics(&self) -> SkidSteer {
+        SkidSteer::new(
+            self.track_width().to_mm(),
+            self.wheel_radius.to_mm(),
+            self.max_wheel_velocity(),
+        )
+    }
+}
+
+impl Default for Car {
+    fn default() -> Self {
+        Car::new()
+    }
 }
 
 #[derive(Clone, Copy)]
+/// Map section piece for physics and rendering (center in m)
 pub struct MapSectionSegment {
     pub center: NaV3,
-    pub heading: f32,
-    pub pitch: f32,
-    pub length_left: f32,
-    pub length_right: f32,
-    pub width_start: f32,
-    pub width_end: f32,
+    pub heading: Rad,
+    pub pitch: Rad,
+    pub length_left: M,
+    pub length_right: M,
+    pub width_start: M,
+    pub width_end: M,
     pub is_lighter: bool,
 }
 
@@ -129,20 +173,21 @@ pub struct MapSectionSegment {
 pub struct MapSectionBox {
     pub center: NaV3,
     pub rotation: NaQ,
-    pub width: f32,
-    pub length: f32,
-    pub height: f32,
+    pub width: M,
+    pub length: M,
+    pub height: M,
 }
 
 impl MapSectionSegment {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         center: NaV3,
-        heading: f32,
-        pitch: f32,
-        length_left: f32,
-        length_right: f32,
-        width_start: f32,
-        width_end: f32,
+        heading: Rad,
+        pitch: Rad,
+        length_left: M,
+        length_right: M,
+        width_start: M,
+        width_end: M,
         is_lighter: bool,
     ) -> Self {
         MapSectionSegment {
@@ -158,21 +203,21 @@ impl MapSectionSegment {
     }
 
     pub fn rotation(&self) -> NaQ {
-        let rotation_x = NaQ::from_axis_angle(&NaV3::x_axis(), -self.pitch);
-        let rotation_y = NaQ::from_axis_angle(&NaV3::y_axis(), self.heading);
+        let rotation_x = NaQ::from_axis_angle(&NaV3::x_axis(), -self.pitch.0);
+        let rotation_y = NaQ::from_axis_angle(&NaV3::y_axis(), self.heading.0);
         rotation_y * rotation_x
     }
 
-    pub fn max_length(&self) -> f32 {
-        f32::max(self.length_left, self.length_right)
+    pub fn max_length(&self) -> M {
+        self.length_left.max(self.length_right)
     }
 
-    pub fn max_width(&self) -> f32 {
-        f32::max(self.width_start, self.width_end)
+    pub fn max_width(&self) -> M {
+        self.width_start.max(self.width_end)
     }
 
-    fn slope_wall_extra_length(&self) -> f32 {
-        1.0 * self.pitch.abs().tan() * MAP_WALL_H
+    fn slope_wall_extra_length(&self) -> M {
+        MAP_WALL_H * self.pitch.abs().0.tan()
     }
 
     pub fn floor_box(&self) -> MapSectionBox {
@@ -192,12 +237,12 @@ impl MapSectionSegment {
         let length = self.length_left;
         let width = MAP_WALL_THICKNESS;
         let height = MAP_WALL_H;
-        let displacement = Vector3::new(0.0, 0.0, self.max_width() / 2.0);
+        let displacement = Vector3::new(0.0, 0.0, (self.max_width() / 2.0).0);
         let displacement_rotation: NaQ =
             NaQ::from_axis_angle(&NaV3::y_axis(), self.rotation().rot_y() + f32::frac_pi_2());
         let displacement = displacement_rotation.transform_vector(&displacement);
         let displacement =
-            displacement + Vector3::new(0.0, (MAP_WALL_H / 2.0) - MAP_FLOOR_THICKNESS, 0.0);
+            displacement + Vector3::new(0.0, ((MAP_WALL_H / 2.0) - MAP_FLOOR_THICKNESS).0, 0.0);
         MapSectionBox {
             center: self.center + displacement,
             width,
@@ -211,12 +256,12 @@ impl MapSectionSegment {
         let length = self.length_right;
         let width = MAP_WALL_THICKNESS;
         let height = MAP_WALL_H;
-        let displacement = Vector3::new(0.0, 0.0, self.max_width() / 2.0);
+        let displacement = Vector3::new(0.0, 0.0, (self.max_width() / 2.0).0);
         let displacement_rotation: NaQ =
             NaQ::from_axis_angle(&NaV3::y_axis(), self.rotation().rot_y() - f32::frac_pi_2());
         let displacement = displacement_rotation.transform_vector(&displacement);
         let displacement =
-            displacement + Vector3::new(0.0, (MAP_WALL_H / 2.0) - MAP_FLOOR_THICKNESS, 0.0);
+            displacement + Vector3::new(0.0, ((MAP_WALL_H / 2.0) - MAP_FLOOR_THICKNESS).0, 0.0);
         MapSectionBox {
             center: self.center + displacement,
             width,
@@ -231,7 +276,10 @@ fn v3(v: V3) -> NaV3 {
     Vector3::new(v.x, v.y, v.z)
 }
 
-fn lerp(v1: f32, v2: f32, interval: f32) -> f32 {
+fn lerp<T>(v1: T, v2: T, interval: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
     v1 + ((v2 - v1) * interval)
 }
 
@@ -245,7 +293,7 @@ pub fn map_segmentation(map: &Map) -> Vec<MapSectionSegment> {
                 segments.push(MapSectionSegment::new(
                     v3(section.center),
                     section.heading_start,
-                    0.0,
+                    Rad(0.0),
                     s.length,
                     s.length,
                     section.width_start,
@@ -254,35 +302,34 @@ pub fn map_segmentation(map: &Map) -> Vec<MapSectionSegment> {
                 ));
             }
             MapSectionShape::Slope(s) => {
+                let length = M(s.length.0.hypot(s.height.0));
                 segments.push(MapSectionSegment::new(
                     v3(section.center),
                     section.heading_start,
-                    (s.height / s.length).atan(),
-                    (s.length.powi(2) + s.height.powi(2)).sqrt(),
-                    (s.length.powi(2) + s.height.powi(2)).sqrt(),
+                    Rad((s.height / s.length).atan()),
+                    length,
+                    length,
                     section.width_start,
                     section.width_end,
                     true,
                 ));
             }
             MapSectionShape::Turn(s) => {
-                let steps = (s.turning_angle.abs().to_degrees() / 15.0) as i32;
+                let turning_angle = s.turning_angle;
+                let (radius_start, radius_end) = (s.radius_start, s.radius_end);
+                let (width_start, width_end) = (section.width_start, section.width_end);
+                let steps = (turning_angle.abs().to_deg().0 / 15.0) as i32;
                 let steps = if steps % 2 == 0 { steps + 1 } else { steps };
                 let half_steps = steps * 2;
                 let half_interval = 1.0 / half_steps as f32;
-                let angle_half_interval = (s.turning_angle * half_interval).abs();
-                let inner_length_start = (s.radius_start - (section.width_start / 2.0))
-                    * angle_half_interval.sin()
-                    * 2.0;
-                let outer_length_start = (s.radius_start + (section.width_start / 2.0))
-                    * angle_half_interval.sin()
-                    * 2.0;
-                let inner_length_end =
-                    (s.radius_end - (section.width_end / 2.0)) * angle_half_interval.sin() * 2.0;
-                let outer_length_end =
-                    (s.radius_end + (section.width_end / 2.0)) * angle_half_interval.sin() * 2.0;
+                // Segment chord length for a unit radius
+                let chord = (turning_angle * half_interval).abs().0.sin() * 2.0;
+                let inner_length_start = (radius_start - (width_start / 2.0)) * chord;
+                let outer_length_start = (radius_start + (width_start / 2.0)) * chord;
+                let inner_length_end = (radius_end - (width_end / 2.0)) * chord;
+                let outer_length_end = (radius_end + (width_end / 2.0)) * chord;
                 let (left_length_start, right_length_start, left_length_end, right_length_end) =
-                    if s.turning_angle > 0.0 {
+                    if turning_angle > Rad(0.0) {
                         (
                             inner_length_start,
                             outer_length_start,
@@ -301,19 +348,19 @@ pub fn map_segmentation(map: &Map) -> Vec<MapSectionSegment> {
                 let mut is_lighter = false;
                 for i in 0..steps {
                     let interval = half_interval * (i as f32 * 2.0 + 1.0);
-                    let angle = lerp(0.0, s.turning_angle, interval);
-                    let rot = NaQ::from_axis_angle(&NaV3::y_axis(), angle);
+                    let angle = lerp(Rad(0.0), turning_angle, interval);
+                    let rot = NaQ::from_axis_angle(&NaV3::y_axis(), angle.0);
                     let center_to_segment_center = rot.transform_vector(&v3(center_to_start))
-                        * lerp(s.radius_start, s.radius_end, interval);
+                        * lerp(radius_start, radius_end, interval).0;
                     let segment_center = v3(section.center) + center_to_segment_center;
                     segments.push(MapSectionSegment::new(
                         segment_center,
                         section.heading_start + angle,
-                        0.0,
+                        Rad(0.0),
                         lerp(left_length_start, left_length_end, interval),
                         lerp(right_length_start, right_length_end, interval),
-                        lerp(section.width_start, section.width_end, interval),
-                        lerp(section.width_start, section.width_end, interval),
+                        lerp(width_start, width_end, interval),
+                        lerp(width_start, width_end, interval),
                         is_lighter,
                     ));
                     is_lighter = !is_lighter;
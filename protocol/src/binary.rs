@@ -0,0 +1,843 @@
+//! Binary frames, for high rate telemetry
+//!
+//! A binary frame is the BINARY_FRAME_MARKER, the COBS encoding of the
+//! message payload followed by its checksum (little endian), and a line
+//! end. Encoded bytes are XORed with the line end, so that (like COBS
+//! removes zeros) no line end can appear before the last byte and line
+//! based transports carry binary frames unchanged.
+//!
+//! The payload is a tag byte followed by the message fields: numbers are
+//! little endian i32 (i16 for laser distances, clamped, and u16 for
+//! checksums), flags and small indexes are single bytes, and log lines
+//! are a length byte followed by the text.
+//! Error indexes refer to the decoded payload.
+
+use hal::{ProtocolBuffer, BINARY_FRAME_MARKER, LASER_COUNT, PROTOCOL_BUFFER_SIZE};
+
+use crate::protocol::{
+    data_checksum, BotCommand, BotEvent, Expected, FrameChecksum, MotorsPowerData,
+    ProtocolBatteryData, ProtocolBotStatus, ProtocolBumperData, ProtocolEncoderData, ProtocolError,
+    ProtocolErrorKind, ProtocolImuData, ProtocolLaserCalibration, ProtocolLaserData,
+    ProtocolLogLineData, ProtocolMapSection, ProtocolMapSectionData, ProtocolMapSectionDataSlope,
+    ProtocolMapSectionDataStraight, ProtocolMapSectionDataTurn, ProtocolParam, ProtocolRacingData,
+    ProtocolVersionData, ProtocolWaitingData, ProtocolWallData, ProtocolWheelEncoderData,
+    MAX_LOG_LINE_SIZE,
+};
+
+const CODE_END: u8 = b'\n';
+
+/// Checksum size in the payload
+const CHECKSUM_SIZE: usize = 2;
+
+/// Largest payload (with its checksum) that fits a frame once encoded
+/// (COBS adds a byte every 254, plus the marker and the line end)
+const PAYLOAD_MAX_SIZE: usize = PROTOCOL_BUFFER_SIZE - 4;
+
+type Payload = [u8; PROTOCOL_BUFFER_SIZE];
+
+const TAG_MAP_START: u8 = 1;
+const TAG_MAP_SECTION: u8 = 2;
+const TAG_MAP_END: u8 = 3;
+const TAG_RESET: u8 = 4;
+const TAG_START: u8 = 5;
+const TAG_PAUSE: u8 = 6;
+const TAG_RESTART: u8 = 7;
+const TAG_DIRECT: u8 = 8;
+const TAG_CALIBRATION_LASER: u8 = 9;
+const TAG_CALIBRATION_IMU: u8 = 10;
+const TAG_CALIBRATION_QUERY: u8 = 11;
+const TAG_CALIBRATION_STORE: u8 = 12;
+const TAG_CALIBRATE_WALL: u8 = 13;
+const TAG_CALIBRATE_STILL: u8 = 14;
+const TAG_CHECKSUM: u8 = 15;
+const TAG_BINARY: u8 = 16;
+const TAG_PARAM_SET: u8 = 17;
+const TAG_PARAM_GET: u8 = 18;
+const TAG_PARAM_LIST: u8 = 19;
+const TAG_HELLO: u8 = 20;
+const TAG_VERSION: u8 = 21;
+
+const TAG_STATUS: u8 = 1;
+const TAG_LASERS: u8 = 2;
+const TAG_IMU: u8 = 3;
+const TAG_ENCODERS: u8 = 4;
+const TAG_BATTERY: u8 = 5;
+const TAG_BUMPERS: u8 = 6;
+const TAG_ACK: u8 = 7;
+const TAG_NAK: u8 = 8;
+// (CALIBRATION-LASER, CALIBRATION-IMU and VERSION share the command tags)
+const TAG_LOG: u8 = 11;
+const TAG_PARAM: u8 = 12;
+
+const TAG_STRAIGHT: u8 = 1;
+const TAG_LEFT: u8 = 2;
+const TAG_RIGHT: u8 = 3;
+const TAG_UP: u8 = 4;
+const TAG_DOWN: u8 = 5;
+
+const TAG_INVALID_MAP: u8 = 1;
+const TAG_DEVICE_ERROR: u8 = 2;
+const TAG_STOPPED: u8 = 3;
+const TAG_WAITING: u8 = 4;
+const TAG_RACING: u8 = 5;
+
+/// True if the buffer holds a binary frame
+pub fn is_binary(buf: &ProtocolBuffer) -> bool {
+    buf[0] == BINARY_FRAME_MARKER
+}
+
+fn put_u8(payload: &mut Payload, index: usize, value: u8) -> usize {
+    payload[index] = value;
+    index + 1
+}
+
+fn put_i32(payload: &mut Payload, index: usize, value: i32) -> usize {
+    payload[index..index + 4].copy_from_slice(&value.to_le_bytes());
+    index + 4
+}
+
+fn put_i16(payload: &mut Payload, index: usize, value: i32) -> usize {
+    let value = value.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+    payload[index..index + 2].copy_from_slice(&value.to_le_bytes());
+    index + 2
+}
+
+fn put_param(payload: &mut Payload, index: usize, data: &ProtocolParam) -> usize {
+    let index = put_i32(payload, index, data.id as i32);
+    put_i32(payload, index, data.value)
+}
+
+fn put_laser_calibration(
+    payload: &mut Payload,
+    index: usize,
+    data: &ProtocolLaserCalibration,
+) -> usize {
+    let mut index = put_u8(payload, index, data.index as u8);
+    index = put_i32(payload, index, data.offset);
+    put_i32(payload, index, data.scale)
+}
+
+/// Ok is value and next index
+fn take_u8(
+    payload: &[u8],
+    index: usize,
+    field: &'static str,
+) -> Result<(u8, usize), ProtocolError> {
+    match payload.get(index) {
+        Some(value) => Ok((*value, index + 1)),
+        None => Err(ProtocolError::new(index, ProtocolErrorKind::Truncated).with_field(field)),
+    }
+}
+
+/// Ok is value and next index
+fn take_i32(
+    payload: &[u8],
+    index: usize,
+    field: &'static str,
+) -> Result<(i32, usize), ProtocolError> {
+    match payload.get(index..index + 4) {
+        Some(bytes) => {
+            let mut value = [0; 4];
+            value.copy_from_slice(bytes);
+            Ok((i32::from_le_bytes(value), index + 4))
+        }
+        None => Err(ProtocolError::new(index, ProtocolErrorKind::Truncated).with_field(field)),
+    }
+}
+
+/// Ok is value and next index
+fn take_i16(
+    payload: &[u8],
+    index: usize,
+    field: &'static str,
+) -> Result<(i32, usize), ProtocolError> {
+    match payload.get(index..index + 2) {
+        Some(bytes) => Ok((i16::from_le_bytes([bytes[0], bytes[1]]) as i32, index + 2)),
+        None => Err(ProtocolError::new(index, ProtocolErrorKind::Truncated).with_field(field)),
+    }
+}
+
+/// Ok is value (not negative) and next index
+fn take_usize(
+    payload: &[u8],
+    index: usize,
+    field: &'static str,
+) -> Result<(usize, usize), ProtocolError> {
+    match take_i32(payload, index, field)? {
+        (value, next) if value >= 0 => Ok((value as usize, next)),
+        _ => Err(ProtocolError::out_of_range(index, field)),
+    }
+}
+
+/// Ok is value (0 or 1) and next index
+fn take_flag(
+    payload: &[u8],
+    index: usize,
+    field: &'static str,
+) -> Result<(bool, usize), ProtocolError> {
+    match take_u8(payload, index, field)? {
+        (0, next) => Ok((false, next)),
+        (1, next) => Ok((true, next)),
+        _ => Err(ProtocolError::out_of_range(index, field)),
+    }
+}
+
+fn take_param(payload: &[u8], index: usize) -> Result<(ProtocolParam, usize), ProtocolError> {
+    let (id, next) = take_usize(payload, index, "id")?;
+    let (value, next) = take_i32(payload, next, "value")?;
+    Ok((ProtocolParam { id, value }, next))
+}
+
+fn take_laser_calibration(
+    payload: &[u8],
+    index: usize,
+) -> Result<(ProtocolLaserCalibration, usize), ProtocolError> {
+    let (laser, next) = take_u8(payload, index, "index")?;
+    if laser as usize >= LASER_COUNT {
+        return Err(ProtocolError::out_of_range(index, "index"));
+    }
+    let (offset, next) = take_i32(payload, next, "offset")?;
+    let (scale, next) = take_i32(payload, next, "scale")?;
+    Ok((
+        ProtocolLaserCalibration {
+            index: laser as usize,
+            offset,
+            scale,
+        },
+        next,
+    ))
+}
+
+fn match_payload_end(payload: &[u8], index: usize) -> Result<(), ProtocolError> {
+    if index == payload.len() {
+        Ok(())
+    } else {
+        Err(ProtocolError::new(
+            index,
+            ProtocolErrorKind::Expected(Expected::End),
+        ))
+    }
+}
+
+/// Frame a payload (of the given length), returning its checksum
+fn write_payload(payload: &mut Payload, length: usize, buf: &mut ProtocolBuffer) -> FrameChecksum {
+    let length = length.min(PAYLOAD_MAX_SIZE - CHECKSUM_SIZE);
+    let checksum = data_checksum(&payload[..length]);
+    payload[length..length + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+    let data = &payload[..length + CHECKSUM_SIZE];
+
+    // COBS encoding
+    buf[0] = BINARY_FRAME_MARKER;
+    let mut code_index = 1;
+    let mut index = 2;
+    let mut code: u8 = 1;
+    for value in data.iter() {
+        if *value == 0 {
+            buf[code_index] = code;
+            code_index = index;
+            index += 1;
+            code = 1;
+        } else {
+            buf[index] = *value;
+            index += 1;
+            code += 1;
+            if code == 0xFF {
+                buf[code_index] = code;
+                code_index = index;
+                index += 1;
+                code = 1;
+            }
+        }
+    }
+    buf[code_index] = code;
+
+    for code in buf[1..index].iter_mut() {
+        *code ^= CODE_END;
+    }
+    buf[index] = CODE_END;
+    checksum
+}
+
+/// Decode a frame, Ok is the payload (without checksum) and the checksum
+fn read_payload(
+    buf: &ProtocolBuffer,
+    payload: &mut Payload,
+) -> Result<(usize, FrameChecksum), ProtocolError> {
+    let end = match buf.iter().skip(1).position(|code| *code == CODE_END) {
+        Some(end) => end + 1,
+        None => {
+            return Err(ProtocolError::new(
+                PROTOCOL_BUFFER_SIZE,
+                ProtocolErrorKind::Unterminated,
+            ))
+        }
+    };
+
+    // COBS decoding
+    let mut index = 1;
+    let mut length = 0;
+    while index < end {
+        let code = buf[index] ^ CODE_END;
+        if code == 0 {
+            return Err(ProtocolError::new(index, ProtocolErrorKind::Malformed));
+        }
+        index += 1;
+        for _ in 1..code {
+            if index >= end {
+                return Err(ProtocolError::new(index, ProtocolErrorKind::Malformed));
+            }
+            payload[length] = buf[index] ^ CODE_END;
+            length += 1;
+            index += 1;
+        }
+        if code < 0xFF && index < end {
+            payload[length] = 0;
+            length += 1;
+        }
+    }
+
+    if length < CHECKSUM_SIZE + 1 {
+        return Err(ProtocolError::new(length, ProtocolErrorKind::Truncated));
+    }
+    length -= CHECKSUM_SIZE;
+    let checksum = data_checksum(&payload[..length]);
+    let expected = u16::from_le_bytes([payload[length], payload[length + 1]]);
+    if checksum != expected {
+        return Err(ProtocolError::new(
+            length,
+            ProtocolErrorKind::ChecksumMismatch,
+        ));
+    }
+    Ok((length, checksum))
+}
+
+/// Write a command as a binary frame, returning its checksum
+pub fn write_command(cmd: &BotCommand, buf: &mut ProtocolBuffer) -> FrameChecksum {
+    let mut payload: Payload = [0; PROTOCOL_BUFFER_SIZE];
+    let p = &mut payload;
+    let index = match cmd {
+        BotCommand::MapStart(count) => {
+            let index = put_u8(p, 0, TAG_MAP_START);
+            put_i32(p, index, *count as i32)
+        }
+        BotCommand::MapSection(section) => {
+            let mut index = put_u8(p, 0, TAG_MAP_SECTION);
+            index = put_i32(p, index, section.index as i32);
+            match section.data {
+                ProtocolMapSectionData::Straight(data) => {
+                    index = put_u8(p, index, TAG_STRAIGHT);
+                    index = put_i32(p, index, data.length);
+                    index = put_i32(p, index, data.width_start);
+                    put_i32(p, index, data.width_end)
+                }
+                ProtocolMapSectionData::TurnLeft(data)
+                | ProtocolMapSectionData::TurnRight(data) => {
+                    let tag = match section.data {
+                        ProtocolMapSectionData::TurnLeft(_) => TAG_LEFT,
+                        _ => TAG_RIGHT,
+                    };
+                    index = put_u8(p, index, tag);
+                    index = put_i32(p, index, data.angle);
+                    index = put_i32(p, index, data.width_start);
+                    index = put_i32(p, index, data.width_end);
+                    index = put_i32(p, index, data.radius_start);
+                    put_i32(p, index, data.radius_end)
+                }
+                ProtocolMapSectionData::SlopeUp(data) | ProtocolMapSectionData::SlopeDown(data) => {
+                    let tag = match section.data {
+                        ProtocolMapSectionData::SlopeUp(_) => TAG_UP,
+                        _ => TAG_DOWN,
+                    };
+                    index = put_u8(p, index, tag);
+                    index = put_i32(p, index, data.length);
+                    index = put_i32(p, index, data.height);
+                    index = put_i32(p, index, data.width_start);
+                    put_i32(p, index, data.width_end)
+                }
+            }
+        }
+        BotCommand::MapEnd => put_u8(p, 0, TAG_MAP_END),
+        BotCommand::Reset => put_u8(p, 0, TAG_RESET),
+        BotCommand::Start => put_u8(p, 0, TAG_START),
+        BotCommand::Pause => put_u8(p, 0, TAG_PAUSE),
+        BotCommand::Restart => put_u8(p, 0, TAG_RESTART),
+        BotCommand::Direct(data) => {
+            let mut index = put_u8(p, 0, TAG_DIRECT);
+            index = put_i32(p, index, data.back_left);
+            index = put_i32(p, index, data.back_right);
+            index = put_i32(p, index, data.front_left);
+            put_i32(p, index, data.front_right)
+        }
+        BotCommand::CalibrationLaser(data) => {
+            let index = put_u8(p, 0, TAG_CALIBRATION_LASER);
+            put_laser_calibration(p, index, data)
+        }
+        BotCommand::CalibrationImu(bias) => {
+            let index = put_u8(p, 0, TAG_CALIBRATION_IMU);
+            put_i32(p, index, *bias)
+        }
+        BotCommand::CalibrationQuery => put_u8(p, 0, TAG_CALIBRATION_QUERY),
+        BotCommand::CalibrationStore => put_u8(p, 0, TAG_CALIBRATION_STORE),
+        BotCommand::CalibrateWall(data) => {
+            let mut index = put_u8(p, 0, TAG_CALIBRATE_WALL);
+            index = put_i32(p, index, data.distance);
+            put_i32(p, index, data.yaw)
+        }
+        BotCommand::CalibrateStill => put_u8(p, 0, TAG_CALIBRATE_STILL),
+        BotCommand::Checksum(checked) => {
+            let index = put_u8(p, 0, TAG_CHECKSUM);
+            put_u8(p, index, *checked as u8)
+        }
+        BotCommand::Binary(binary) => {
+            let index = put_u8(p, 0, TAG_BINARY);
+            put_u8(p, index, *binary as u8)
+        }
+        BotCommand::ParamSet(data) => {
+            let index = put_u8(p, 0, TAG_PARAM_SET);
+            put_param(p, index, data)
+        }
+        BotCommand::ParamGet(id) => {
+            let index = put_u8(p, 0, TAG_PARAM_GET);
+            put_i32(p, index, *id as i32)
+        }
+        BotCommand::ParamList => put_u8(p, 0, TAG_PARAM_LIST),
+        BotCommand::Hello => put_u8(p, 0, TAG_HELLO),
+        BotCommand::Version => put_u8(p, 0, TAG_VERSION),
+    };
+    write_payload(&mut payload, index, buf)
+}
+
+/// Parse a binary command frame, Ok includes the frame checksum
+pub fn parse_command(buf: &ProtocolBuffer) -> Result<(BotCommand, FrameChecksum), ProtocolError> {
+    let mut payload: Payload = [0; PROTOCOL_BUFFER_SIZE];
+    let (length, checksum) = read_payload(buf, &mut payload)?;
+    let p = &payload[..length];
+    let (tag, index) = take_u8(p, 0, "tag")?;
+    let (cmd, index) = match tag {
+        TAG_MAP_START => {
+            let (count, index) = take_usize(p, index, "count")?;
+            (BotCommand::MapStart(count), index)
+        }
+        TAG_MAP_SECTION => {
+            let (section_index, index) = take_usize(p, index, "index")?;
+            let (shape, index) = take_u8(p, index, "shape")?;
+            let (data, index) = match shape {
+                TAG_STRAIGHT => {
+                    let (length, index) = take_i32(p, index, "length")?;
+                    let (width_start, index) = take_i32(p, index, "width_start")?;
+                    let (width_end, index) = take_i32(p, index, "width_end")?;
+                    (
+                        ProtocolMapSectionData::Straight(ProtocolMapSectionDataStraight {
+                            length,
+                            width_start,
+                            width_end,
+                        }),
+                        index,
+                    )
+                }
+                TAG_LEFT | TAG_RIGHT => {
+                    let (angle, index) = take_i32(p, index, "angle")?;
+                    let (width_start, index) = take_i32(p, index, "width_start")?;
+                    let (width_end, index) = take_i32(p, index, "width_end")?;
+                    let (radius_start, index) = take_i32(p, index, "radius_start")?;
+                    let (radius_end, index) = take_i32(p, index, "radius_end")?;
+                    let data = ProtocolMapSectionDataTurn {
+                        angle,
+                        width_start,
+                        width_end,
+                        radius_start,
+                        radius_end,
+                    };
+                    if shape == TAG_LEFT {
+                        (ProtocolMapSectionData::TurnLeft(data), index)
+                    } else {
+                        (ProtocolMapSectionData::TurnRight(data), index)
+                    }
+                }
+                TAG_UP | TAG_DOWN => {
+                    let (length, index) = take_i32(p, index, "length")?;
+                    let (height, index) = take_i32(p, index, "height")?;
+                    let (width_start, index) = take_i32(p, index, "width_start")?;
+                    let (width_end, index) = take_i32(p, index, "width_end")?;
+                    let data = ProtocolMapSectionDataSlope {
+                        length,
+                        height,
+                        width_start,
+                        width_end,
+                    };
+                    if shape == TAG_UP {
+                        (ProtocolMapSectionData::SlopeUp(data), index)
+                    } else {
+                        (ProtocolMapSectionData::SlopeDown(data), index)
+                    }
+                }
+                _ => {
+                    return Err(
+                        ProtocolError::new(index - 1, ProtocolErrorKind::UnknownKeyword)
+                            .with_field("shape"),
+                    )
+                }
+            };
+            (
+                BotCommand::MapSection(ProtocolMapSection {
+                    index: section_index,
+                    data,
+                }),
+                index,
+            )
+        }
+        TAG_MAP_END => (BotCommand::MapEnd, index),
+        TAG_RESET => (BotCommand::Reset, index),
+        TAG_START => (BotCommand::Start, index),
+        TAG_PAUSE => (BotCommand::Pause, index),
+        TAG_RESTART => (BotCommand::Restart, index),
+        TAG_DIRECT => {
+            let (back_left, index) = take_i32(p, index, "back_left")?;
+            let (back_right, index) = take_i32(p, index, "back_right")?;
+            let (front_left, index) = take_i32(p, index, "front_left")?;
+            let (front_right, index) = take_i32(p, index, "front_right")?;
+            (
+                BotCommand::Direct(MotorsPowerData {
+                    back_left,
+                    back_right,
+                    front_left,
+                    front_right,
+                }),
+                index,
+            )
+        }
+        TAG_CALIBRATION_LASER => {
+            let (data, index) = take_laser_calibration(p, index)?;
+            (BotCommand::CalibrationLaser(data), index)
+        }
+        TAG_CALIBRATION_IMU => {
+            let (bias, index) = take_i32(p, index, "bias")?;
+            (BotCommand::CalibrationImu(bias), index)
+        }
+        TAG_CALIBRATION_QUERY => (BotCommand::CalibrationQuery, index),
+        TAG_CALIBRATION_STORE => (BotCommand::CalibrationStore, index),
+        TAG_CALIBRATE_WALL => {
+            let (distance, index) = take_i32(p, index, "distance")?;
+            let (yaw, index) = take_i32(p, index, "yaw")?;
+            (
+                BotCommand::CalibrateWall(ProtocolWallData { distance, yaw }),
+                index,
+            )
+        }
+        TAG_CALIBRATE_STILL => (BotCommand::CalibrateStill, index),
+        TAG_CHECKSUM => {
+            let (checked, index) = take_flag(p, index, "checked")?;
+            (BotCommand::Checksum(checked), index)
+        }
+        TAG_BINARY => {
+            let (binary, index) = take_flag(p, index, "binary")?;
+            (BotCommand::Binary(binary), index)
+        }
+        TAG_PARAM_SET => {
+            let (data, index) = take_param(p, index)?;
+            (BotCommand::ParamSet(data), index)
+        }
+        TAG_PARAM_GET => {
+            let (id, index) = take_usize(p, index, "id")?;
+            (BotCommand::ParamGet(id), index)
+        }
+        TAG_PARAM_LIST => (BotCommand::ParamList, index),
+        TAG_HELLO => (BotCommand::Hello, index),
+        TAG_VERSION => (BotCommand::Version, index),
+        _ => return Err(ProtocolError::new(0, ProtocolErrorKind::UnknownKeyword).with_field("tag")),
+    };
+    match_payload_end(p, index)?;
+    Ok((cmd, checksum))
+}
+
+/// Write an event as a binary frame, returning its checksum
+pub fn write_event(evt: &BotEvent, buf: &mut ProtocolBuffer) -> FrameChecksum {
+    let mut payload: Payload = [0; PROTOCOL_BUFFER_SIZE];
+    let p = &mut payload;
+    let index = match evt {
+        BotEvent::Status(status) => {
+            let index = put_u8(p, 0, TAG_STATUS);
+            match status {
+                ProtocolBotStatus::InvalidMap => put_u8(p, index, TAG_INVALID_MAP),
+                ProtocolBotStatus::DeviceError => put_u8(p, index, TAG_DEVICE_ERROR),
+                ProtocolBotStatus::Stopped => put_u8(p, index, TAG_STOPPED),
+                ProtocolBotStatus::Waiting(data) => {
+                    let mut index = put_u8(p, index, TAG_WAITING);
+                    index = put_i32(p, index, data.target);
+                    put_i32(p, index, data.elapsed)
+                }
+                ProtocolBotStatus::Racing(data) => {
+                    let mut index = put_u8(p, index, TAG_RACING);
+                    index = put_i32(p, index, data.section as i32);
+                    index = put_i32(p, index, data.completion_low);
+                    index = put_i32(p, index, data.completion_high);
+                    index = put_i32(p, index, data.positioning_left);
+                    put_i32(p, index, data.positioning_right)
+                }
+            }
+        }
+        BotEvent::Lasers(data) => {
+            let mut index = put_u8(p, 0, TAG_LASERS);
+            for laser in data.iter() {
+                index = put_i16(p, index, *laser);
+            }
+            index
+        }
+        BotEvent::Imu(data) => {
+            let mut index = put_u8(p, 0, TAG_IMU);
+            for value in [
+                data.rotation_x,
+                data.rotation_y,
+                data.rotation_z,
+                data.acceleration_x,
+                data.acceleration_y,
+                data.acceleration_z,
+                data.gravity_x,
+                data.gravity_y,
+                data.gravity_z,
+            ]
+            .iter()
+            {
+                index = put_i32(p, index, *value);
+            }
+            index
+        }
+        BotEvent::Encoders(data) => {
+            let mut index = put_u8(p, 0, TAG_ENCODERS);
+            for wheel in [
+                &data.back_left,
+                &data.back_right,
+                &data.front_left,
+                &data.front_right,
+            ]
+            .iter()
+            {
+                index = put_i32(p, index, wheel.ticks);
+                index = put_i32(p, index, wheel.velocity);
+            }
+            index
+        }
+        BotEvent::Battery(data) => {
+            let mut index = put_u8(p, 0, TAG_BATTERY);
+            index = put_i32(p, index, data.voltage);
+            put_i32(p, index, data.current)
+        }
+        BotEvent::Bumpers(data) => {
+            let mut index = put_u8(p, 0, TAG_BUMPERS);
+            index = put_u8(p, index, data.front as u8);
+            put_u8(p, index, data.rear as u8)
+        }
+        BotEvent::CalibrationLaser(data) => {
+            let index = put_u8(p, 0, TAG_CALIBRATION_LASER);
+            put_laser_calibration(p, index, data)
+        }
+        BotEvent::CalibrationImu(bias) => {
+            let index = put_u8(p, 0, TAG_CALIBRATION_IMU);
+            put_i32(p, index, *bias)
+        }
+        BotEvent::Ack(checksum) => {
+            let index = put_u8(p, 0, TAG_ACK);
+            p[index..index + 2].copy_from_slice(&checksum.to_le_bytes());
+            index + 2
+        }
+        BotEvent::Nak(position) => {
+            let index = put_u8(p, 0, TAG_NAK);
+            put_i32(p, index, *position as i32)
+        }
+        BotEvent::Param(data) => {
+            let index = put_u8(p, 0, TAG_PARAM);
+            put_param(p, index, data)
+        }
+        BotEvent::Version(data) => {
+            let mut index = put_u8(p, 0, TAG_VERSION);
+            index = put_i32(p, index, data.protocol);
+            index = put_i32(p, index, data.build);
+            index = put_i32(p, index, data.lasers as i32);
+            index = put_i32(p, index, data.map_sections as i32);
+            index = put_i32(p, index, data.buffer_size as i32);
+            put_i32(p, index, data.features)
+        }
+        BotEvent::Log(line) => {
+            let mut index = put_u8(p, 0, TAG_LOG);
+            index = put_u8(p, index, line.length as u8);
+            p[index..index + line.length].copy_from_slice(&line.message[..line.length]);
+            index + line.length
+        }
+    };
+    write_payload(&mut payload, index, buf)
+}
+
+/// Parse a binary event frame, Ok includes the frame checksum
+pub fn parse_event(buf: &ProtocolBuffer) -> Result<(BotEvent, FrameChecksum), ProtocolError> {
+    let mut payload: Payload = [0; PROTOCOL_BUFFER_SIZE];
+    let (length, checksum) = read_payload(buf, &mut payload)?;
+    let p = &payload[..length];
+    let (tag, index) = take_u8(p, 0, "tag")?;
+    let (evt, index) = match tag {
+        TAG_STATUS => {
+            let (status, index) = take_u8(p, index, "status")?;
+            let (status, index) = match status {
+                TAG_INVALID_MAP => (ProtocolBotStatus::InvalidMap, index),
+                TAG_DEVICE_ERROR => (ProtocolBotStatus::DeviceError, index),
+                TAG_STOPPED => (ProtocolBotStatus::Stopped, index),
+                TAG_WAITING => {
+                    let (target, index) = take_i32(p, index, "target")?;
+                    let (elapsed, index) = take_i32(p, index, "elapsed")?;
+                    (
+                        ProtocolBotStatus::Waiting(ProtocolWaitingData { target, elapsed }),
+                        index,
+                    )
+                }
+                TAG_RACING => {
+                    let (section, index) = take_usize(p, index, "section")?;
+                    let (completion_low, index) = take_i32(p, index, "completion_low")?;
+                    let (completion_high, index) = take_i32(p, index, "completion_high")?;
+                    let (positioning_left, index) = take_i32(p, index, "positioning_left")?;
+                    let (positioning_right, index) = take_i32(p, index, "positioning_right")?;
+                    (
+                        ProtocolBotStatus::Racing(ProtocolRacingData {
+                            section,
+                            completion_low,
+                            completion_high,
+                            positioning_left,
+                            positioning_right,
+                        }),
+                        index,
+                    )
+                }
+                _ => {
+                    return Err(
+                        ProtocolError::new(index - 1, ProtocolErrorKind::UnknownKeyword)
+                            .with_field("status"),
+                    )
+                }
+            };
+            (BotEvent::Status(status), index)
+        }
+        TAG_LASERS => {
+            let mut data: ProtocolLaserData = [0; LASER_COUNT];
+            let mut index = index;
+            for laser in data.iter_mut() {
+                let (value, next) = take_i16(p, index, "lasers")?;
+                *laser = value;
+                index = next;
+            }
+            (BotEvent::Lasers(data), index)
+        }
+        TAG_IMU => {
+            let mut values = [0; 9];
+            let mut index = index;
+            for value in values.iter_mut() {
+                let (v, next) = take_i32(p, index, "imu")?;
+                *value = v;
+                index = next;
+            }
+            (
+                BotEvent::Imu(ProtocolImuData {
+                    rotation_x: values[0],
+                    rotation_y: values[1],
+                    rotation_z: values[2],
+                    acceleration_x: values[3],
+                    acceleration_y: values[4],
+                    acceleration_z: values[5],
+                    gravity_x: values[6],
+                    gravity_y: values[7],
+                    gravity_z: values[8],
+                }),
+                index,
+            )
+        }
+        TAG_ENCODERS => {
+            let mut wheels = [ProtocolWheelEncoderData {
+                ticks: 0,
+                velocity: 0,
+            }; 4];
+            let mut index = index;
+            for wheel in wheels.iter_mut() {
+                let (ticks, next) = take_i32(p, index, "ticks")?;
+                let (velocity, next) = take_i32(p, next, "velocity")?;
+                *wheel = ProtocolWheelEncoderData { ticks, velocity };
+                index = next;
+            }
+            (
+                BotEvent::Encoders(ProtocolEncoderData {
+                    back_left: wheels[0],
+                    back_right: wheels[1],
+                    front_left: wheels[2],
+                    front_right: wheels[3],
+                }),
+                index,
+            )
+        }
+        TAG_BATTERY => {
+            let (voltage, index) = take_i32(p, index, "voltage")?;
+            let (current, index) = take_i32(p, index, "current")?;
+            (
+                BotEvent::Battery(ProtocolBatteryData { voltage, current }),
+                index,
+            )
+        }
+        TAG_BUMPERS => {
+            let (front, index) = take_flag(p, index, "front")?;
+            let (rear, index) = take_flag(p, index, "rear")?;
+            (BotEvent::Bumpers(ProtocolBumperData { front, rear }), index)
+        }
+        TAG_CALIBRATION_LASER => {
+            let (data, index) = take_laser_calibration(p, index)?;
+            (BotEvent::CalibrationLaser(data), index)
+        }
+        TAG_CALIBRATION_IMU => {
+            let (bias, index) = take_i32(p, index, "bias")?;
+            (BotEvent::CalibrationImu(bias), index)
+        }
+        TAG_ACK => {
+            let (low, index) = take_u8(p, index, "checksum")?;
+            let (high, index) = take_u8(p, index, "checksum")?;
+            (BotEvent::Ack(u16::from_le_bytes([low, high])), index)
+        }
+        TAG_NAK => {
+            let (position, next) = take_usize(p, index, "index")?;
+            (BotEvent::Nak(position), next)
+        }
+        TAG_PARAM => {
+            let (data, index) = take_param(p, index)?;
+            (BotEvent::Param(data), index)
+        }
+        TAG_VERSION => {
+            let (protocol, index) = take_i32(p, index, "protocol")?;
+            let (build, index) = take_i32(p, index, "build")?;
+            let (lasers, index) = take_usize(p, index, "lasers")?;
+            let (map_sections, index) = take_usize(p, index, "map_sections")?;
+            let (buffer_size, index) = take_usize(p, index, "buffer_size")?;
+            let (features, index) = take_i32(p, index, "features")?;
+            (
+                BotEvent::Version(ProtocolVersionData {
+                    protocol,
+                    build,
+                    lasers,
+                    map_sections,
+                    buffer_size,
+                    features,
+                }),
+                index,
+            )
+        }
+        TAG_LOG => {
+            let (length, index) = take_u8(p, index, "message")?;
+            let length = length as usize;
+            if length > MAX_LOG_LINE_SIZE {
+                return Err(ProtocolError::out_of_range(index - 1, "message"));
+            }
+            let text = p.get(index..index + length).ok_or_else(|| {
+                ProtocolError::new(index, ProtocolErrorKind::Truncated).with_field("message")
+            })?;
+            let mut line = ProtocolLogLineData::new();
+            line.message[..length].copy_from_slice(text);
+            line.length = length;
+            (BotEvent::Log(line), index + length)
+        }
+        _ => return Err(ProtocolError::new(0, ProtocolErrorKind::UnknownKeyword).with_field("tag")),
+    };
+    match_payload_end(p, index)?;
+    Ok((evt, checksum))
+}
@@ -1,5 +1,9 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+pub mod binary;
 pub mod map;
 pub mod protocol;
+pub mod stream;
 use vek::{Vec3,Quaternion};
 
 pub type V3 = Vec3<f32>;
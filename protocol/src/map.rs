@@ -1,33 +1,37 @@
-use hal::{Angle, LinearDimension};
-use crate::protocol::{ProtocolAngle, ProtocolLinearDimension, ProtocolMapSectionData};
+use hal::{elapsed, new_protocol_buffer, Deg, Mm, ProtocolBuffer, Rad, Time, Timestamp, M};
+use crate::protocol::{
+    append_checksum, BotCommand, BotEvent, FrameChecksum, ProtocolAngle, ProtocolBotStatus,
+    ProtocolLinearDimension, ProtocolMapSection, ProtocolMapSectionData,
+};
 use crate::{Q, V3};
 use core::f32::consts::*;
+use core::fmt;
 
 #[derive(Clone, Copy)]
 /// Straigth map section
 pub struct MapSectionStraigth {
     // Length
-    pub length: LinearDimension,
+    pub length: M,
 }
 
 #[derive(Clone, Copy)]
 /// Sloping map section
 pub struct MapSectionSlope {
     // Length
-    pub length: LinearDimension,
+    pub length: M,
     // Slope height (negative if descending)
-    pub height: LinearDimension,
+    pub height: M,
 }
 
 #[derive(Clone, Copy)]
 /// Turning map section
 pub struct MapSectionTurn {
     // Starting radius
-    pub radius_start: LinearDimension,
+    pub radius_start: M,
     // Ending radius
-    pub radius_end: LinearDimension,
+    pub radius_end: M,
     // Turning angle
-    pub turning_angle: Angle,
+    pub turning_angle: Rad,
 }
 
 #[derive(Clone, Copy)]
@@ -39,14 +43,14 @@ pub enum MapSectionShape {
 }
 
 #[derive(Clone, Copy)]
-/// Map section
+/// Map section (positions are in metres)
 pub struct MapSection {
     /// Section shape
     pub shape: MapSectionShape,
     /// Starting width
-    pub width_start: LinearDimension,
+    pub width_start: M,
     /// Ending width
-    pub width_end: LinearDimension,
+    pub width_end: M,
 
     /// Center of section start
     pub start: V3,
@@ -55,25 +59,25 @@ pub struct MapSection {
     /// Either center of section (for straight and slopes) or center of rotation (for turns)
     pub center: V3,
     /// Starting heading
-    pub heading_start: Angle,
+    pub heading_start: Rad,
     /// Ending heading
-    pub heading_end: Angle,
+    pub heading_end: Rad,
 }
 
-fn dim_from_proto(dim: ProtocolLinearDimension) -> LinearDimension {
-    dim as LinearDimension / 1000.0
+fn dim_from_proto(dim: ProtocolLinearDimension) -> M {
+    Mm(dim as f32).to_m()
 }
-fn ang_from_proto(ang: ProtocolAngle) -> Angle {
-    -(ang as Angle) * 3.1415 / 180.0
+fn ang_from_proto(ang: ProtocolAngle) -> Rad {
+    -Deg(ang as f32).to_rad()
 }
 
-fn normalize_angle(angle: f32) -> f32 {
+fn normalize_angle(angle: Rad) -> Rad {
     let mut angle = angle;
-    while angle.to_degrees() > PI {
-        angle -= PI * 2.0;
+    while angle > Rad(PI) {
+        angle -= Rad(PI * 2.0);
     }
-    while angle < -PI {
-        angle += PI * 2.0;
+    while angle < Rad(-PI) {
+        angle += Rad(PI * 2.0);
     }
     angle
 }
@@ -82,8 +86,8 @@ impl MapSection {
     /// Build new section with config data
     pub fn new(
         shape: MapSectionShape,
-        width_start: LinearDimension,
-        width_end: LinearDimension,
+        width_start: M,
+        width_end: M,
     ) -> Self {
         MapSection {
             shape,
@@ -93,40 +97,40 @@ impl MapSection {
             start: V3::zero(),
             end: V3::zero(),
             center: V3::zero(),
-            heading_start: 0.0,
-            heading_end: 0.0,
+            heading_start: Rad(0.0),
+            heading_end: Rad(0.0),
         }
     }
 
     /// Check if section is valid
     pub fn is_valid(&self) -> bool {
-        if self.width_start <= 0.0 || self.width_end <= 0.0 {
+        if self.width_start <= M(0.0) || self.width_end <= M(0.0) {
             return false;
         }
         match self.shape {
             MapSectionShape::Straigth(s) => {
-                if s.length <= 0.0 {
+                if s.length <= M(0.0) {
                     return false;
                 }
             }
             MapSectionShape::Slope(s) => {
-                if s.length <= 0.0 {
+                if s.length <= M(0.0) {
                     return false;
                 }
-                if s.height == 0.0 {
+                if s.height == M(0.0) {
                     return false;
                 }
             }
             MapSectionShape::Turn(s) => {
-                if s.radius_start <= 0.0 {
+                if s.radius_start <= M(0.0) {
                     return false;
                 }
-                if s.radius_end <= 0.0 {
+                if s.radius_end <= M(0.0) {
                     return false;
                 }
             }
         }
-        return true;
+        true
     }
 
     pub fn from_protocol_data(data: &ProtocolMapSectionData) -> Self {
@@ -175,25 +179,25 @@ impl MapSection {
         }
     }
 
-    fn compute_end_geometry(&self) -> (V3, f32, V3) {
+    fn compute_end_geometry(&self) -> (V3, Rad, V3) {
         match self.shape {
             MapSectionShape::Straigth(s) => {
-                let rot = Q::rotation_y(self.heading_start);
-                let delta = rot * V3::unit_z() * s.length;
+                let rot = Q::rotation_y(self.heading_start.0);
+                let delta = rot * V3::unit_z() * s.length.0;
                 let center = self.start + (delta / 2.0);
                 (self.start + delta, self.heading_start, center)
             }
             MapSectionShape::Turn(s) => {
-                let dir_front = Q::rotation_y(self.heading_start) * V3::unit_z();
-                let dir_to_center = if s.turning_angle > 0.0 {
+                let dir_front = Q::rotation_y(self.heading_start.0) * V3::unit_z();
+                let dir_to_center = if s.turning_angle > Rad(0.0) {
                     Q::rotation_y(FRAC_PI_2) * dir_front
                 } else {
                     Q::rotation_y(-FRAC_PI_2) * dir_front
                 };
-                let center = self.start + (dir_to_center * s.radius_start);
+                let center = self.start + (dir_to_center * s.radius_start.0);
                 let dir_from_center_to_start = -dir_to_center;
                 let from_center_to_end =
-                    Q::rotation_y(s.turning_angle) * (dir_from_center_to_start * s.radius_end);
+                    Q::rotation_y(s.turning_angle.0) * (dir_from_center_to_start * s.radius_end.0);
                 (
                     center + from_center_to_end,
                     normalize_angle(self.heading_start + s.turning_angle),
@@ -201,9 +205,9 @@ impl MapSection {
                 )
             }
             MapSectionShape::Slope(s) => {
-                let rot = Q::rotation_y(self.heading_start);
-                let delta_flat = rot * V3::unit_z() * s.length;
-                let delta_height = V3::unit_y() * s.height;
+                let rot = Q::rotation_y(self.heading_start.0);
+                let delta_flat = rot * V3::unit_z() * s.length.0;
+                let delta_height = V3::unit_y() * s.height.0;
                 let delta = delta_flat + delta_height;
                 let center = self.start + (delta / 2.0);
                 (self.start + delta, self.heading_start, center)
@@ -224,9 +228,9 @@ pub struct Map {
 }
 
 const EMPTY_SECTION: MapSection = MapSection {
-    shape: MapSectionShape::Straigth(MapSectionStraigth { length: 0.0 }),
-    width_start: 0.0,
-    width_end: 0.0,
+    shape: MapSectionShape::Straigth(MapSectionStraigth { length: M(0.0) }),
+    width_start: M(0.0),
+    width_end: M(0.0),
     start: V3 {
         x: 0.0,
         y: 0.0,
@@ -242,11 +246,11 @@ const EMPTY_SECTION: MapSection = MapSection {
         y: 0.0,
         z: 0.0,
     },
-    heading_start: 0.0,
-    heading_end: 0.0,
+    heading_start: Rad(0.0),
+    heading_end: Rad(0.0),
 };
 
-impl std::ops::Index<usize> for Map {
+impl core::ops::Index<usize> for Map {
     type Output = MapSection;
 
     fn index(&self, i: usize) -> &Self::Output {
@@ -286,7 +290,7 @@ impl Map {
         }
         if self.is_valid() {
             let mut start = V3::zero();
-            let mut heading_start = 0.0;
+            let mut heading_start = Rad(0.0);
             for i in 0..self.length {
                 self.sections[i].start = start;
                 self.sections[i].heading_start = heading_start;
@@ -310,7 +314,7 @@ impl Map {
                 return false;
             }
         }
-        return true;
+        true
     }
 
     /// Make sure section index is inside map (wrap it if needed)
@@ -336,3 +340,280 @@ impl Map {
         }
     }
 }
+
+impl Default for Map {
+    fn default() -> Self {
+        Map::new()
+    }
+}
+
+/// Time to wait for the acknowledgement of a map upload frame (ms)
+pub const MAP_UPLOAD_TIMEOUT: Time = 200.0;
+/// Times a map upload frame is retried before giving up
+pub const MAP_UPLOAD_MAX_RETRIES: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Progress of a map upload
+pub enum MapUploadStatus {
+    /// Frames still waiting to be acknowledged
+    Sending,
+    /// Every frame has been acknowledged
+    Done,
+    /// A frame has not been acknowledged after all retries
+    /// (or there were too many sections)
+    Failed,
+}
+
+/// Sends a map (MAP-START, each MAP-SECTION and MAP-END) over a link in
+/// Checked frame mode, retrying each frame until the bot acknowledges it
+///
+/// Frames are sent one at a time: the next one only goes out when the
+/// previous one has been acknowledged.
+pub struct MapUploader {
+    sections: [Option<ProtocolMapSection>; MAP_SECTIONS_MAX_COUNT],
+    count: usize,
+    /// Frame being sent (0 is MAP-START, count + 1 is MAP-END)
+    frame: usize,
+    /// Checksum of the frame being sent and when it was sent (if it was)
+    sent: Option<(FrameChecksum, Timestamp)>,
+    retries: usize,
+    status: MapUploadStatus,
+}
+
+impl MapUploader {
+    pub fn new(sections: &[ProtocolMapSection]) -> Self {
+        let mut uploader = MapUploader {
+            sections: [None; MAP_SECTIONS_MAX_COUNT],
+            count: sections.len(),
+            frame: 0,
+            sent: None,
+            retries: 0,
+            status: MapUploadStatus::Sending,
+        };
+        if sections.len() > MAP_SECTIONS_MAX_COUNT {
+            uploader.status = MapUploadStatus::Failed;
+        } else {
+            for (i, section) in sections.iter().enumerate() {
+                uploader.sections[i] = Some(*section);
+            }
+        }
+        uploader
+    }
+
+    pub fn status(&self) -> MapUploadStatus {
+        self.status
+    }
+
+    fn command(&self) -> BotCommand {
+        if self.frame == 0 {
+            return BotCommand::MapStart(self.count);
+        }
+        match self.sections.get(self.frame - 1) {
+            Some(Some(section)) => BotCommand::MapSection(*section),
+            _ => BotCommand::MapEnd,
+        }
+    }
+
+    /// Frame to send now, if any (the next one, or a retry when the
+    /// acknowledgement is late or the bot rejected the last one)
+    pub fn poll(&mut self, now: Timestamp) -> Option<ProtocolBuffer> {
+        if self.status != MapUploadStatus::Sending {
+            return None;
+        }
+        if let Some((_, sent_at)) = self.sent {
+            if elapsed(sent_at, now) < MAP_UPLOAD_TIMEOUT {
+                return None;
+            }
+            self.sent = None;
+            self.retries += 1;
+        }
+        if self.retries > MAP_UPLOAD_MAX_RETRIES {
+            self.status = MapUploadStatus::Failed;
+            return None;
+        }
+        let mut buf = new_protocol_buffer();
+        self.command().write(&mut buf);
+        let checksum = append_checksum(&mut buf);
+        self.sent = Some((checksum, now));
+        Some(buf)
+    }
+
+    /// Track acknowledgements sent by the bot
+    pub fn handle_event(&mut self, event: &BotEvent) {
+        match (event, self.sent) {
+            (BotEvent::Ack(checksum), Some((sent, _))) if *checksum == sent => {
+                self.sent = None;
+                self.retries = 0;
+                self.frame += 1;
+                if self.frame > self.count + 1 {
+                    self.status = MapUploadStatus::Done;
+                }
+            }
+            (BotEvent::Nak(_), Some(_)) => {
+                // Retry at the next poll
+                self.sent = None;
+                self.retries += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Reasons for a received map to be rejected
+pub enum MapError {
+    /// MAP-SECTION or MAP-END without a MAP-START
+    NotStarted,
+    /// MAP-START with no sections, or more than MAP_SECTIONS_MAX_COUNT
+    InvalidCount(usize),
+    /// Section index not below the announced count
+    IndexOutOfRange(usize),
+    /// Section index received twice, with different data
+    DuplicateIndex(usize),
+    /// Section index not received before MAP-END
+    MissingSection(usize),
+    /// Section with invalid dimensions
+    InvalidSection(usize),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::NotStarted => write!(f, "map data without MAP-START"),
+            MapError::InvalidCount(count) => write!(f, "invalid section count {}", count),
+            MapError::IndexOutOfRange(index) => write!(f, "section {} out of range", index),
+            MapError::DuplicateIndex(index) => {
+                write!(f, "section {} received twice with different data", index)
+            }
+            MapError::MissingSection(index) => write!(f, "section {} missing", index),
+            MapError::InvalidSection(index) => write!(f, "section {} invalid", index),
+        }
+    }
+}
+
+/// Any map error in an upload leaves the bot without a valid map
+impl From<MapError> for ProtocolBotStatus {
+    fn from(_: MapError) -> Self {
+        ProtocolBotStatus::InvalidMap
+    }
+}
+
+/// Builds a map from the upload commands (MAP-START, each MAP-SECTION and
+/// MAP-END), checking them against the announced section count
+///
+/// Errors are reported when the upload ends, and the sections after the
+/// first error are ignored; a new MAP-START restarts the upload.
+/// Retransmissions are harmless: a section received again with the same
+/// data, or a MAP-END repeated after a completed upload, are ignored.
+pub struct MapReceiver {
+    map: Map,
+    /// Announced section count (None when no upload is in progress)
+    count: Option<usize>,
+    sections: [Option<ProtocolMapSectionData>; MAP_SECTIONS_MAX_COUNT],
+    error: Option<MapError>,
+    /// The last upload completed successfully (and nothing came after it)
+    completed: bool,
+}
+
+impl MapReceiver {
+    pub fn new() -> Self {
+        MapReceiver {
+            map: Map::new(),
+            count: None,
+            sections: [None; MAP_SECTIONS_MAX_COUNT],
+            error: None,
+            completed: false,
+        }
+    }
+
+    /// True between MAP-START and MAP-END
+    pub fn is_receiving(&self) -> bool {
+        self.count.is_some()
+    }
+
+    /// Handle a map upload command (other commands are ignored), returning
+    /// the map (or why it is invalid) when the upload ends
+    ///
+    /// Map data outside an upload is reported as NotStarted, which (unlike
+    /// the other errors) says nothing about the last received map.
+    pub fn handle_command(&mut self, cmd: &BotCommand) -> Option<Result<Map, MapError>> {
+        match cmd {
+            BotCommand::MapStart(count) => {
+                self.map.reset();
+                self.count = Some(*count);
+                self.sections = [None; MAP_SECTIONS_MAX_COUNT];
+                self.completed = false;
+                self.error = if *count == 0 || *count > MAP_SECTIONS_MAX_COUNT {
+                    Some(MapError::InvalidCount(*count))
+                } else {
+                    None
+                };
+                None
+            }
+            BotCommand::MapSection(section) => {
+                let count = match self.count {
+                    Some(count) => count,
+                    None => {
+                        self.completed = false;
+                        return Some(Err(MapError::NotStarted));
+                    }
+                };
+                if self.error.is_none() {
+                    self.error = self.receive_section(section, count).err();
+                }
+                None
+            }
+            BotCommand::MapEnd => {
+                let count = match self.count.take() {
+                    Some(count) => count,
+                    // Retransmission of the last MAP-END
+                    None if self.completed => return None,
+                    None => return Some(Err(MapError::NotStarted)),
+                };
+                let result = match self.error.take() {
+                    Some(error) => Err(error),
+                    None => self.complete(count),
+                };
+                self.completed = result.is_ok();
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    fn receive_section(
+        &mut self,
+        section: &ProtocolMapSection,
+        count: usize,
+    ) -> Result<(), MapError> {
+        if section.index >= count {
+            return Err(MapError::IndexOutOfRange(section.index));
+        }
+        match self.sections[section.index] {
+            Some(data) if data == section.data => return Ok(()),
+            Some(_) => return Err(MapError::DuplicateIndex(section.index)),
+            None => {}
+        }
+        let data = MapSection::from_protocol_data(&section.data);
+        if !data.is_valid() {
+            return Err(MapError::InvalidSection(section.index));
+        }
+        self.map.configure_section(section.index, &data);
+        self.sections[section.index] = Some(section.data);
+        Ok(())
+    }
+
+    fn complete(&mut self, count: usize) -> Result<Map, MapError> {
+        if let Some(missing) = self.sections[..count].iter().position(Option::is_none) {
+            return Err(MapError::MissingSection(missing));
+        }
+        self.map.complete_configuration();
+        Ok(self.map)
+    }
+}
+
+impl Default for MapReceiver {
+    fn default() -> Self {
+        MapReceiver::new()
+    }
+}
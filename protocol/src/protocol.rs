@@ -1,10 +1,148 @@
-use hal::{LASER_COUNT,ProtocolBuffer};
+use crate::binary;
+use crate::map::MAP_SECTIONS_MAX_COUNT;
+use core::fmt;
+use hal::{ProtocolBuffer, LASER_COUNT, PROTOCOL_BUFFER_SIZE};
+
+/// Protocol revision (increased on incompatible changes)
+pub const PROTOCOL_VERSION: i32 = 1;
 
 pub const MAX_LOG_LINE_SIZE: usize = 200;
 
-const CODE_MINUS: u8 = '-' as u8;
-const CODE_SEPARATOR: u8 = ':' as u8;
-const CODE_END: u8 = '\n' as u8;
+const CODE_MINUS: u8 = b'-';
+const CODE_SEPARATOR: u8 = b':';
+const CODE_END: u8 = b'\n';
+const CODE_CHECKSUM: u8 = b'*';
+
+/// Checksum suffix length ('*' and four hex digits)
+const CHECKSUM_SIZE: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Item a parser was looking for
+pub enum Expected {
+    /// Field separator (':')
+    Separator,
+    /// Decimal digit (or '-')
+    Digit,
+    /// Hexadecimal digit (in a checksum)
+    HexDigit,
+    /// Message or sub-message keyword
+    Keyword,
+    /// Line end
+    End,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Separator => write!(f, "':'"),
+            Expected::Digit => write!(f, "digit"),
+            Expected::HexDigit => write!(f, "hex digit"),
+            Expected::Keyword => write!(f, "keyword"),
+            Expected::End => write!(f, "end of line"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What went wrong parsing a frame
+pub enum ProtocolErrorKind {
+    /// Unexpected character
+    Expected(Expected),
+    /// Keyword not known in this position
+    UnknownKeyword,
+    /// Number that does not fit an i32
+    Overflow,
+    /// Number outside the range allowed for its field
+    OutOfRange,
+    /// Checksum suffix not matching the frame
+    ChecksumMismatch,
+    /// Checksum suffix missing (in Checked frame mode)
+    MissingChecksum,
+    /// No line end in the buffer
+    Unterminated,
+    /// Line too long for a protocol buffer (in a stream)
+    LineOverflow,
+    /// Binary frame shorter than its message
+    Truncated,
+    /// Binary frame with an invalid encoding
+    Malformed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Error parsing a frame
+///
+/// Displays like "MAP-SECTION: expected ':' after width_start at column 32"
+/// (columns start from 1, index from 0).
+pub struct ProtocolError {
+    /// Index of the offending byte
+    pub index: usize,
+    pub kind: ProtocolErrorKind,
+    /// Keyword of the message being parsed (if it was recognized)
+    pub message: Option<&'static str>,
+    /// Field being parsed (or, for separators and line ends, the one before)
+    pub field: Option<&'static str>,
+}
+
+impl ProtocolError {
+    pub fn new(index: usize, kind: ProtocolErrorKind) -> Self {
+        ProtocolError {
+            index,
+            kind,
+            message: None,
+            field: None,
+        }
+    }
+
+    fn expected(index: usize, expected: Expected, field: &'static str) -> Self {
+        ProtocolError::new(index, ProtocolErrorKind::Expected(expected)).with_field(field)
+    }
+
+    pub(crate) fn out_of_range(index: usize, field: &'static str) -> Self {
+        ProtocolError::new(index, ProtocolErrorKind::OutOfRange).with_field(field)
+    }
+
+    pub(crate) fn with_field(self, field: &'static str) -> Self {
+        ProtocolError {
+            field: Some(field),
+            ..self
+        }
+    }
+
+    fn in_message(self, message: &'static str) -> Self {
+        ProtocolError {
+            message: Some(message),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(message) = self.message {
+            write!(f, "{}: ", message)?;
+        }
+        match self.kind {
+            ProtocolErrorKind::Expected(expected) => write!(f, "expected {}", expected)?,
+            ProtocolErrorKind::UnknownKeyword => write!(f, "unknown keyword")?,
+            ProtocolErrorKind::Overflow => write!(f, "number overflow")?,
+            ProtocolErrorKind::OutOfRange => write!(f, "value out of range")?,
+            ProtocolErrorKind::ChecksumMismatch => write!(f, "checksum mismatch")?,
+            ProtocolErrorKind::MissingChecksum => write!(f, "missing checksum")?,
+            ProtocolErrorKind::Unterminated => write!(f, "missing end of line")?,
+            ProtocolErrorKind::LineOverflow => write!(f, "line too long")?,
+            ProtocolErrorKind::Truncated => write!(f, "truncated binary frame")?,
+            ProtocolErrorKind::Malformed => write!(f, "malformed binary frame")?,
+        }
+        if let Some(field) = self.field {
+            match self.kind {
+                ProtocolErrorKind::Expected(Expected::Separator)
+                | ProtocolErrorKind::Expected(Expected::End) => write!(f, " after {}", field)?,
+                _ => write!(f, " for {}", field)?,
+            }
+        }
+        write!(f, " at column {}", self.index + 1)
+    }
+}
 
 fn append_code(buf: &mut ProtocolBuffer, index: usize, code: u8) -> usize {
     buf[index] = code;
@@ -19,21 +157,30 @@ fn append_end(buf: &mut ProtocolBuffer, index: usize) -> usize {
     append_code(buf, index, CODE_END)
 }
 
-/// Ok is next index, Err is index of wrong character
-fn match_code(buf: &ProtocolBuffer, index: usize, code: u8) -> Result<usize, usize> {
-    if buf[index] == code {
+/// Ok is next index (field is the one before the separator)
+fn match_separator(
+    buf: &ProtocolBuffer,
+    index: usize,
+    field: &'static str,
+) -> Result<usize, ProtocolError> {
+    if buf[index] == CODE_SEPARATOR {
         Ok(index + 1)
     } else {
-        Err(index)
+        Err(ProtocolError::expected(index, Expected::Separator, field))
     }
 }
 
-fn match_separator(buf: &ProtocolBuffer, index: usize) -> Result<usize, usize> {
-    match_code(buf, index, CODE_SEPARATOR)
-}
-
-fn match_end(buf: &ProtocolBuffer, index: usize) -> Result<usize, usize> {
-    match_code(buf, index, CODE_END)
+/// Ok is next index (field is the one before the line end)
+fn match_end(
+    buf: &ProtocolBuffer,
+    index: usize,
+    field: &'static str,
+) -> Result<usize, ProtocolError> {
+    if buf[index] == CODE_END {
+        Ok(index + 1)
+    } else {
+        Err(ProtocolError::expected(index, Expected::End, field))
+    }
 }
 
 fn write_string(buf: &mut ProtocolBuffer, index: usize, s: &str) -> usize {
@@ -58,19 +205,18 @@ fn match_string(buf: &ProtocolBuffer, index: usize, s: &str) -> Result<usize, us
 }
 
 fn digit_value(code: u8) -> Option<i32> {
-    if code >= '0' as u8 && code <= '9' as u8 {
-        Some(code as i32 - '0' as i32)
+    if code.is_ascii_digit() {
+        Some((code - b'0') as i32)
     } else {
         None
     }
 }
 
 fn digit_code(digit: i32) -> u8 {
-    digit as u8 + '0' as u8
+    digit as u8 + b'0'
 }
 
 fn write_i32(buf: &mut ProtocolBuffer, index: usize, value: i32) -> usize {
-    let mut value = value;
     let mut index = index;
     if value == 0 {
         buf[index] = digit_code(0);
@@ -78,52 +224,223 @@ fn write_i32(buf: &mut ProtocolBuffer, index: usize, value: i32) -> usize {
     } else {
         if value < 0 {
             buf[index] = CODE_MINUS;
-            value = -value;
             index += 1;
         }
+        // Digits of the magnitude (i32::MIN has no positive counterpart)
+        let mut value = value.unsigned_abs();
 
-        let mut pow10 = 10;
-        while pow10 <= value {
+        // Largest power of 10 not above value (without overflowing)
+        let mut pow10 = 1;
+        while pow10 <= value / 10 {
             pow10 *= 10
         }
-        pow10 /= 10;
 
         while pow10 > 0 {
             let digit = value / pow10;
-            value = value % pow10;
+            value %= pow10;
             pow10 /= 10;
-            buf[index] = digit_code(digit);
+            buf[index] = digit_code(digit as i32);
             index += 1;
         }
     }
     index
 }
 
-/// Ok is value and next index, Err is index of wrong character
-fn match_i32(buf: &ProtocolBuffer, index: usize) -> Result<(i32, usize), usize> {
+/// Ok is value and next index
+fn match_i32(
+    buf: &ProtocolBuffer,
+    index: usize,
+    field: &'static str,
+) -> Result<(i32, usize), ProtocolError> {
     let mut index = index;
     let negative = buf[index] == CODE_MINUS;
     if negative {
         index += 1;
     }
     match digit_value(buf[index]) {
-        None => Err(index),
+        None => Err(ProtocolError::expected(index, Expected::Digit, field)),
         Some(v) => {
-            let mut value = v;
+            // Accumulate with the sign, so that i32::MIN does not overflow
+            let sign = if negative { -1 } else { 1 };
+            let mut value = sign * v;
             index += 1;
-            loop {
-                match digit_value(buf[index]) {
-                    Some(v) => {
-                        value *= 10;
-                        value += v;
-                        index += 1;
-                    }
-                    None => break,
-                }
+            while let Some(v) = digit_value(buf[index]) {
+                value = value
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(sign * v))
+                    .ok_or_else(|| {
+                        ProtocolError::new(index, ProtocolErrorKind::Overflow).with_field(field)
+                    })?;
+                index += 1;
             }
-            Ok((if negative { -value } else { value }, index))
+            Ok((value, index))
+        }
+    }
+}
+
+/// Ok is the keyword (one of keywords, followed by a separator or the
+/// line end) and next index
+fn match_keyword(
+    buf: &ProtocolBuffer,
+    index: usize,
+    keywords: &[&'static str],
+) -> Result<(&'static str, usize), ProtocolError> {
+    for keyword in keywords.iter() {
+        if let Ok(next) = match_string(buf, index, keyword) {
+            if buf[next] == CODE_SEPARATOR || buf[next] == CODE_END {
+                return Ok((keyword, next));
+            }
+        }
+    }
+    if buf[index].is_ascii_uppercase() {
+        Err(ProtocolError::new(index, ProtocolErrorKind::UnknownKeyword))
+    } else {
+        Err(ProtocolError::new(
+            index,
+            ProtocolErrorKind::Expected(Expected::Keyword),
+        ))
+    }
+}
+
+fn hex_code(digit: u16) -> u8 {
+    if digit < 10 {
+        digit as u8 + b'0'
+    } else {
+        digit as u8 - 10 + b'A'
+    }
+}
+
+fn hex_value(code: u8) -> Option<u16> {
+    if code.is_ascii_digit() {
+        Some((code - b'0') as u16)
+    } else if (b'A'..=b'F').contains(&code) {
+        Some((code - b'A') as u16 + 10)
+    } else {
+        None
+    }
+}
+
+/// Frame checksum (CRC-16/CCITT-FALSE)
+pub type FrameChecksum = u16;
+
+/// Checksum of the frame bytes before index
+pub fn frame_checksum(buf: &ProtocolBuffer, end: usize) -> FrameChecksum {
+    data_checksum(&buf[..end])
+}
+
+pub(crate) fn data_checksum(data: &[u8]) -> FrameChecksum {
+    let mut crc: u16 = 0xFFFF;
+    for code in data.iter() {
+        crc ^= (*code as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
         }
     }
+    crc
+}
+
+/// Index of the line end, if the buffer has one
+fn find_end(buf: &ProtocolBuffer) -> Option<usize> {
+    buf.iter().position(|code| *code == CODE_END)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Integrity checking of the frames on a link
+pub enum FrameMode {
+    /// Frames without checksum are accepted, and commands are not
+    /// acknowledged (compatible with peers that know nothing of checksums)
+    Plain,
+    /// Every frame carries a checksum, and commands are acknowledged
+    Checked,
+    /// Frames are binary (see the binary module), and commands are
+    /// acknowledged; text frames are still accepted, as in Plain mode
+    Binary,
+}
+
+/// Append the checksum suffix to a frame, returning the checksum
+/// (frames are "<payload>*XXXX\n", with four uppercase hex digits)
+pub fn append_checksum(buf: &mut ProtocolBuffer) -> FrameChecksum {
+    let end = find_end(buf).unwrap_or(PROTOCOL_BUFFER_SIZE - CHECKSUM_SIZE - 1);
+    let checksum = frame_checksum(buf, end);
+    let mut index = append_code(buf, end, CODE_CHECKSUM);
+    for shift in [12, 8, 4, 0].iter() {
+        index = append_code(buf, index, hex_code((checksum >> shift) & 0xF));
+    }
+    append_end(buf, index);
+    checksum
+}
+
+/// Check the integrity of a frame
+///
+/// Ok is the frame ready to be parsed and the checksum of its payload.
+/// Only Checked mode frames carry a checksum suffix: it is verified and
+/// removed, errors are at the suffix if it does not match, or at the line
+/// end if it is missing. In the other modes frames are taken as they are
+/// (a trailing "*XXXX" is part of the payload).
+pub fn check_frame(
+    buf: &ProtocolBuffer,
+    mode: FrameMode,
+) -> Result<(ProtocolBuffer, FrameChecksum), ProtocolError> {
+    let end = match_terminated(buf)?;
+    if mode != FrameMode::Checked {
+        return Ok((*buf, frame_checksum(buf, end)));
+    }
+    if end < CHECKSUM_SIZE || buf[end - CHECKSUM_SIZE] != CODE_CHECKSUM {
+        return Err(ProtocolError::new(end, ProtocolErrorKind::MissingChecksum));
+    }
+    let start = end - CHECKSUM_SIZE;
+    let mut expected: u16 = 0;
+    for (index, code) in buf.iter().enumerate().take(end).skip(start + 1) {
+        let digit = hex_value(*code).ok_or_else(|| {
+            ProtocolError::new(index, ProtocolErrorKind::Expected(Expected::HexDigit))
+        })?;
+        expected = (expected << 4) | digit;
+    }
+    let checksum = frame_checksum(buf, start);
+    if checksum != expected {
+        return Err(ProtocolError::new(
+            start,
+            ProtocolErrorKind::ChecksumMismatch,
+        ));
+    }
+    let mut frame = *buf;
+    frame[start] = CODE_END;
+    Ok((frame, checksum))
+}
+
+/// Ok is value (0 or 1) and next index
+fn match_flag(
+    buf: &ProtocolBuffer,
+    index: usize,
+    field: &'static str,
+) -> Result<(bool, usize), ProtocolError> {
+    match match_i32(buf, index, field)? {
+        (0, next) => Ok((false, next)),
+        (1, next) => Ok((true, next)),
+        _ => Err(ProtocolError::out_of_range(index, field)),
+    }
+}
+
+/// Ok is value (not negative) and next index
+fn match_usize(
+    buf: &ProtocolBuffer,
+    index: usize,
+    field: &'static str,
+) -> Result<(usize, usize), ProtocolError> {
+    match match_i32(buf, index, field)? {
+        (value, next) if value >= 0 => Ok((value as usize, next)),
+        _ => Err(ProtocolError::out_of_range(index, field)),
+    }
+}
+
+/// Index of the line end, or an error if the buffer has none
+fn match_terminated(buf: &ProtocolBuffer) -> Result<usize, ProtocolError> {
+    find_end(buf)
+        .ok_or_else(|| ProtocolError::new(PROTOCOL_BUFFER_SIZE, ProtocolErrorKind::Unterminated))
 }
 
 /// Motor power (from -100 to +100)
@@ -147,6 +464,110 @@ pub type ProtocolLinearAcceleration = i32;
 /// Angle in deg, from -360 to +360, positive is clockwise
 pub type ProtocolAngle = i32;
 
+/// Laser scale factor in 1/10000
+pub type ProtocolScale = i32;
+
+/// Fine angle in 1/100 deg, positive is clockwise
+pub type ProtocolFineAngle = i32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Calibration of a single laser (true distance = raw * scale + offset)
+pub struct ProtocolLaserCalibration {
+    /// Laser index
+    pub index: usize,
+    /// Offset added to readings (after scaling)
+    pub offset: ProtocolLinearDimension,
+    /// Scale applied to readings
+    pub scale: ProtocolScale,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Known position of a flat wall (for laser calibration)
+pub struct ProtocolWallData {
+    /// Wall distance from the car center
+    pub distance: ProtocolLinearDimension,
+    /// Yaw of the wall normal relative to the car heading
+    pub yaw: ProtocolAngle,
+}
+
+fn write_laser_calibration(
+    buf: &mut ProtocolBuffer,
+    index: usize,
+    data: &ProtocolLaserCalibration,
+) -> usize {
+    let mut index = write_string(buf, index, CALIBRATION_LASER);
+    index = append_separator(buf, index);
+    index = write_i32(buf, index, data.index as i32);
+    index = append_separator(buf, index);
+    index = write_i32(buf, index, data.offset);
+    index = append_separator(buf, index);
+    write_i32(buf, index, data.scale)
+}
+
+/// Ok is data and next index (starts after CALIBRATION_LASER)
+fn match_laser_calibration(
+    buf: &ProtocolBuffer,
+    index: usize,
+) -> Result<(ProtocolLaserCalibration, usize), ProtocolError> {
+    let mut index = match_separator(buf, index, CALIBRATION_LASER)?;
+    let (laser, next) = match_usize(buf, index, "index")?;
+    if laser >= LASER_COUNT {
+        return Err(ProtocolError::out_of_range(index, "index"));
+    }
+    index = next;
+    index = match_separator(buf, index, "index")?;
+    let (offset, next) = match_i32(buf, index, "offset")?;
+    index = next;
+    index = match_separator(buf, index, "offset")?;
+    let (scale, next) = match_i32(buf, index, "scale")?;
+    Ok((
+        ProtocolLaserCalibration {
+            index: laser,
+            offset,
+            scale,
+        },
+        next,
+    ))
+}
+
+/// Tunable parameter value in 1/1000 of its unit
+pub type ProtocolParamValue = i32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Value of a tunable parameter
+pub struct ProtocolParam {
+    /// Parameter identifier
+    pub id: usize,
+    pub value: ProtocolParamValue,
+}
+
+fn write_param(
+    buf: &mut ProtocolBuffer,
+    index: usize,
+    keyword: &str,
+    data: &ProtocolParam,
+) -> usize {
+    let mut index = write_string(buf, index, keyword);
+    index = append_separator(buf, index);
+    index = write_i32(buf, index, data.id as i32);
+    index = append_separator(buf, index);
+    write_i32(buf, index, data.value)
+}
+
+/// Ok is data and next index (starts after the keyword)
+fn match_param(
+    buf: &ProtocolBuffer,
+    index: usize,
+    keyword: &'static str,
+) -> Result<(ProtocolParam, usize), ProtocolError> {
+    let mut index = match_separator(buf, index, keyword)?;
+    let (id, next) = match_usize(buf, index, "id")?;
+    index = next;
+    index = match_separator(buf, index, "id")?;
+    let (value, next) = match_i32(buf, index, "value")?;
+    Ok((ProtocolParam { id, value }, next))
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 /// Description of straight map section
 pub struct ProtocolMapSectionDataStraight {
@@ -218,6 +639,19 @@ static START: &str = "START";
 static PAUSE: &str = "PAUSE";
 static RESTART: &str = "RESTART";
 static DIRECT: &str = "DIRECT";
+static CALIBRATION_LASER: &str = "CALIBRATION-LASER";
+static CALIBRATION_IMU: &str = "CALIBRATION-IMU";
+static CALIBRATION_QUERY: &str = "CALIBRATION-QUERY";
+static CALIBRATION_STORE: &str = "CALIBRATION-STORE";
+static CALIBRATE_WALL: &str = "CALIBRATE-WALL";
+static CALIBRATE_STILL: &str = "CALIBRATE-STILL";
+static CHECKSUM: &str = "CHECKSUM";
+static BINARY: &str = "BINARY";
+static PARAM_SET: &str = "PARAM-SET";
+static PARAM_GET: &str = "PARAM-GET";
+static PARAM_LIST: &str = "PARAM-LIST";
+static HELLO: &str = "HELLO";
+static VERSION: &str = "VERSION";
 
 static STRAIGHT: &str = "STRAIGHT";
 static LEFT: &str = "LEFT";
@@ -244,6 +678,36 @@ pub enum BotCommand {
     Restart,
     /// Directly apply motor power
     Direct(MotorsPowerData),
+    /// Set the calibration of a laser
+    CalibrationLaser(ProtocolLaserCalibration),
+    /// Set the IMU heading bias
+    CalibrationImu(ProtocolFineAngle),
+    /// Report the current calibration
+    CalibrationQuery,
+    /// Write the current calibration to persistent storage
+    CalibrationStore,
+    /// Calibrate lasers with the bot facing a wall at a known position
+    CalibrateWall(ProtocolWallData),
+    /// Calibrate the IMU with the bot still, aligned with the reference heading
+    CalibrateStill,
+    /// Switch the link frame mode (true for Checked)
+    ///
+    /// Always acknowledged, with the acknowledgement framed in the new mode.
+    Checksum(bool),
+    /// Switch the link frame mode (true for Binary, false for Plain)
+    ///
+    /// Always acknowledged, with the acknowledgement framed in the new mode.
+    Binary(bool),
+    /// Change a tunable parameter (replied with its new value)
+    ParamSet(ProtocolParam),
+    /// Report the value of a tunable parameter
+    ParamGet(usize),
+    /// Report the values of all tunable parameters
+    ParamList,
+    /// Open a session (replied with the version and the current status)
+    Hello,
+    /// Report the protocol version, limits and features
+    Version,
 }
 
 impl BotCommand {
@@ -346,65 +810,178 @@ impl BotCommand {
                 index = append_separator(buf, index);
                 index = write_i32(buf, index, cmd.front_right);
             }
+            BotCommand::CalibrationLaser(cmd) => {
+                index = write_laser_calibration(buf, index, cmd);
+            }
+            BotCommand::CalibrationImu(cmd) => {
+                index = write_string(buf, index, CALIBRATION_IMU);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *cmd);
+            }
+            BotCommand::CalibrationQuery => {
+                index = write_string(buf, index, CALIBRATION_QUERY);
+            }
+            BotCommand::CalibrationStore => {
+                index = write_string(buf, index, CALIBRATION_STORE);
+            }
+            BotCommand::CalibrateWall(cmd) => {
+                index = write_string(buf, index, CALIBRATE_WALL);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, cmd.distance);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, cmd.yaw);
+            }
+            BotCommand::CalibrateStill => {
+                index = write_string(buf, index, CALIBRATE_STILL);
+            }
+            BotCommand::Checksum(cmd) => {
+                index = write_string(buf, index, CHECKSUM);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *cmd as i32);
+            }
+            BotCommand::Binary(cmd) => {
+                index = write_string(buf, index, BINARY);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *cmd as i32);
+            }
+            BotCommand::ParamSet(cmd) => {
+                index = write_param(buf, index, PARAM_SET, cmd);
+            }
+            BotCommand::ParamGet(cmd) => {
+                index = write_string(buf, index, PARAM_GET);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *cmd as i32);
+            }
+            BotCommand::ParamList => {
+                index = write_string(buf, index, PARAM_LIST);
+            }
+            BotCommand::Hello => {
+                index = write_string(buf, index, HELLO);
+            }
+            BotCommand::Version => {
+                index = write_string(buf, index, VERSION);
+            }
         }
         append_end(buf, index);
     }
 
-    pub fn parse(buf: &ProtocolBuffer) -> Result<Self, usize> {
-        let mut index = 0;
-        if let Ok(next) = match_string(buf, index, MAP_START) {
-            index = next;
-            index = match_separator(buf, index)?;
-            let (size, next) = match_i32(buf, index)?;
+    /// Write a frame for a link in the given mode (with a checksum if needed)
+    pub fn write_frame(&self, buf: &mut ProtocolBuffer, mode: FrameMode) {
+        match mode {
+            FrameMode::Plain => self.write(buf),
+            FrameMode::Checked => {
+                self.write(buf);
+                append_checksum(buf);
+            }
+            FrameMode::Binary => {
+                binary::write_command(self, buf);
+            }
+        }
+    }
+
+    /// Check and parse a frame, Ok includes the frame checksum
+    pub fn parse_frame(
+        buf: &ProtocolBuffer,
+        mode: FrameMode,
+    ) -> Result<(Self, FrameChecksum), ProtocolError> {
+        if mode == FrameMode::Binary && binary::is_binary(buf) {
+            return binary::parse_command(buf);
+        }
+        let (frame, checksum) = check_frame(buf, mode)?;
+        Ok((BotCommand::parse(&frame)?, checksum))
+    }
+
+    pub fn parse(buf: &ProtocolBuffer) -> Result<Self, ProtocolError> {
+        match_terminated(buf)?;
+        let (keyword, index) = match_keyword(
+            buf,
+            0,
+            &[
+                MAP_START,
+                MAP_SECTION,
+                MAP_END,
+                RESET,
+                START,
+                PAUSE,
+                RESTART,
+                DIRECT,
+                CALIBRATION_LASER,
+                CALIBRATION_IMU,
+                CALIBRATION_QUERY,
+                CALIBRATION_STORE,
+                CALIBRATE_WALL,
+                CALIBRATE_STILL,
+                CHECKSUM,
+                BINARY,
+                PARAM_SET,
+                PARAM_GET,
+                PARAM_LIST,
+                HELLO,
+                VERSION,
+            ],
+        )?;
+        BotCommand::parse_fields(buf, keyword, index).map_err(|error| error.in_message(keyword))
+    }
+
+    /// Parse what follows the keyword
+    fn parse_fields(
+        buf: &ProtocolBuffer,
+        keyword: &str,
+        index: usize,
+    ) -> Result<Self, ProtocolError> {
+        let mut index = index;
+        if keyword == MAP_START {
+            index = match_separator(buf, index, MAP_START)?;
+            let (size, next) = match_usize(buf, index, "count")?;
             index = next;
-            match_end(buf, index)?;
-            Ok(BotCommand::MapStart(size as usize))
-        } else if let Ok(next) = match_string(buf, index, MAP_SECTION) {
+            match_end(buf, index, "count")?;
+            Ok(BotCommand::MapStart(size))
+        } else if keyword == MAP_SECTION {
+            index = match_separator(buf, index, MAP_SECTION)?;
+            let (section_index, next) = match_usize(buf, index, "index")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (section_index, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "index")?;
+            let (sub, next) = match_keyword(buf, index, &[STRAIGHT, LEFT, RIGHT, UP, DOWN])
+                .map_err(|error| error.with_field("shape"))?;
             index = next;
-            index = match_separator(buf, index)?;
-            if let Ok(next) = match_string(buf, index, STRAIGHT) {
+            if sub == STRAIGHT {
+                index = match_separator(buf, index, STRAIGHT)?;
+                let (length, next) = match_i32(buf, index, "length")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (length, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "length")?;
+                let (width_start, next) = match_i32(buf, index, "width_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_start")?;
+                let (width_end, next) = match_i32(buf, index, "width_end")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_end, next) = match_i32(buf, index)?;
-                index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "width_end")?;
                 Ok(BotCommand::MapSection(ProtocolMapSection {
-                    index: section_index as usize,
+                    index: section_index,
                     data: ProtocolMapSectionData::Straight(ProtocolMapSectionDataStraight {
                         length,
                         width_start,
                         width_end,
                     }),
                 }))
-            } else if let Ok(next) = match_string(buf, index, LEFT) {
-                index = next;
-                index = match_separator(buf, index)?;
-                let (angle, next) = match_i32(buf, index)?;
+            } else if sub == LEFT {
+                index = match_separator(buf, index, LEFT)?;
+                let (angle, next) = match_i32(buf, index, "angle")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "angle")?;
+                let (width_start, next) = match_i32(buf, index, "width_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_end, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_start")?;
+                let (width_end, next) = match_i32(buf, index, "width_end")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (radius_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_end")?;
+                let (radius_start, next) = match_i32(buf, index, "radius_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (radius_end, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "radius_start")?;
+                let (radius_end, next) = match_i32(buf, index, "radius_end")?;
                 index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "radius_end")?;
                 Ok(BotCommand::MapSection(ProtocolMapSection {
-                    index: section_index as usize,
+                    index: section_index,
                     data: ProtocolMapSectionData::TurnLeft(ProtocolMapSectionDataTurn {
                         angle,
                         width_start,
@@ -413,26 +990,25 @@ impl BotCommand {
                         radius_end,
                     }),
                 }))
-            } else if let Ok(next) = match_string(buf, index, RIGHT) {
+            } else if sub == RIGHT {
+                index = match_separator(buf, index, RIGHT)?;
+                let (angle, next) = match_i32(buf, index, "angle")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (angle, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "angle")?;
+                let (width_start, next) = match_i32(buf, index, "width_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_start")?;
+                let (width_end, next) = match_i32(buf, index, "width_end")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_end, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_end")?;
+                let (radius_start, next) = match_i32(buf, index, "radius_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (radius_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "radius_start")?;
+                let (radius_end, next) = match_i32(buf, index, "radius_end")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (radius_end, next) = match_i32(buf, index)?;
-                index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "radius_end")?;
                 Ok(BotCommand::MapSection(ProtocolMapSection {
-                    index: section_index as usize,
+                    index: section_index,
                     data: ProtocolMapSectionData::TurnRight(ProtocolMapSectionDataTurn {
                         angle,
                         width_start,
@@ -441,23 +1017,22 @@ impl BotCommand {
                         radius_end,
                     }),
                 }))
-            } else if let Ok(next) = match_string(buf, index, UP) {
-                index = next;
-                index = match_separator(buf, index)?;
-                let (length, next) = match_i32(buf, index)?;
+            } else if sub == UP {
+                index = match_separator(buf, index, UP)?;
+                let (length, next) = match_i32(buf, index, "length")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (height, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "length")?;
+                let (height, next) = match_i32(buf, index, "height")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "height")?;
+                let (width_start, next) = match_i32(buf, index, "width_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_end, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_start")?;
+                let (width_end, next) = match_i32(buf, index, "width_end")?;
                 index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "width_end")?;
                 Ok(BotCommand::MapSection(ProtocolMapSection {
-                    index: section_index as usize,
+                    index: section_index,
                     data: ProtocolMapSectionData::SlopeUp(ProtocolMapSectionDataSlope {
                         length,
                         height,
@@ -465,23 +1040,22 @@ impl BotCommand {
                         width_end,
                     }),
                 }))
-            } else if let Ok(next) = match_string(buf, index, DOWN) {
+            } else if sub == DOWN {
+                index = match_separator(buf, index, DOWN)?;
+                let (length, next) = match_i32(buf, index, "length")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (length, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "length")?;
+                let (height, next) = match_i32(buf, index, "height")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (height, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "height")?;
+                let (width_start, next) = match_i32(buf, index, "width_start")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_start, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "width_start")?;
+                let (width_end, next) = match_i32(buf, index, "width_end")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (width_end, next) = match_i32(buf, index)?;
-                index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "width_end")?;
                 Ok(BotCommand::MapSection(ProtocolMapSection {
-                    index: section_index as usize,
+                    index: section_index,
                     data: ProtocolMapSectionData::SlopeDown(ProtocolMapSectionDataSlope {
                         length,
                         height,
@@ -490,51 +1064,106 @@ impl BotCommand {
                     }),
                 }))
             } else {
-                Err(index)
+                Err(ProtocolError::new(index, ProtocolErrorKind::UnknownKeyword))
             }
-        } else if let Ok(next) = match_string(buf, index, MAP_END) {
-            index = next;
-            match_end(buf, index)?;
+        } else if keyword == MAP_END {
+            match_end(buf, index, MAP_END)?;
             Ok(BotCommand::MapEnd)
-        } else if let Ok(next) = match_string(buf, index, RESET) {
-            index = next;
-            match_end(buf, index)?;
+        } else if keyword == RESET {
+            match_end(buf, index, RESET)?;
             Ok(BotCommand::Reset)
-        } else if let Ok(next) = match_string(buf, index, START) {
-            index = next;
-            match_end(buf, index)?;
+        } else if keyword == START {
+            match_end(buf, index, START)?;
             Ok(BotCommand::Start)
-        } else if let Ok(next) = match_string(buf, index, PAUSE) {
-            index = next;
-            match_end(buf, index)?;
+        } else if keyword == PAUSE {
+            match_end(buf, index, PAUSE)?;
             Ok(BotCommand::Pause)
-        } else if let Ok(next) = match_string(buf, index, RESTART) {
-            index = next;
-            match_end(buf, index)?;
+        } else if keyword == RESTART {
+            match_end(buf, index, RESTART)?;
             Ok(BotCommand::Restart)
-        } else if let Ok(next) = match_string(buf, index, DIRECT) {
-            index = next;
-            index = match_separator(buf, index)?;
-            let (back_left, next) = match_i32(buf, index)?;
+        } else if keyword == DIRECT {
+            index = match_separator(buf, index, DIRECT)?;
+            let (back_left, next) = match_i32(buf, index, "back_left")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (back_right, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "back_left")?;
+            let (back_right, next) = match_i32(buf, index, "back_right")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (front_left, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "back_right")?;
+            let (front_left, next) = match_i32(buf, index, "front_left")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (front_right, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "front_left")?;
+            let (front_right, next) = match_i32(buf, index, "front_right")?;
             index = next;
-            match_end(buf, index)?;
+            match_end(buf, index, "front_right")?;
             Ok(BotCommand::Direct(MotorsPowerData {
                 back_left,
                 back_right,
                 front_left,
                 front_right,
             }))
+        } else if keyword == CALIBRATION_LASER {
+            let (data, next) = match_laser_calibration(buf, index)?;
+            index = next;
+            match_end(buf, index, "scale")?;
+            Ok(BotCommand::CalibrationLaser(data))
+        } else if keyword == CALIBRATION_IMU {
+            index = match_separator(buf, index, CALIBRATION_IMU)?;
+            let (bias, next) = match_i32(buf, index, "bias")?;
+            index = next;
+            match_end(buf, index, "bias")?;
+            Ok(BotCommand::CalibrationImu(bias))
+        } else if keyword == CALIBRATION_QUERY {
+            match_end(buf, index, CALIBRATION_QUERY)?;
+            Ok(BotCommand::CalibrationQuery)
+        } else if keyword == CALIBRATION_STORE {
+            match_end(buf, index, CALIBRATION_STORE)?;
+            Ok(BotCommand::CalibrationStore)
+        } else if keyword == CALIBRATE_WALL {
+            index = match_separator(buf, index, CALIBRATE_WALL)?;
+            let (distance, next) = match_i32(buf, index, "distance")?;
+            index = next;
+            index = match_separator(buf, index, "distance")?;
+            let (yaw, next) = match_i32(buf, index, "yaw")?;
+            index = next;
+            match_end(buf, index, "yaw")?;
+            Ok(BotCommand::CalibrateWall(ProtocolWallData { distance, yaw }))
+        } else if keyword == CALIBRATE_STILL {
+            match_end(buf, index, CALIBRATE_STILL)?;
+            Ok(BotCommand::CalibrateStill)
+        } else if keyword == CHECKSUM {
+            index = match_separator(buf, index, CHECKSUM)?;
+            let (checked, next) = match_flag(buf, index, "checked")?;
+            index = next;
+            match_end(buf, index, "checked")?;
+            Ok(BotCommand::Checksum(checked))
+        } else if keyword == BINARY {
+            index = match_separator(buf, index, BINARY)?;
+            let (binary, next) = match_flag(buf, index, "binary")?;
+            index = next;
+            match_end(buf, index, "binary")?;
+            Ok(BotCommand::Binary(binary))
+        } else if keyword == PARAM_SET {
+            let (data, next) = match_param(buf, index, PARAM_SET)?;
+            index = next;
+            match_end(buf, index, "value")?;
+            Ok(BotCommand::ParamSet(data))
+        } else if keyword == PARAM_GET {
+            index = match_separator(buf, index, PARAM_GET)?;
+            let (id, next) = match_usize(buf, index, "id")?;
+            index = next;
+            match_end(buf, index, "id")?;
+            Ok(BotCommand::ParamGet(id))
+        } else if keyword == PARAM_LIST {
+            match_end(buf, index, PARAM_LIST)?;
+            Ok(BotCommand::ParamList)
+        } else if keyword == HELLO {
+            match_end(buf, index, HELLO)?;
+            Ok(BotCommand::Hello)
+        } else if keyword == VERSION {
+            match_end(buf, index, VERSION)?;
+            Ok(BotCommand::Version)
         } else {
-            Err(index)
+            Err(ProtocolError::new(0, ProtocolErrorKind::UnknownKeyword))
         }
     }
 }
@@ -593,6 +1222,96 @@ pub struct ProtocolImuData {
     pub gravity_z: ProtocolLinearAcceleration,
 }
 
+/// Angular velocity in deg/s
+pub type ProtocolAngularVelocity = i32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Data from a wheel encoder
+pub struct ProtocolWheelEncoderData {
+    /// Wheel position (encoder ticks)
+    pub ticks: i32,
+    /// Wheel angular velocity (positive forward)
+    pub velocity: ProtocolAngularVelocity,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Data from all wheel encoders
+pub struct ProtocolEncoderData {
+    pub back_left: ProtocolWheelEncoderData,
+    pub back_right: ProtocolWheelEncoderData,
+    pub front_left: ProtocolWheelEncoderData,
+    pub front_right: ProtocolWheelEncoderData,
+}
+
+/// Voltage in mV
+pub type ProtocolVoltage = i32;
+
+/// Current in mA
+pub type ProtocolCurrent = i32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Data from the battery monitor
+pub struct ProtocolBatteryData {
+    pub voltage: ProtocolVoltage,
+    pub current: ProtocolCurrent,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Data from the bumper switches (true when pressed)
+pub struct ProtocolBumperData {
+    pub front: bool,
+    pub rear: bool,
+}
+
+/// Optional protocol features (a bitmap of FEATURE_* flags)
+pub type ProtocolFeatures = i32;
+
+/// Checked frames (CHECKSUM command)
+pub const FEATURE_CHECKSUM: ProtocolFeatures = 1;
+/// Binary frames (BINARY command)
+pub const FEATURE_BINARY: ProtocolFeatures = 1 << 1;
+/// Tunable parameters (PARAM-* commands)
+pub const FEATURE_PARAMS: ProtocolFeatures = 1 << 2;
+/// Sensor calibration (CALIBRATION-* and CALIBRATE-* commands)
+pub const FEATURE_CALIBRATION: ProtocolFeatures = 1 << 3;
+/// Bumper switches (BUMPERS events)
+pub const FEATURE_BUMPERS: ProtocolFeatures = 1 << 4;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// Protocol version, limits and features of a bot
+pub struct ProtocolVersionData {
+    /// Protocol revision
+    pub protocol: i32,
+    /// Firmware build number
+    pub build: i32,
+    /// Number of lasers in LASERS events
+    pub lasers: usize,
+    /// Maximum number of map sections
+    pub map_sections: usize,
+    /// Protocol buffer size (longest frame, including the line end)
+    pub buffer_size: usize,
+    pub features: ProtocolFeatures,
+}
+
+impl ProtocolVersionData {
+    /// Version data of this protocol implementation
+    pub fn new(build: i32, features: ProtocolFeatures) -> Self {
+        ProtocolVersionData {
+            protocol: PROTOCOL_VERSION,
+            build,
+            lasers: LASER_COUNT,
+            map_sections: MAP_SECTIONS_MAX_COUNT,
+            buffer_size: PROTOCOL_BUFFER_SIZE,
+            features,
+        }
+    }
+
+    /// True if all the given features are supported
+    pub fn supports(&self, features: ProtocolFeatures) -> bool {
+        self.features & features == features
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 /// Bot status
 pub enum ProtocolBotStatus {
@@ -614,6 +1333,41 @@ pub struct ProtocolLogLineData {
     pub message: [u8; MAX_LOG_LINE_SIZE],
 }
 
+impl ProtocolLogLineData {
+    /// Create an empty log line
+    pub fn new() -> Self {
+        ProtocolLogLineData {
+            length: 0,
+            message: [0; MAX_LOG_LINE_SIZE],
+        }
+    }
+
+    /// Create a log line from a string (truncated if too long)
+    pub fn from_message(s: &str) -> Self {
+        let mut data = ProtocolLogLineData::new();
+        let _ = fmt::Write::write_str(&mut data, s);
+        data
+    }
+}
+
+impl Default for ProtocolLogLineData {
+    fn default() -> Self {
+        ProtocolLogLineData::new()
+    }
+}
+
+/// Appends text to the line, silently truncating it at MAX_LOG_LINE_SIZE
+/// (line ends are replaced with spaces so the line cannot be split)
+impl fmt::Write for ProtocolLogLineData {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes().take(MAX_LOG_LINE_SIZE - self.length) {
+            self.message[self.length] = if c == CODE_END { b' ' } else { c };
+            self.length += 1;
+        }
+        Ok(())
+    }
+}
+
 impl PartialEq for ProtocolLogLineData {
     fn eq(&self, other: &Self) -> bool {
         if self.length != other.length {
@@ -636,12 +1390,30 @@ pub enum BotEvent {
     Status(ProtocolBotStatus),
     Lasers(ProtocolLaserData),
     Imu(ProtocolImuData),
+    Encoders(ProtocolEncoderData),
+    Battery(ProtocolBatteryData),
+    Bumpers(ProtocolBumperData),
+    CalibrationLaser(ProtocolLaserCalibration),
+    CalibrationImu(ProtocolFineAngle),
+    /// Command received (with the checksum of its frame)
+    Ack(FrameChecksum),
+    /// Command rejected (with the index of the offending byte)
+    Nak(usize),
+    /// Value of a tunable parameter
+    Param(ProtocolParam),
+    Version(ProtocolVersionData),
     Log(ProtocolLogLineData),
 }
 
 static STATUS: &str = "STATUS";
 static LASERS: &str = "LASERS";
 static IMU: &str = "IMU";
+static ENCODERS: &str = "ENCODERS";
+static BATTERY: &str = "BATTERY";
+static BUMPERS: &str = "BUMPERS";
+static ACK: &str = "ACK";
+static NAK: &str = "NAK";
+static PARAM: &str = "PARAM";
 static LOG: &str = "LOG";
 
 static INVALID_MAP: &str = "INVALID-MAP";
@@ -717,6 +1489,72 @@ impl BotEvent {
                 index = append_separator(buf, index);
                 index = write_i32(buf, index, evt.gravity_z);
             }
+            BotEvent::Encoders(evt) => {
+                index = write_string(buf, index, ENCODERS);
+                for wheel in [
+                    &evt.back_left,
+                    &evt.back_right,
+                    &evt.front_left,
+                    &evt.front_right,
+                ]
+                .iter()
+                {
+                    index = append_separator(buf, index);
+                    index = write_i32(buf, index, wheel.ticks);
+                    index = append_separator(buf, index);
+                    index = write_i32(buf, index, wheel.velocity);
+                }
+            }
+            BotEvent::Battery(evt) => {
+                index = write_string(buf, index, BATTERY);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.voltage);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.current);
+            }
+            BotEvent::Bumpers(evt) => {
+                index = write_string(buf, index, BUMPERS);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.front as i32);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.rear as i32);
+            }
+            BotEvent::CalibrationLaser(evt) => {
+                index = write_laser_calibration(buf, index, evt);
+            }
+            BotEvent::CalibrationImu(evt) => {
+                index = write_string(buf, index, CALIBRATION_IMU);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *evt);
+            }
+            BotEvent::Ack(evt) => {
+                index = write_string(buf, index, ACK);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *evt as i32);
+            }
+            BotEvent::Nak(evt) => {
+                index = write_string(buf, index, NAK);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, *evt as i32);
+            }
+            BotEvent::Param(evt) => {
+                index = write_param(buf, index, PARAM, evt);
+            }
+            BotEvent::Version(evt) => {
+                index = write_string(buf, index, VERSION);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.protocol);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.build);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.lasers as i32);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.map_sections as i32);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.buffer_size as i32);
+                index = append_separator(buf, index);
+                index = write_i32(buf, index, evt.features);
+            }
             BotEvent::Log(evt) => {
                 index = write_string(buf, index, LOG);
                 index = append_separator(buf, index);
@@ -728,56 +1566,86 @@ impl BotEvent {
         append_end(buf, index);
     }
 
-    pub fn parse(buf: &ProtocolBuffer) -> Result<Self, usize> {
-        let mut index = 0;
-        if let Ok(next) = match_string(buf, index, STATUS) {
+    pub fn parse(buf: &ProtocolBuffer) -> Result<Self, ProtocolError> {
+        match_terminated(buf)?;
+        let (keyword, index) = match_keyword(
+            buf,
+            0,
+            &[
+                STATUS,
+                LASERS,
+                IMU,
+                ENCODERS,
+                BATTERY,
+                BUMPERS,
+                CALIBRATION_LASER,
+                CALIBRATION_IMU,
+                ACK,
+                NAK,
+                PARAM,
+                VERSION,
+                LOG,
+            ],
+        )?;
+        BotEvent::parse_fields(buf, keyword, index).map_err(|error| error.in_message(keyword))
+    }
+
+    /// Parse what follows the keyword
+    fn parse_fields(
+        buf: &ProtocolBuffer,
+        keyword: &str,
+        index: usize,
+    ) -> Result<Self, ProtocolError> {
+        let mut index = index;
+        if keyword == STATUS {
+            index = match_separator(buf, index, STATUS)?;
+            let (sub, next) = match_keyword(
+                buf,
+                index,
+                &[INVALID_MAP, DEVICE_ERROR, STOPPED, WAITING, RACING],
+            )
+            .map_err(|error| error.with_field("status"))?;
             index = next;
-            index = match_separator(buf, index)?;
-            if let Ok(next) = match_string(buf, index, INVALID_MAP) {
-                index = next;
-                match_end(buf, index)?;
+            if sub == INVALID_MAP {
+                match_end(buf, index, INVALID_MAP)?;
                 Ok(BotEvent::Status(ProtocolBotStatus::InvalidMap))
-            } else if let Ok(next) = match_string(buf, index, DEVICE_ERROR) {
-                index = next;
-                match_end(buf, index)?;
+            } else if sub == DEVICE_ERROR {
+                match_end(buf, index, DEVICE_ERROR)?;
                 Ok(BotEvent::Status(ProtocolBotStatus::DeviceError))
-            } else if let Ok(next) = match_string(buf, index, STOPPED) {
-                index = next;
-                match_end(buf, index)?;
+            } else if sub == STOPPED {
+                match_end(buf, index, STOPPED)?;
                 Ok(BotEvent::Status(ProtocolBotStatus::Stopped))
-            } else if let Ok(next) = match_string(buf, index, WAITING) {
-                index = next;
-                index = match_separator(buf, index)?;
-                let (target, next) = match_i32(buf, index)?;
+            } else if sub == WAITING {
+                index = match_separator(buf, index, WAITING)?;
+                let (target, next) = match_i32(buf, index, "target")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (elapsed, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "target")?;
+                let (elapsed, next) = match_i32(buf, index, "elapsed")?;
                 index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "elapsed")?;
                 Ok(BotEvent::Status(ProtocolBotStatus::Waiting(
                     ProtocolWaitingData { target, elapsed },
                 )))
-            } else if let Ok(next) = match_string(buf, index, RACING) {
+            } else if sub == RACING {
+                index = match_separator(buf, index, RACING)?;
+                let (section, next) = match_usize(buf, index, "section")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (section, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "section")?;
+                let (completion_low, next) = match_i32(buf, index, "completion_low")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (completion_low, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "completion_low")?;
+                let (completion_high, next) = match_i32(buf, index, "completion_high")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (completion_high, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "completion_high")?;
+                let (positioning_left, next) = match_i32(buf, index, "positioning_left")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (positioning_left, next) = match_i32(buf, index)?;
+                index = match_separator(buf, index, "positioning_left")?;
+                let (positioning_right, next) = match_i32(buf, index, "positioning_right")?;
                 index = next;
-                index = match_separator(buf, index)?;
-                let (positioning_right, next) = match_i32(buf, index)?;
-                index = next;
-                match_end(buf, index)?;
+                match_end(buf, index, "positioning_right")?;
                 Ok(BotEvent::Status(ProtocolBotStatus::Racing(
                     ProtocolRacingData {
-                        section: section as usize,
+                        section,
                         completion_low,
                         completion_high,
                         positioning_left,
@@ -785,48 +1653,47 @@ impl BotEvent {
                     },
                 )))
             } else {
-                Err(index)
+                Err(ProtocolError::new(index, ProtocolErrorKind::UnknownKeyword))
             }
-        } else if let Ok(next) = match_string(buf, index, LASERS) {
-            index = next;
+        } else if keyword == LASERS {
             let mut data: ProtocolLaserData = [0; LASER_COUNT];
-            for i in 0..LASER_COUNT {
-                index = match_separator(buf, index)?;
-                let (laser, next) = match_i32(buf, index)?;
+            for laser in data.iter_mut() {
+                index = match_separator(buf, index, LASERS)?;
+                let (value, next) = match_i32(buf, index, "lasers")?;
                 index = next;
-                data[i] = laser;
+                *laser = value;
             }
+            match_end(buf, index, "lasers")?;
             Ok(BotEvent::Lasers(data))
-        } else if let Ok(next) = match_string(buf, index, IMU) {
-            index = next;
-            index = match_separator(buf, index)?;
-            let (rotation_x, next) = match_i32(buf, index)?;
+        } else if keyword == IMU {
+            index = match_separator(buf, index, IMU)?;
+            let (rotation_x, next) = match_i32(buf, index, "rotation_x")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (rotation_y, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "rotation_x")?;
+            let (rotation_y, next) = match_i32(buf, index, "rotation_y")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (rotation_z, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "rotation_y")?;
+            let (rotation_z, next) = match_i32(buf, index, "rotation_z")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (acceleration_x, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "rotation_z")?;
+            let (acceleration_x, next) = match_i32(buf, index, "acceleration_x")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (acceleration_y, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "acceleration_x")?;
+            let (acceleration_y, next) = match_i32(buf, index, "acceleration_y")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (acceleration_z, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "acceleration_y")?;
+            let (acceleration_z, next) = match_i32(buf, index, "acceleration_z")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (gravity_x, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "acceleration_z")?;
+            let (gravity_x, next) = match_i32(buf, index, "gravity_x")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (gravity_y, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "gravity_x")?;
+            let (gravity_y, next) = match_i32(buf, index, "gravity_y")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let (gravity_z, next) = match_i32(buf, index)?;
+            index = match_separator(buf, index, "gravity_y")?;
+            let (gravity_z, next) = match_i32(buf, index, "gravity_z")?;
             index = next;
-            match_end(buf, index)?;
+            match_end(buf, index, "gravity_z")?;
             Ok(BotEvent::Imu(ProtocolImuData {
                 rotation_x,
                 rotation_y,
@@ -838,28 +1705,143 @@ impl BotEvent {
                 gravity_y,
                 gravity_z,
             }))
-        } else if let Ok(next) = match_string(buf, index, LOG) {
+        } else if keyword == ENCODERS {
+            let mut wheels = [ProtocolWheelEncoderData {
+                ticks: 0,
+                velocity: 0,
+            }; 4];
+            for wheel in wheels.iter_mut() {
+                index = match_separator(buf, index, ENCODERS)?;
+                let (ticks, next) = match_i32(buf, index, "ticks")?;
+                index = next;
+                index = match_separator(buf, index, "ticks")?;
+                let (velocity, next) = match_i32(buf, index, "velocity")?;
+                index = next;
+                *wheel = ProtocolWheelEncoderData { ticks, velocity };
+            }
+            match_end(buf, index, "velocity")?;
+            Ok(BotEvent::Encoders(ProtocolEncoderData {
+                back_left: wheels[0],
+                back_right: wheels[1],
+                front_left: wheels[2],
+                front_right: wheels[3],
+            }))
+        } else if keyword == BATTERY {
+            index = match_separator(buf, index, BATTERY)?;
+            let (voltage, next) = match_i32(buf, index, "voltage")?;
             index = next;
-            index = match_separator(buf, index)?;
-            let mut data = ProtocolLogLineData {
-                length: 0,
-                message: [0; MAX_LOG_LINE_SIZE],
-            };
-            for i in 0..MAX_LOG_LINE_SIZE {
-                let code = buf[index];
-                if code != CODE_END {
-                    data.message[i] = code;
-                    index += 1;
-                } else {
-                    data.length = i;
-                    break;
-                }
+            index = match_separator(buf, index, "voltage")?;
+            let (current, next) = match_i32(buf, index, "current")?;
+            index = next;
+            match_end(buf, index, "current")?;
+            Ok(BotEvent::Battery(ProtocolBatteryData { voltage, current }))
+        } else if keyword == BUMPERS {
+            index = match_separator(buf, index, BUMPERS)?;
+            let (front, next) = match_flag(buf, index, "front")?;
+            index = next;
+            index = match_separator(buf, index, "front")?;
+            let (rear, next) = match_flag(buf, index, "rear")?;
+            index = next;
+            match_end(buf, index, "rear")?;
+            Ok(BotEvent::Bumpers(ProtocolBumperData { front, rear }))
+        } else if keyword == CALIBRATION_LASER {
+            let (data, next) = match_laser_calibration(buf, index)?;
+            index = next;
+            match_end(buf, index, "scale")?;
+            Ok(BotEvent::CalibrationLaser(data))
+        } else if keyword == CALIBRATION_IMU {
+            index = match_separator(buf, index, CALIBRATION_IMU)?;
+            let (bias, next) = match_i32(buf, index, "bias")?;
+            index = next;
+            match_end(buf, index, "bias")?;
+            Ok(BotEvent::CalibrationImu(bias))
+        } else if keyword == ACK {
+            index = match_separator(buf, index, ACK)?;
+            let (checksum, next) = match_i32(buf, index, "checksum")?;
+            if checksum < 0 || checksum > FrameChecksum::MAX as i32 {
+                return Err(ProtocolError::out_of_range(index, "checksum"));
             }
-            match_end(buf, index)?;
+            index = next;
+            match_end(buf, index, "checksum")?;
+            Ok(BotEvent::Ack(checksum as FrameChecksum))
+        } else if keyword == NAK {
+            index = match_separator(buf, index, NAK)?;
+            let (position, next) = match_usize(buf, index, "index")?;
+            index = next;
+            match_end(buf, index, "index")?;
+            Ok(BotEvent::Nak(position))
+        } else if keyword == PARAM {
+            let (data, next) = match_param(buf, index, PARAM)?;
+            index = next;
+            match_end(buf, index, "value")?;
+            Ok(BotEvent::Param(data))
+        } else if keyword == VERSION {
+            index = match_separator(buf, index, VERSION)?;
+            let (protocol, next) = match_i32(buf, index, "protocol")?;
+            index = next;
+            index = match_separator(buf, index, "protocol")?;
+            let (build, next) = match_i32(buf, index, "build")?;
+            index = next;
+            index = match_separator(buf, index, "build")?;
+            let (lasers, next) = match_usize(buf, index, "lasers")?;
+            index = next;
+            index = match_separator(buf, index, "lasers")?;
+            let (map_sections, next) = match_usize(buf, index, "map_sections")?;
+            index = next;
+            index = match_separator(buf, index, "map_sections")?;
+            let (buffer_size, next) = match_usize(buf, index, "buffer_size")?;
+            index = next;
+            index = match_separator(buf, index, "buffer_size")?;
+            let (features, next) = match_i32(buf, index, "features")?;
+            index = next;
+            match_end(buf, index, "features")?;
+            Ok(BotEvent::Version(ProtocolVersionData {
+                protocol,
+                build,
+                lasers,
+                map_sections,
+                buffer_size,
+                features,
+            }))
+        } else if keyword == LOG {
+            index = match_separator(buf, index, LOG)?;
+            let mut data = ProtocolLogLineData::new();
+            while data.length < MAX_LOG_LINE_SIZE && buf[index] != CODE_END {
+                data.message[data.length] = buf[index];
+                data.length += 1;
+                index += 1;
+            }
+            match_end(buf, index, "message")?;
             Ok(BotEvent::Log(data))
         } else {
-            Err(index)
+            Err(ProtocolError::new(0, ProtocolErrorKind::UnknownKeyword))
+        }
+    }
+
+    /// Write a frame for a link in the given mode (with a checksum if needed)
+    pub fn write_frame(&self, buf: &mut ProtocolBuffer, mode: FrameMode) {
+        match mode {
+            FrameMode::Plain => self.write(buf),
+            FrameMode::Checked => {
+                self.write(buf);
+                append_checksum(buf);
+            }
+            FrameMode::Binary => {
+                binary::write_event(self, buf);
+            }
+        }
+    }
+
+    /// Check and parse a frame, Ok includes the frame checksum
+    pub fn parse_frame(
+        buf: &ProtocolBuffer,
+        mode: FrameMode,
+    ) -> Result<(Self, FrameChecksum), ProtocolError> {
+        if mode == FrameMode::Binary && binary::is_binary(buf) {
+            return binary::parse_event(buf);
         }
+        let (frame, checksum) = check_frame(buf, mode)?;
+        Ok((BotEvent::parse(&frame)?, checksum))
     }
 }
 
@@ -0,0 +1,191 @@
+//! Incremental decoding of protocol lines from a byte stream
+//!
+//! Serial reads deliver partial lines and several lines at once: decoders
+//! accept arbitrary chunks and yield each line (or message) as soon as its
+//! line end arrives. Errors are confined to the line they happen in, so the
+//! stream resynchronises at the next line end.
+
+use core::marker::PhantomData;
+use hal::{new_protocol_buffer, ProtocolBuffer, BINARY_FRAME_MARKER, PROTOCOL_BUFFER_SIZE};
+
+use crate::protocol::{
+    BotCommand, BotEvent, FrameChecksum, FrameMode, ProtocolError, ProtocolErrorKind,
+};
+
+const CODE_END: u8 = b'\n';
+const CODE_CR: u8 = b'\r';
+
+/// Reassembles lines from a byte stream
+///
+/// Lines that do not fit a protocol buffer are reported (once) and the
+/// rest of them is skipped. Carriage returns are ignored (except in
+/// binary frames).
+pub struct LineDecoder {
+    line: ProtocolBuffer,
+    length: usize,
+    discarding: bool,
+    overflows: usize,
+}
+
+impl LineDecoder {
+    pub fn new() -> Self {
+        LineDecoder {
+            line: new_protocol_buffer(),
+            length: 0,
+            discarding: false,
+            overflows: 0,
+        }
+    }
+
+    /// Lines dropped because they did not fit a protocol buffer
+    pub fn overflows(&self) -> usize {
+        self.overflows
+    }
+
+    /// Drop the partial line (if any)
+    pub fn reset(&mut self) {
+        self.line = new_protocol_buffer();
+        self.length = 0;
+        self.discarding = false;
+    }
+
+    /// Decode one byte, returning the line it completes (if any)
+    pub fn push(&mut self, code: u8) -> Option<Result<ProtocolBuffer, ProtocolError>> {
+        if self.discarding {
+            // Skip the rest of an overflowing line
+            self.discarding = code != CODE_END;
+            return None;
+        }
+        if code == CODE_CR && !(self.length > 0 && self.line[0] == BINARY_FRAME_MARKER) {
+            return None;
+        }
+        // Keep room for the line end
+        if code != CODE_END && self.length == PROTOCOL_BUFFER_SIZE - 1 {
+            self.overflows += 1;
+            self.reset();
+            self.discarding = true;
+            return Some(Err(ProtocolError::new(
+                PROTOCOL_BUFFER_SIZE - 1,
+                ProtocolErrorKind::LineOverflow,
+            )));
+        }
+        self.line[self.length] = code;
+        self.length += 1;
+        if code == CODE_END {
+            let line = self.line;
+            self.reset();
+            Some(Ok(line))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for LineDecoder {
+    fn default() -> Self {
+        LineDecoder::new()
+    }
+}
+
+/// Messages that can be decoded from a stream
+pub trait Message: Sized {
+    fn parse_frame(
+        buf: &ProtocolBuffer,
+        mode: FrameMode,
+    ) -> Result<(Self, FrameChecksum), ProtocolError>;
+}
+
+impl Message for BotCommand {
+    fn parse_frame(
+        buf: &ProtocolBuffer,
+        mode: FrameMode,
+    ) -> Result<(Self, FrameChecksum), ProtocolError> {
+        BotCommand::parse_frame(buf, mode)
+    }
+}
+
+impl Message for BotEvent {
+    fn parse_frame(
+        buf: &ProtocolBuffer,
+        mode: FrameMode,
+    ) -> Result<(Self, FrameChecksum), ProtocolError> {
+        BotEvent::parse_frame(buf, mode)
+    }
+}
+
+/// Decodes messages (BotCommand or BotEvent) from a byte stream
+pub struct StreamDecoder<T: Message> {
+    lines: LineDecoder,
+    mode: FrameMode,
+    message: PhantomData<T>,
+}
+
+impl<T: Message> StreamDecoder<T> {
+    pub fn new(mode: FrameMode) -> Self {
+        StreamDecoder {
+            lines: LineDecoder::new(),
+            mode,
+            message: PhantomData,
+        }
+    }
+
+    pub fn mode(&self) -> FrameMode {
+        self.mode
+    }
+
+    /// Change the frame mode (applies from the next line)
+    pub fn set_mode(&mut self, mode: FrameMode) {
+        self.mode = mode;
+    }
+
+    /// Lines dropped because they did not fit a protocol buffer
+    pub fn overflows(&self) -> usize {
+        self.lines.overflows()
+    }
+
+    /// Decode one byte, returning the message it completes (if any)
+    ///
+    /// Messages come with their frame checksum, to be acknowledged in
+    /// Checked and Binary modes.
+    pub fn push(&mut self, code: u8) -> Option<Result<(T, FrameChecksum), ProtocolError>> {
+        self.lines
+            .push(code)
+            .map(|line| line.and_then(|line| T::parse_frame(&line, self.mode)))
+    }
+
+    /// Decode a chunk, iterating over the messages it completes (with their
+    /// frame checksums)
+    ///
+    /// Bytes after the last line end stay in the decoder, waiting for the
+    /// next chunk. Bytes are only consumed as the iteration reaches them,
+    /// so it should always run to the end.
+    pub fn decode<'a>(&'a mut self, chunk: &'a [u8]) -> Decoded<'a, T> {
+        Decoded {
+            decoder: self,
+            chunk,
+            index: 0,
+        }
+    }
+}
+
+/// Messages completed by a chunk (see StreamDecoder::decode)
+pub struct Decoded<'a, T: Message> {
+    decoder: &'a mut StreamDecoder<T>,
+    chunk: &'a [u8],
+    index: usize,
+}
+
+impl<'a, T: Message> Iterator for Decoded<'a, T> {
+    type Item = Result<(T, FrameChecksum), ProtocolError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.chunk.len() {
+            let code = self.chunk[self.index];
+            self.index += 1;
+            if let Some(item) = self.decoder.push(code) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
@@ -7,7 +7,7 @@ fn buffer_from_str(s: &str) -> ProtocolBuffer {
     let mut buffer = new_protocol_buffer();
     for (i, c) in s.chars().enumerate() {
         buffer[i] = c as u8;
-        buffer[i + 1] = '\n' as u8;
+        buffer[i + 1] = b'\n';
     }
     buffer
 }
@@ -79,3 +79,178 @@ fn parses_map() {
     check_relative_eq(map[6].center, V3::new(0.5, 0.0, 0.0));
     check_relative_eq(map[6].end, V3::new(0.0, 0.0, 0.0));
 }
+
+fn section(s: &str) -> ProtocolMapSection {
+    match BotCommand::parse(&buffer_from_str(s)) {
+        Ok(BotCommand::MapSection(section)) => section,
+        _ => panic!("invalid section {}", s),
+    }
+}
+
+fn frame_command(frame: &ProtocolBuffer) -> BotCommand {
+    let (cmd, _) = BotCommand::parse_frame(frame, FrameMode::Checked).ok().unwrap();
+    cmd
+}
+
+/// Acknowledge a frame like the bot would
+fn ack(frame: &ProtocolBuffer) -> BotEvent {
+    let (_, checksum) = check_frame(frame, FrameMode::Checked).ok().unwrap();
+    BotEvent::Ack(checksum)
+}
+
+#[test]
+fn uploads_map_with_retries() {
+    let sections = [section(SECTIONS[0]), section(SECTIONS[1])];
+    let mut uploader = MapUploader::new(&sections);
+
+    let start = uploader.poll(0).unwrap();
+    assert!(frame_command(&start) == BotCommand::MapStart(2));
+    uploader.handle_event(&ack(&start));
+
+    // Retry after a timeout
+    let first = uploader.poll(1000).unwrap();
+    assert!(uploader.poll(100_000).is_none());
+    assert!(uploader.poll(300_000).unwrap() == first);
+
+    // Retry after a rejection, stale acknowledgements are ignored
+    uploader.handle_event(&ack(&start));
+    uploader.handle_event(&BotEvent::Nak(12));
+    assert!(uploader.poll(310_000).unwrap() == first);
+    uploader.handle_event(&ack(&first));
+
+    let second = uploader.poll(320_000).unwrap();
+    uploader.handle_event(&ack(&second));
+    let end = uploader.poll(330_000).unwrap();
+    assert!(frame_command(&end) == BotCommand::MapEnd);
+    assert_eq!(uploader.status(), MapUploadStatus::Sending);
+    uploader.handle_event(&ack(&end));
+    assert_eq!(uploader.status(), MapUploadStatus::Done);
+    assert!(uploader.poll(340_000).is_none());
+}
+
+#[test]
+fn gives_up_map_upload() {
+    let mut uploader = MapUploader::new(&[section(SECTIONS[0])]);
+    let mut now = 0;
+    let mut frames = 0;
+    while uploader.status() == MapUploadStatus::Sending {
+        if uploader.poll(now).is_some() {
+            frames += 1;
+        }
+        now += 250_000;
+    }
+    assert_eq!(uploader.status(), MapUploadStatus::Failed);
+    assert_eq!(frames, MAP_UPLOAD_MAX_RETRIES + 1);
+}
+
+/// Feed upload commands to a receiver, returning the result of the last one
+fn receive(receiver: &mut MapReceiver, cmds: &[BotCommand]) -> Option<Result<Map, MapError>> {
+    let mut result = None;
+    for cmd in cmds.iter() {
+        result = receiver.handle_command(cmd);
+    }
+    result
+}
+
+#[test]
+fn receives_map() {
+    let mut receiver = MapReceiver::new();
+    let mut cmds = vec![BotCommand::MapStart(SECTIONS.len())];
+    // Sections can come in any order
+    for s in SECTIONS.iter().rev() {
+        cmds.push(BotCommand::MapSection(section(s)));
+    }
+    assert!(receive(&mut receiver, &cmds).is_none());
+    assert!(receiver.is_receiving());
+    let map = receive(&mut receiver, &[BotCommand::MapEnd])
+        .unwrap()
+        .ok()
+        .unwrap();
+    assert!(!receiver.is_receiving());
+    assert_eq!(map.length, SECTIONS.len());
+    check_relative_eq(map[6].end, new_map(&SECTIONS)[6].end);
+}
+
+#[test]
+fn rejects_invalid_map_uploads() {
+    let mut receiver = MapReceiver::new();
+    let first = BotCommand::MapSection(section(SECTIONS[0]));
+    let second = BotCommand::MapSection(section(SECTIONS[1]));
+    let third = BotCommand::MapSection(section(SECTIONS[2]));
+    let check = |receiver: &mut MapReceiver, cmds: &[BotCommand], error: MapError| {
+        let result = receive(receiver, cmds).unwrap();
+        assert_eq!(result.err(), Some(error));
+    };
+
+    check(&mut receiver, &[first], MapError::NotStarted);
+    check(&mut receiver, &[BotCommand::MapEnd], MapError::NotStarted);
+    check(
+        &mut receiver,
+        &[
+            BotCommand::MapStart(MAP_SECTIONS_MAX_COUNT + 1),
+            BotCommand::MapEnd,
+        ],
+        MapError::InvalidCount(MAP_SECTIONS_MAX_COUNT + 1),
+    );
+    check(
+        &mut receiver,
+        &[BotCommand::MapStart(2), first, third, BotCommand::MapEnd],
+        MapError::IndexOutOfRange(2),
+    );
+    let other = BotCommand::MapSection(section("MAP-SECTION:0:STRAIGHT:500:800:800"));
+    check(
+        &mut receiver,
+        &[
+            BotCommand::MapStart(2),
+            first,
+            other,
+            second,
+            BotCommand::MapEnd,
+        ],
+        MapError::DuplicateIndex(0),
+    );
+    check(
+        &mut receiver,
+        &[BotCommand::MapStart(3), first, third, BotCommand::MapEnd],
+        MapError::MissingSection(1),
+    );
+    let flat = BotCommand::MapSection(section("MAP-SECTION:1:UP:500:0:800:800"));
+    check(
+        &mut receiver,
+        &[BotCommand::MapStart(2), first, flat, BotCommand::MapEnd],
+        MapError::InvalidSection(1),
+    );
+    assert!(ProtocolBotStatus::from(MapError::NotStarted) == ProtocolBotStatus::InvalidMap);
+
+    // A new upload starts from scratch
+    let result = receive(
+        &mut receiver,
+        &[BotCommand::MapStart(2), second, first, BotCommand::MapEnd],
+    );
+    assert_eq!(result.unwrap().ok().unwrap().length, 2);
+}
+
+#[test]
+fn ignores_map_retransmissions() {
+    let mut receiver = MapReceiver::new();
+    let first = BotCommand::MapSection(section(SECTIONS[0]));
+    let second = BotCommand::MapSection(section(SECTIONS[1]));
+    let result = receive(
+        &mut receiver,
+        &[
+            BotCommand::MapStart(2),
+            BotCommand::MapStart(2),
+            first,
+            first,
+            second,
+            BotCommand::MapEnd,
+        ],
+    );
+    assert_eq!(result.unwrap().ok().unwrap().length, 2);
+    assert!(receiver.handle_command(&BotCommand::MapEnd).is_none());
+    // Sections after a completed upload are stray data
+    let result = receiver.handle_command(&second).unwrap();
+    assert_eq!(result.err(), Some(MapError::NotStarted));
+    let result = receiver.handle_command(&BotCommand::MapEnd).unwrap();
+    assert_eq!(result.err(), Some(MapError::NotStarted));
+}
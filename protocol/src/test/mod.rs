@@ -1,2 +1,3 @@
 mod map_tests;
-mod protocol_tests;
\ No newline at end of file
+mod protocol_tests;
+mod stream_tests;
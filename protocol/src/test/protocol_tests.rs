@@ -1,11 +1,13 @@
-use hal::{ProtocolBuffer,new_protocol_buffer};
+use hal::{ProtocolBuffer,new_protocol_buffer,LASER_COUNT,PROTOCOL_BUFFER_SIZE};
+use crate::binary;
+use crate::map::MAP_SECTIONS_MAX_COUNT;
 use crate::protocol::*;
 
 fn buffer_from_str(s: &str) -> ProtocolBuffer {
     let mut buffer = new_protocol_buffer();
     for (i, c) in s.chars().enumerate() {
         buffer[i] = c as u8;
-        buffer[i + 1] = '\n' as u8;
+        buffer[i + 1] = b'\n';
     }
     buffer
 }
@@ -13,7 +15,7 @@ fn buffer_from_str(s: &str) -> ProtocolBuffer {
 fn buffer_to_string(b: &ProtocolBuffer) -> String {
     let mut s = String::new();
     for c in b.iter() {
-        if *c == '\n' as u8 {
+        if *c == b'\n' {
             break;
         }
         s.push(*c as char);
@@ -21,7 +23,7 @@ fn buffer_to_string(b: &ProtocolBuffer) -> String {
     s
 }
 
-static COMMANDS: [&str; 11] = [
+static COMMANDS: [&str; 27] = [
     "MAP-START:5",
     "MAP-SECTION:0:STRAIGHT:1000:800:800",
     "MAP-SECTION:1:LEFT:90:800:800:500:500",
@@ -33,9 +35,25 @@ static COMMANDS: [&str; 11] = [
     "PAUSE",
     "RESTART",
     "DIRECT:100:-100:0:50",
+    "CALIBRATION-LASER:0:-12:10250",
+    "CALIBRATION-LASER:19:7:9980",
+    "CALIBRATION-IMU:-150",
+    "CALIBRATION-QUERY",
+    "CALIBRATION-STORE",
+    "CALIBRATE-WALL:300:-45",
+    "CALIBRATE-STILL",
+    "CHECKSUM:1",
+    "CHECKSUM:0",
+    "BINARY:1",
+    "BINARY:0",
+    "PARAM-SET:3:-2500",
+    "PARAM-GET:3",
+    "PARAM-LIST",
+    "HELLO",
+    "VERSION",
 ];
 
-static EVENTS: [&str; 11] = [
+static EVENTS: [&str; 21] = [
     "STATUS:INVALID-MAP",
     "STATUS:DEVICE-ERROR",
     "STATUS:STOPPED",
@@ -46,6 +64,16 @@ static EVENTS: [&str; 11] = [
     "LASERS:101:102:103:104:105:106:107:108:109:110:111:112:113:114:115:116:117:118:119:120",
     "IMU:0:0:45:0:0:0:0:0:-1",
     "IMU:2:-5:-45:12:23:4:1:-1:-5",
+    "ENCODERS:360:90:-12:-45:0:0:2147483647:-2147483647",
+    "BATTERY:7400:-120",
+    "BUMPERS:1:0",
+    "BUMPERS:0:1",
+    "CALIBRATION-LASER:4:3:10000",
+    "CALIBRATION-IMU:250",
+    "ACK:12345",
+    "NAK:7",
+    "PARAM:12:6600",
+    "VERSION:1:100:20:20:256:31",
     "LOG:This is a lovely log message",
 ];
 
@@ -67,7 +95,7 @@ fn it_handles_commands() {
                 let rs = buffer_to_string(&rb);
                 assert_eq!(*s, rs);
             }
-            Err(index) => panic!(format!("error parsing {} at index {}", s, index)),
+            Err(error) => panic!("error parsing {}: {}", s, error),
         }
     }
 }
@@ -83,7 +111,292 @@ fn it_handles_events() {
                 let rs = buffer_to_string(&rb);
                 assert_eq!(*s, rs);
             }
-            Err(index) => panic!(format!("error parsing {} at index {}", s, index)),
+            Err(error) => panic!("error parsing {}: {}", s, error),
         }
     }
 }
+
+#[test]
+fn it_writes_log_lines() {
+    use std::fmt::Write;
+
+    let mut line = ProtocolLogLineData::new();
+    writeln!(line, "laser {} timeout", 3).unwrap();
+    let mut rb = new_protocol_buffer();
+    BotEvent::Log(line).write(&mut rb);
+    assert_eq!(buffer_to_string(&rb), "LOG:laser 3 timeout ");
+
+    let mut long_line = ProtocolLogLineData::new();
+    for _ in 0..MAX_LOG_LINE_SIZE {
+        write!(long_line, "xy").unwrap();
+    }
+    assert_eq!(long_line.length, MAX_LOG_LINE_SIZE);
+}
+
+#[test]
+fn it_handles_full_log_lines() {
+    use std::fmt::Write;
+
+    let mut line = ProtocolLogLineData::new();
+    write!(line, "{}", "0123456789".repeat(30)).unwrap();
+    assert_eq!(line.length, MAX_LOG_LINE_SIZE);
+    let mut rb = new_protocol_buffer();
+    BotEvent::Log(line).write(&mut rb);
+    match BotEvent::parse(&rb) {
+        Ok(BotEvent::Log(parsed)) => {
+            assert_eq!(parsed.length, MAX_LOG_LINE_SIZE);
+            assert_eq!(&parsed.message[..], &line.message[..]);
+        }
+        _ => panic!("log line not parsed"),
+    }
+}
+
+#[test]
+fn it_rejects_invalid_values() {
+    let error = BotEvent::parse(&buffer_from_str("BUMPERS:2:0"))
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 8);
+    assert_eq!(error.kind, ProtocolErrorKind::OutOfRange);
+    let error = BotCommand::parse(&buffer_from_str("CALIBRATION-LASER:20:0:10000"))
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 18);
+    assert_eq!(error.field, Some("index"));
+    let error = BotCommand::parse(&buffer_from_str("PARAM-SET:-1:0"))
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 10);
+    assert_eq!(error.field, Some("id"));
+}
+
+#[test]
+fn it_computes_frame_checksums() {
+    let b = buffer_from_str("123456789");
+    assert_eq!(frame_checksum(&b, 9), 0x29B1);
+
+    let mut b = buffer_from_str("PAUSE");
+    let checksum = append_checksum(&mut b);
+    assert_eq!(buffer_to_string(&b), format!("PAUSE*{:04X}", checksum));
+}
+
+#[test]
+fn it_checks_frames() {
+    let mut b = new_protocol_buffer();
+    BotCommand::MapStart(5).write_frame(&mut b, FrameMode::Checked);
+    let (cmd, checksum) = BotCommand::parse_frame(&b, FrameMode::Checked).unwrap();
+    assert!(cmd == BotCommand::MapStart(5));
+    assert_eq!(checksum, frame_checksum(&b, 11));
+
+    // Corrupted payload
+    b[10] = b'6';
+    let error = BotCommand::parse_frame(&b, FrameMode::Checked)
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 11);
+    assert_eq!(error.kind, ProtocolErrorKind::ChecksumMismatch);
+
+    // Missing checksum
+    let b = buffer_from_str("MAP-START:5");
+    let error = BotCommand::parse_frame(&b, FrameMode::Checked)
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 11);
+    assert_eq!(error.kind, ProtocolErrorKind::MissingChecksum);
+    assert!(BotCommand::parse_frame(&b, FrameMode::Plain).is_ok());
+
+    // Plain frames are taken as they are, a trailing "*XXXX" included
+    let mut b = new_protocol_buffer();
+    BotCommand::MapStart(5).write_frame(&mut b, FrameMode::Checked);
+    let (frame, checksum) = check_frame(&b, FrameMode::Plain).unwrap();
+    assert!(frame[..] == b[..]);
+    assert_eq!(checksum, frame_checksum(&b, 16));
+    assert!(BotCommand::parse_frame(&b, FrameMode::Plain).is_err());
+}
+
+#[test]
+fn it_rejects_overflowing_numbers() {
+    let error = BotEvent::parse(&buffer_from_str("BATTERY:99999999999:0"))
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 17);
+    assert_eq!(error.kind, ProtocolErrorKind::Overflow);
+    assert!(BotEvent::parse(&buffer_from_str("ACK:65536")).is_err());
+    assert!(BotEvent::parse(&buffer_from_str("NAK:-1")).is_err());
+
+    // The most negative number fits, one less does not
+    assert!(BotEvent::parse(&buffer_from_str("IMU:-2147483648:0:0:0:0:0:0:0:0")).is_ok());
+    let error = BotEvent::parse(&buffer_from_str("IMU:-2147483649:0:0:0:0:0:0:0:0"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::Overflow);
+}
+
+#[test]
+fn it_writes_extreme_numbers() {
+    for value in [i32::MIN, i32::MAX] {
+        let param = BotEvent::Param(ProtocolParam { id: 1, value });
+        let mut rb = new_protocol_buffer();
+        param.write(&mut rb);
+        assert_eq!(buffer_to_string(&rb), format!("PARAM:1:{}", value));
+        assert!(BotEvent::parse(&rb).ok().unwrap() == param);
+    }
+}
+
+#[test]
+fn it_rejects_negative_counts_and_indexes() {
+    let error = BotCommand::parse(&buffer_from_str("MAP-START:-1"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::OutOfRange);
+    assert_eq!(error.field, Some("count"));
+
+    let error = BotCommand::parse(&buffer_from_str("MAP-SECTION:-2:STRAIGHT:1000:800:800"))
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 12);
+    assert_eq!(error.kind, ProtocolErrorKind::OutOfRange);
+    assert_eq!(error.field, Some("index"));
+}
+
+#[test]
+fn it_rejects_trailing_lasers() {
+    let error = BotEvent::parse(&buffer_from_str(
+        "LASERS:1:2:3:4:5:6:7:8:9:10:11:12:13:14:15:16:17:18:19:20:21",
+    ))
+    .err()
+    .unwrap();
+    assert_eq!(error.index, 57);
+    assert_eq!(error.kind, ProtocolErrorKind::Expected(Expected::End));
+}
+
+#[test]
+fn it_describes_errors() {
+    let error = BotCommand::parse(&buffer_from_str("MAP-SECTION:0:STRAIGHT:1000:800 800"))
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "MAP-SECTION: expected ':' after width_start at column 32"
+    );
+
+    let error = BotCommand::parse(&buffer_from_str("MAP-SECTION:0:CURVE:90"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::UnknownKeyword);
+    assert_eq!(
+        error.to_string(),
+        "MAP-SECTION: unknown keyword for shape at column 15"
+    );
+
+    let error = BotCommand::parse(&buffer_from_str("DIRECT:100:x:0:0"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::Expected(Expected::Digit));
+    assert_eq!(error.field, Some("back_right"));
+
+    let error = BotCommand::parse(&buffer_from_str("RESETALL"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::UnknownKeyword);
+    assert_eq!(error.message, None);
+    let error = BotEvent::parse(&buffer_from_str("12")).err().unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::Expected(Expected::Keyword));
+    let error = BotEvent::parse(&[b'7'; PROTOCOL_BUFFER_SIZE])
+        .err()
+        .unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::Unterminated);
+}
+
+/// Length of a frame, up to its line end
+fn frame_length(b: &ProtocolBuffer) -> usize {
+    b.iter().position(|c| *c == b'\n').unwrap() + 1
+}
+
+#[test]
+fn it_handles_binary_commands() {
+    for s in COMMANDS.iter() {
+        let cmd = BotCommand::parse(&buffer_from_str(s)).ok().unwrap();
+        let mut bb = new_protocol_buffer();
+        cmd.write_frame(&mut bb, FrameMode::Binary);
+        match BotCommand::parse_frame(&bb, FrameMode::Binary) {
+            Ok((cmd, _)) => {
+                let mut rb = new_protocol_buffer();
+                cmd.write(&mut rb);
+                let rs = buffer_to_string(&rb);
+                assert_eq!(*s, rs);
+            }
+            Err(error) => panic!("error parsing binary {}: {}", s, error),
+        }
+    }
+}
+
+#[test]
+fn it_handles_binary_events() {
+    for s in EVENTS.iter() {
+        let evt = BotEvent::parse(&buffer_from_str(s)).ok().unwrap();
+        let mut bb = new_protocol_buffer();
+        evt.write_frame(&mut bb, FrameMode::Binary);
+        match BotEvent::parse_frame(&bb, FrameMode::Binary) {
+            Ok((evt, _)) => {
+                let mut rb = new_protocol_buffer();
+                evt.write(&mut rb);
+                let rs = buffer_to_string(&rb);
+                assert_eq!(*s, rs);
+            }
+            Err(error) => panic!("error parsing binary {}: {}", s, error),
+        }
+    }
+}
+
+#[test]
+fn it_checks_binary_frames() {
+    // Bytes that encode as a line end or a carriage return included
+    let lasers = BotEvent::Lasers([
+        0, 7, 10, 13, 255, 256, 1000, 1500, 2000, 1999, 1234, 800, 700, 650, 900, 1100, 1300, 1700,
+        1800, 1900,
+    ]);
+    let mut tb = new_protocol_buffer();
+    lasers.write(&mut tb);
+    let mut bb = new_protocol_buffer();
+    lasers.write_frame(&mut bb, FrameMode::Binary);
+    assert_eq!(bb[0], hal::BINARY_FRAME_MARKER);
+    assert!(frame_length(&bb) < frame_length(&tb));
+
+    let (evt, checksum) = BotEvent::parse_frame(&bb, FrameMode::Binary).ok().unwrap();
+    assert!(evt == lasers);
+    let mut cb = new_protocol_buffer();
+    let pause_checksum = binary::write_command(&BotCommand::Pause, &mut cb);
+    let (cmd, ack) = BotCommand::parse_frame(&cb, FrameMode::Binary)
+        .ok()
+        .unwrap();
+    assert!(cmd == BotCommand::Pause);
+    assert_eq!(ack, pause_checksum);
+    assert_ne!(ack, checksum);
+
+    // Corrupted payload
+    bb[20] ^= 1;
+    let error = BotEvent::parse_frame(&bb, FrameMode::Binary).err().unwrap();
+    assert_eq!(error.kind, ProtocolErrorKind::ChecksumMismatch);
+
+    // Text is still accepted
+    let (cmd, _) = BotCommand::parse_frame(&buffer_from_str("RESET"), FrameMode::Binary)
+        .ok()
+        .unwrap();
+    assert!(cmd == BotCommand::Reset);
+}
+
+#[test]
+fn it_reports_versions() {
+    let version = ProtocolVersionData::new(203, FEATURE_CHECKSUM | FEATURE_PARAMS);
+    assert_eq!(version.protocol, PROTOCOL_VERSION);
+    assert_eq!(version.lasers, LASER_COUNT);
+    assert_eq!(version.map_sections, MAP_SECTIONS_MAX_COUNT);
+    assert_eq!(version.buffer_size, PROTOCOL_BUFFER_SIZE);
+    assert!(version.supports(FEATURE_PARAMS));
+    assert!(!version.supports(FEATURE_PARAMS | FEATURE_BINARY));
+
+    let mut buf = new_protocol_buffer();
+    BotEvent::Version(version).write(&mut buf);
+    assert!(BotEvent::parse(&buf).ok().unwrap() == BotEvent::Version(version));
+}
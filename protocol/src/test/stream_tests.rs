@@ -0,0 +1,101 @@
+use crate::protocol::*;
+use crate::stream::*;
+
+fn decode_all(decoder: &mut StreamDecoder<BotCommand>, chunk: &[u8]) -> Vec<BotCommand> {
+    decoder
+        .decode(chunk)
+        .map(|cmd| cmd.ok().unwrap().0)
+        .collect()
+}
+
+#[test]
+fn it_decodes_partial_and_concatenated_lines() {
+    let mut decoder = StreamDecoder::<BotCommand>::new(FrameMode::Plain);
+    assert!(decode_all(&mut decoder, b"MAP-ST").is_empty());
+    assert!(decode_all(&mut decoder, b"ART:2\nPAU") == vec![BotCommand::MapStart(2)]);
+    assert!(
+        decode_all(&mut decoder, b"SE\r\nMAP-END\nRESET\n")
+            == vec![BotCommand::Pause, BotCommand::MapEnd, BotCommand::Reset]
+    );
+}
+
+#[test]
+fn it_resynchronises_after_garbage() {
+    let mut decoder = StreamDecoder::<BotCommand>::new(FrameMode::Plain);
+    let results: Vec<_> = decoder.decode(b"\x00\xffPA\nUSE\nPAUSE\n").collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[0].err().unwrap().kind,
+        ProtocolErrorKind::Expected(Expected::Keyword)
+    );
+    assert_eq!(
+        results[1].err().unwrap().kind,
+        ProtocolErrorKind::UnknownKeyword
+    );
+    assert!(results[2].ok().unwrap().0 == BotCommand::Pause);
+}
+
+#[test]
+fn it_reports_overflowing_lines() {
+    let mut decoder = StreamDecoder::<BotCommand>::new(FrameMode::Plain);
+    let long_line = [b'7'; 1000];
+    let results: Vec<_> = decoder.decode(&long_line).collect();
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].err().unwrap().kind,
+        ProtocolErrorKind::LineOverflow
+    );
+    assert_eq!(decoder.overflows(), 1);
+
+    // The rest of the line is skipped
+    assert!(decode_all(&mut decoder, b"77\nRESTART\n") == vec![BotCommand::Restart]);
+}
+
+#[test]
+fn it_decodes_checked_frames() {
+    let mut decoder = StreamDecoder::<BotEvent>::new(FrameMode::Checked);
+    let mut frame = hal::new_protocol_buffer();
+    BotEvent::Ack(42).write_frame(&mut frame, FrameMode::Checked);
+    let (_, checksum) = BotEvent::parse_frame(&frame, FrameMode::Checked)
+        .ok()
+        .unwrap();
+    let length = frame.iter().position(|code| *code == b'\n').unwrap() + 1;
+
+    let mut stream = b"NAK:3\n".to_vec();
+    stream.extend_from_slice(&frame[..length]);
+    let results: Vec<_> = decoder.decode(&stream).collect();
+    assert_eq!(
+        results[0].err().unwrap().kind,
+        ProtocolErrorKind::MissingChecksum
+    );
+    assert!(results[1].ok().unwrap() == (BotEvent::Ack(42), checksum));
+}
+
+#[test]
+fn it_decodes_binary_frames() {
+    let mut decoder = StreamDecoder::<BotEvent>::new(FrameMode::Binary);
+    // 7 is encoded as a carriage return
+    let lasers = BotEvent::Lasers([7; hal::LASER_COUNT]);
+    let mut frame = hal::new_protocol_buffer();
+    lasers.write_frame(&mut frame, FrameMode::Binary);
+    let (_, checksum) = BotEvent::parse_frame(&frame, FrameMode::Binary)
+        .ok()
+        .unwrap();
+    let length = frame.iter().position(|code| *code == b'\n').unwrap() + 1;
+    assert!(frame[..length].contains(&b'\r'));
+
+    let mut stream = b"BUMPERS:1:0\r\n".to_vec();
+    stream.extend_from_slice(&frame[..length]);
+    let (first, second) = stream.split_at(20);
+    let mut results: Vec<_> = decoder.decode(first).collect();
+    results.extend(decoder.decode(second));
+    assert_eq!(results.len(), 2);
+    assert!(
+        results[0].ok().unwrap().0
+            == BotEvent::Bumpers(ProtocolBumperData {
+                front: true,
+                rear: false
+            })
+    );
+    assert!(results[1].ok().unwrap() == (lasers, checksum));
+}
@@ -0,0 +1,293 @@
+//! Newline framed protocol transport over a Linux tty or pseudo terminal
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+use hal::calibration::Calibration;
+use hal::{
+    BatteryData, BumperData, DeviceHal, EncoderData, HalError, ImuData, LaserData, MotorPower,
+    ProtocolBuffer, Timestamp, Timestamped, PROTOCOL_BUFFER_SIZE,
+};
+use protocol::stream::LineDecoder;
+
+#[cfg(test)]
+mod test;
+
+/// Default baud rate of the bot serial link
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Maximum amount of data waiting to be written (bytes)
+pub const TX_BACKLOG_MAX: usize = 16 * PROTOCOL_BUFFER_SIZE;
+
+const CODE_END: u8 = b'\n';
+
+fn baud_speed(baud: u32) -> io::Result<libc::speed_t> {
+    match baud {
+        1200 => Ok(libc::B1200),
+        2400 => Ok(libc::B2400),
+        4800 => Ok(libc::B4800),
+        9600 => Ok(libc::B9600),
+        19200 => Ok(libc::B19200),
+        38400 => Ok(libc::B38400),
+        57600 => Ok(libc::B57600),
+        115_200 => Ok(libc::B115200),
+        230_400 => Ok(libc::B230400),
+        460_800 => Ok(libc::B460800),
+        500_000 => Ok(libc::B500000),
+        576_000 => Ok(libc::B576000),
+        921_600 => Ok(libc::B921600),
+        1_000_000 => Ok(libc::B1000000),
+        2_000_000 => Ok(libc::B2000000),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "unsupported baud rate",
+        )),
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Put a terminal in raw non blocking mode at the given baud rate
+fn configure(fd: RawFd, baud: u32) -> io::Result<()> {
+    let speed = baud_speed(baud)?;
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        check(libc::tcgetattr(fd, &mut termios))?;
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = 0;
+        check(libc::cfsetispeed(&mut termios, speed))?;
+        check(libc::cfsetospeed(&mut termios, speed))?;
+        check(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+
+        let flags = check(libc::fcntl(fd, libc::F_GETFL))?;
+        check(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+    }
+    Ok(())
+}
+
+/// Protocol link over a serial line
+///
+/// Incoming bytes are reassembled into lines, each returned as a protocol
+/// buffer; lines that do not fit a buffer are dropped. Outgoing buffers
+/// are written up to their end of line, without ever blocking.
+pub struct SerialLink {
+    file: File,
+    decoder: LineDecoder,
+    lines: VecDeque<ProtocolBuffer>,
+    tx: VecDeque<u8>,
+    tx_overflows: usize,
+    error: Option<io::Error>,
+}
+
+impl SerialLink {
+    fn new(file: File) -> Self {
+        SerialLink {
+            file,
+            decoder: LineDecoder::new(),
+            lines: VecDeque::new(),
+            tx: VecDeque::new(),
+            tx_overflows: 0,
+            error: None,
+        }
+    }
+
+    /// Open a tty device (like /dev/ttyUSB0)
+    pub fn open<P: AsRef<Path>>(path: P, baud: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        configure(file.as_raw_fd(), baud)?;
+        Ok(SerialLink::new(file))
+    }
+
+    /// Two links wired together through a pseudo terminal
+    pub fn pty_pair(baud: u32) -> io::Result<(Self, Self)> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        check(unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        })?;
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        configure(master.as_raw_fd(), baud)?;
+        configure(slave.as_raw_fd(), baud)?;
+        Ok((SerialLink::new(master), SerialLink::new(slave)))
+    }
+
+    /// Lines dropped because they did not fit a protocol buffer
+    pub fn rx_overflows(&self) -> usize {
+        self.decoder.overflows()
+    }
+
+    /// Buffers dropped because too much data was waiting to be written
+    pub fn tx_overflows(&self) -> usize {
+        self.tx_overflows
+    }
+
+    /// First IO error (the link stops working after it)
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Next complete incoming line (if any)
+    pub fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.flush();
+        self.receive();
+        self.lines.pop_front()
+    }
+
+    /// Queue a buffer (up to its end of line) and write as much as possible
+    pub fn send(&mut self, data: ProtocolBuffer) {
+        let length = match data.iter().position(|c| *c == CODE_END) {
+            Some(end) => end,
+            None => data.iter().position(|c| *c == 0).unwrap_or(data.len()),
+        };
+        if self.tx.len() + length + 1 > TX_BACKLOG_MAX {
+            self.tx_overflows += 1;
+        } else {
+            self.tx.extend(data[..length].iter());
+            self.tx.push_back(CODE_END);
+        }
+        self.flush();
+    }
+
+    /// Write queued data without blocking
+    pub fn flush(&mut self) {
+        while self.error.is_none() && !self.tx.is_empty() {
+            let (data, _) = self.tx.as_slices();
+            match self.file.write(data) {
+                Ok(count) => {
+                    self.tx.drain(..count);
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => self.error = Some(error),
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut chunk = [0; PROTOCOL_BUFFER_SIZE];
+        while self.error.is_none() {
+            match self.file.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(count) => {
+                    // Overflowing lines are dropped (and counted by the decoder)
+                    for code in chunk[..count].iter() {
+                        if let Some(Ok(line)) = self.decoder.push(*code) {
+                            self.lines.push_back(line);
+                        }
+                    }
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => self.error = Some(error),
+            }
+        }
+    }
+}
+
+/// Device HAL decorator that exchanges protocol data over a serial link
+pub struct SerialHal<H: DeviceHal> {
+    hal: H,
+    link: SerialLink,
+}
+
+impl<H: DeviceHal> SerialHal<H> {
+    pub fn new(hal: H, link: SerialLink) -> Self {
+        SerialHal { hal, link }
+    }
+
+    pub fn hal(&self) -> &H {
+        &self.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut H {
+        &mut self.hal
+    }
+
+    pub fn link(&self) -> &SerialLink {
+        &self.link
+    }
+
+    pub fn link_mut(&mut self) -> &mut SerialLink {
+        &mut self.link
+    }
+}
+
+impl<H: DeviceHal> DeviceHal for SerialHal<H> {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.hal.init()
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.hal.now()
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        self.hal.read_imu()
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        self.hal.read_lasers()
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        self.hal.read_encoders()
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        self.hal.read_battery()
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        self.hal.read_bumpers()
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.hal
+            .set_motor_power(back_left, back_right, front_left, front_right);
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.link.poll()
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.link.send(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.hal.load_calibration()
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.hal.store_calibration(calibration)
+    }
+}
@@ -0,0 +1 @@
+mod serial_tests;
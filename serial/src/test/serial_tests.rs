@@ -0,0 +1,65 @@
+use crate::*;
+use hal::new_protocol_buffer;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn buffer_from_str(s: &str) -> ProtocolBuffer {
+    let mut buffer = new_protocol_buffer();
+    buffer[..s.len()].copy_from_slice(s.as_bytes());
+    buffer
+}
+
+fn buffer_to_string(buffer: &ProtocolBuffer) -> String {
+    let end = buffer.iter().position(|c| *c == b'\n').unwrap() + 1;
+    String::from_utf8(buffer[..end].to_vec()).unwrap()
+}
+
+/// Poll until a line arrives (the pty needs a little time)
+fn receive(link: &mut SerialLink) -> Option<String> {
+    for _ in 0..100 {
+        if let Some(buffer) = link.poll() {
+            return Some(buffer_to_string(&buffer));
+        }
+        sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+#[test]
+fn it_exchanges_lines() {
+    let (mut bot, mut station) = SerialLink::pty_pair(DEFAULT_BAUD_RATE).unwrap();
+    station.send(buffer_from_str("START\n"));
+    bot.send(buffer_from_str("STATUS:STOPPED\n"));
+    assert_eq!(receive(&mut bot).unwrap(), "START\n");
+    assert_eq!(receive(&mut station).unwrap(), "STATUS:STOPPED\n");
+    assert!(bot.poll().is_none());
+    assert!(bot.error().is_none());
+}
+
+#[test]
+fn it_reassembles_lines() {
+    let (mut bot, station) = SerialLink::pty_pair(DEFAULT_BAUD_RATE).unwrap();
+    let mut writer = &station.file;
+    writer.write_all(b"STA").unwrap();
+    assert!(receive(&mut bot).is_none());
+    writer.write_all(b"RT\r\nPAUSE\nRES").unwrap();
+    assert_eq!(receive(&mut bot).unwrap(), "START\n");
+    assert_eq!(receive(&mut bot).unwrap(), "PAUSE\n");
+    writer.write_all(b"ET\n").unwrap();
+    assert_eq!(receive(&mut bot).unwrap(), "RESET\n");
+}
+
+#[test]
+fn it_drops_overflowing_lines() {
+    let (mut bot, station) = SerialLink::pty_pair(DEFAULT_BAUD_RATE).unwrap();
+    let mut writer = &station.file;
+    writer.write_all(&[b'A'; PROTOCOL_BUFFER_SIZE]).unwrap();
+    writer.write_all(b"AAAA\nPAUSE\n").unwrap();
+    assert_eq!(receive(&mut bot).unwrap(), "PAUSE\n");
+    assert_eq!(bot.rx_overflows(), 1);
+}
+
+#[test]
+fn it_rejects_unsupported_baud_rates() {
+    assert!(SerialLink::pty_pair(12345).is_err());
+}
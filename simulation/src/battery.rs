@@ -0,0 +1,65 @@
+use hal::{BatteryData, Current, Voltage};
+
+/// Cells in the battery pack
+pub const BATTERY_CELLS: usize = 2;
+/// Cell voltage when fully charged
+pub const CELL_VOLTAGE_FULL: Voltage = 4.2;
+/// Cell voltage when fully discharged
+pub const CELL_VOLTAGE_EMPTY: Voltage = 3.3;
+/// Battery capacity (Ah)
+pub const BATTERY_CAPACITY: f32 = 1.0;
+/// Pack internal resistance (ohm)
+pub const BATTERY_INTERNAL_RESISTANCE: f32 = 0.08;
+/// Current drawn by the electronics
+pub const IDLE_CURRENT: Current = 0.15;
+/// Current drawn by a motor at full power
+pub const MOTOR_CURRENT: Current = 1.2;
+
+/// Simple LiPo pack model: linear discharge curve plus internal resistance
+pub struct SimulatedBattery {
+    /// Remaining charge (from 0 to 1)
+    charge: f32,
+    /// Current drawn in the last step
+    current: Current,
+}
+
+impl SimulatedBattery {
+    pub fn new() -> Self {
+        SimulatedBattery {
+            charge: 1.0,
+            current: IDLE_CURRENT,
+        }
+    }
+
+    pub fn charge(&self) -> f32 {
+        self.charge
+    }
+
+    pub fn set_charge(&mut self, charge: f32) {
+        self.charge = charge.max(0.0).min(1.0);
+    }
+
+    /// Open circuit voltage
+    pub fn open_voltage(&self) -> Voltage {
+        let cell = CELL_VOLTAGE_EMPTY + (CELL_VOLTAGE_FULL - CELL_VOLTAGE_EMPTY) * self.charge;
+        cell * BATTERY_CELLS as f32
+    }
+
+    /// Drain the battery for dt seconds, given the (absolute) motor powers
+    pub fn discharge(&mut self, motor_powers: &[f32], dt: f32) {
+        let mut current = IDLE_CURRENT;
+        for power in motor_powers.iter() {
+            current += power.abs().min(1.0) * MOTOR_CURRENT;
+        }
+        self.current = current;
+        self.set_charge(self.charge - (current * dt / 3600.0 / BATTERY_CAPACITY));
+    }
+
+    /// Voltage and current as seen by the battery monitor (voltage sags under load)
+    pub fn read(&self) -> BatteryData {
+        BatteryData {
+            voltage: self.open_voltage() - self.current * BATTERY_INTERNAL_RESISTANCE,
+            current: self.current,
+        }
+    }
+}
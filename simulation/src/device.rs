@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use hal::calibration::Calibration;
+use hal::{
+    BatteryData, BumperData, DeviceHal, EncoderData, HalError, ImuData, LaserData, MotorPower,
+    ProtocolBuffer, Timestamp, Timestamped,
+};
+
+use crate::SimulatedWorld;
+
+/// Device HAL backed by a simulated world
+pub struct SimulatedHal {
+    world: SimulatedWorld,
+    /// Buffers waiting to be polled by the bot
+    incoming: VecDeque<ProtocolBuffer>,
+    /// Buffers sent by the bot
+    outgoing: VecDeque<ProtocolBuffer>,
+    /// Calibration storage (kept in memory)
+    calibration: Option<Calibration>,
+}
+
+impl SimulatedHal {
+    pub fn new(world: SimulatedWorld) -> Self {
+        SimulatedHal {
+            world,
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            calibration: None,
+        }
+    }
+
+    pub fn world(&self) -> &SimulatedWorld {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut SimulatedWorld {
+        &mut self.world
+    }
+
+    /// Queue data for the bot (as if received on the serial line)
+    pub fn push_incoming(&mut self, data: ProtocolBuffer) {
+        self.incoming.push_back(data);
+    }
+
+    /// Take the oldest data sent by the bot
+    pub fn pop_outgoing(&mut self) -> Option<ProtocolBuffer> {
+        self.outgoing.pop_front()
+    }
+}
+
+impl DeviceHal for SimulatedHal {
+    fn init(&mut self) -> Result<(), HalError> {
+        self.world.set_motor_power(0.0, 0.0, 0.0, 0.0);
+        Ok(())
+    }
+
+    fn now(&mut self) -> Timestamp {
+        self.world.now()
+    }
+
+    fn read_imu(&mut self) -> Result<Timestamped<ImuData>, HalError> {
+        Ok(Timestamped {
+            timestamp: self.world.now(),
+            data: self.world.read_imu(),
+        })
+    }
+
+    fn read_lasers(&mut self) -> Result<Timestamped<LaserData>, HalError> {
+        Ok(Timestamped {
+            timestamp: self.world.now(),
+            data: self.world.read_lasers(),
+        })
+    }
+
+    fn read_encoders(&mut self) -> Result<EncoderData, HalError> {
+        Ok(self.world.read_encoders())
+    }
+
+    fn read_battery(&mut self) -> Result<BatteryData, HalError> {
+        Ok(self.world.read_battery())
+    }
+
+    fn read_bumpers(&mut self) -> Result<Option<BumperData>, HalError> {
+        Ok(Some(self.world.read_bumpers()))
+    }
+
+    fn set_motor_power(
+        &mut self,
+        back_left: MotorPower,
+        back_right: MotorPower,
+        front_left: MotorPower,
+        front_right: MotorPower,
+    ) {
+        self.world
+            .set_motor_power(back_left, back_right, front_left, front_right);
+    }
+
+    fn poll(&mut self) -> Option<ProtocolBuffer> {
+        self.incoming.pop_front()
+    }
+
+    fn send(&mut self, data: ProtocolBuffer) {
+        self.outgoing.push_back(data);
+    }
+
+    fn load_calibration(&mut self) -> Option<Calibration> {
+        self.calibration
+    }
+
+    fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), HalError> {
+        self.calibration = Some(*calibration);
+        Ok(())
+    }
+}
@@ -1,16 +1,36 @@
+use core::f32::consts::PI;
 use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
 
+use ncollide3d::pipeline::object::CollisionGroups;
+use ncollide3d::query::Ray;
 use ncollide3d::shape::{Ball, Cuboid, ShapeHandle};
 use nphysics3d::force_generator::DefaultForceGeneratorSet;
 use nphysics3d::joint::DefaultJointConstraintSet;
-use nphysics3d::object::{BodyPartHandle, DefaultBodyHandle, DefaultBodySet, DefaultColliderSet};
+use nphysics3d::object::{
+    BodyPartHandle, DefaultBodyHandle, DefaultBodySet, DefaultColliderHandle, DefaultColliderSet,
+};
 use nphysics3d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld};
 
 use nphysics3d::joint::{FixedJoint, FreeJoint, RevoluteJoint};
 use nphysics3d::object::{ColliderDesc, Ground, MultibodyDesc};
 
+use hal::layout::{LaserLayout, LaserMount};
+use hal::safety::clamp_motor_power;
+use hal::{
+    Acceleration, Angle, BatteryData, BumperData, EncoderData, ImuData, LaserData, Mm, MmPerS2, Rad,
+    Timestamp, WheelEncoderData, ENCODER_TICKS_PER_REVOLUTION, LASER_COUNT,
+};
 use map::*;
 use protocol::map::Map;
+use protocol::protocol::ProtocolImuData;
+
+pub mod battery;
+pub mod device;
+
+#[cfg(test)]
+mod test;
+
+use battery::SimulatedBattery;
 
 pub struct SimulatedWorld {
     mechanical_world: DefaultMechanicalWorld<f32>,
@@ -24,6 +44,10 @@ pub struct SimulatedWorld {
     ground_part_count: usize,
 
     car: DefaultBodyHandle,
+    car_body_collider: DefaultColliderHandle,
+
+    /// Colliders of the map walls (the ones bumpers can hit)
+    wall_colliders: Vec<DefaultColliderHandle>,
 
     car_part_id_body: usize,
     car_part_id_bl: usize,
@@ -37,10 +61,36 @@ pub struct SimulatedWorld {
 
     motor_stall_torque: f32,
     motor_max_speed: f32,
+
+    laser_layout: LaserLayout,
+
+    car_velocity: NaV3,
+    car_acceleration: NaV3,
+
+    /// Total wheel rotations (radians, integrated at each step)
+    wheel_travel_bl: f32,
+    wheel_travel_br: f32,
+    wheel_travel_fl: f32,
+    wheel_travel_fr: f32,
+
+    battery: SimulatedBattery,
+
+    /// Steps performed so far (drives the simulated clock)
+    steps: u64,
 }
 
 const COLLIDER_MARGIN: f32 = 0.001;
 
+/// Collision group of the surfaces the car drives on
+const FLOOR_GROUP: usize = 1;
+/// Collision group of obstacles (colliders are in every group by default)
+const OBSTACLE_GROUP: usize = 2;
+
+/// Collision groups of the ground and of the map floor
+fn floor_groups() -> CollisionGroups {
+    CollisionGroups::new().with_membership(&[FLOOR_GROUP])
+}
+
 fn cuboid(l: f32, w: f32, h: f32) -> ColliderDesc<f32> {
     ColliderDesc::new(ShapeHandle::new(Cuboid::new(Vector3::new(
         l / 2.0,
@@ -71,11 +121,22 @@ fn isometry_xyz(x: f32, y: f32, z: f32) -> Isometry3<f32> {
     )
 }
 
-const WHEEL_DISPLACEMENT_Z: f32 = (CAR_LENGTH / 2.0) - (CAR_WHEEL_RADIUS + CAR_WHEEL_SPACE);
-const WHEEL_DISPLACEMENT_X: f32 = CAR_WIDTH / 2.0;
-const BODY_LENGTH: f32 = CAR_LENGTH;
-const BODY_WIDTH: f32 = CAR_WIDTH - 2.0 * (CAR_WHEEL_RADIUS + CAR_WHEEL_SPACE);
-const BODY_HEIGHT: f32 = CAR_WHEEL_RADIUS;
+/// Heading, pitch and roll of a body rotation (see `SimulatedWorld::read_imu`)
+fn body_angles(rotation: &NaQ) -> (Angle, Angle, Angle) {
+    let front = rotation.transform_vector(&NaV3::z());
+    let up = rotation.transform_vector(&NaV3::y());
+    let right = rotation.transform_vector(&-NaV3::x());
+    // Rotations around y are counterclockwise seen from above
+    let heading = -front.x.atan2(front.z);
+    let pitch = front.y.max(-1.0).min(1.0).asin();
+    let roll = (-right.y).atan2(up.y);
+    (Rad(heading), Rad(pitch), Rad(roll))
+}
+
+const WHEEL_DISPLACEMENT_Z: f32 = (CAR_LENGTH.0 / 2.0) - (CAR_WHEEL_RADIUS.0 + CAR_WHEEL_SPACE.0);
+const BODY_LENGTH: f32 = CAR_LENGTH.0;
+const BODY_WIDTH: f32 = CAR_WIDTH.0 - 2.0 * (CAR_WHEEL_RADIUS.0 + CAR_WHEEL_SPACE.0);
+const BODY_HEIGHT: f32 = CAR_WHEEL_RADIUS.0;
 const GROUND_THICKNESS: f32 = 0.1;
 
 fn wheel_joint() -> RevoluteJoint<f32> {
@@ -85,16 +146,23 @@ fn wheel_joint() -> RevoluteJoint<f32> {
     joint
 }
 
+const GRAVITY: f32 = 9.81;
+const STEPS_PER_SECOND: u64 = 120;
+const TIMESTEP: f32 = 1.0 / STEPS_PER_SECOND as f32;
+
 const MOTOR_STALL_TORQUE: f32 = 0.4 / 3.0;
-const MOTOR_MAX_RPM: f32 = 220.0 * 3.0;
 
 impl SimulatedWorld {
     pub fn new() -> Self {
-        let mut mechanical_world = DefaultMechanicalWorld::new(Vector3::new(0.0, -9.81, 0.0));
+        let mut mechanical_world = DefaultMechanicalWorld::new(Vector3::new(0.0, -GRAVITY, 0.0));
         let mut bodies = DefaultBodySet::new();
         let mut colliders: DefaultColliderSet<f32> = DefaultColliderSet::new();
         let joint_constraints = DefaultJointConstraintSet::new();
 
+        // Same wheel spacing as the car kinematics (used for odometry)
+        let car = Car::new();
+        let wheel_x = car.wheel_x().0;
+
         let mut car_root_desc =
             MultibodyDesc::new(FreeJoint::new(isometry_xyz(0.0, 0.4, 0.0))).name("car".to_owned());
 
@@ -104,35 +172,19 @@ impl SimulatedWorld {
         car_root_desc
             .add_child(wheel_joint())
             .set_name("bl".to_owned())
-            .set_parent_shift(Vector3::new(
-                WHEEL_DISPLACEMENT_X,
-                0.0,
-                -WHEEL_DISPLACEMENT_Z,
-            ));
+            .set_parent_shift(Vector3::new(wheel_x, 0.0, -WHEEL_DISPLACEMENT_Z));
         car_root_desc
             .add_child(wheel_joint())
             .set_name("br".to_owned())
-            .set_parent_shift(Vector3::new(
-                -WHEEL_DISPLACEMENT_X,
-                0.0,
-                -WHEEL_DISPLACEMENT_Z,
-            ));
+            .set_parent_shift(Vector3::new(-wheel_x, 0.0, -WHEEL_DISPLACEMENT_Z));
         car_root_desc
             .add_child(wheel_joint())
             .set_name("fl".to_owned())
-            .set_parent_shift(Vector3::new(
-                WHEEL_DISPLACEMENT_X,
-                0.0,
-                WHEEL_DISPLACEMENT_Z,
-            ));
+            .set_parent_shift(Vector3::new(wheel_x, 0.0, WHEEL_DISPLACEMENT_Z));
         car_root_desc
             .add_child(wheel_joint())
             .set_name("fr".to_owned())
-            .set_parent_shift(Vector3::new(
-                -WHEEL_DISPLACEMENT_X,
-                0.0,
-                WHEEL_DISPLACEMENT_Z,
-            ));
+            .set_parent_shift(Vector3::new(-wheel_x, 0.0, WHEEL_DISPLACEMENT_Z));
 
         let car_multibody = car_root_desc.build();
         let car_part_id_body = car_multibody
@@ -167,23 +219,24 @@ impl SimulatedWorld {
             .link_id();
         let car_root = bodies.insert(car_multibody);
 
-        colliders.insert(
+        let car_body_collider = colliders.insert(
             cuboid(BODY_WIDTH, BODY_HEIGHT, BODY_LENGTH)
                 .build(BodyPartHandle(car_root, car_part_id_body)),
         );
-        colliders.insert(ball(CAR_WHEEL_RADIUS).build(BodyPartHandle(car_root, car_part_id_bl)));
-        colliders.insert(ball(CAR_WHEEL_RADIUS).build(BodyPartHandle(car_root, car_part_id_br)));
-        colliders.insert(ball(CAR_WHEEL_RADIUS).build(BodyPartHandle(car_root, car_part_id_fl)));
-        colliders.insert(ball(CAR_WHEEL_RADIUS).build(BodyPartHandle(car_root, car_part_id_fr)));
+        colliders.insert(ball(CAR_WHEEL_RADIUS.0).build(BodyPartHandle(car_root, car_part_id_bl)));
+        colliders.insert(ball(CAR_WHEEL_RADIUS.0).build(BodyPartHandle(car_root, car_part_id_br)));
+        colliders.insert(ball(CAR_WHEEL_RADIUS.0).build(BodyPartHandle(car_root, car_part_id_fl)));
+        colliders.insert(ball(CAR_WHEEL_RADIUS.0).build(BodyPartHandle(car_root, car_part_id_fr)));
 
         let ground_shape = ShapeHandle::new(Cuboid::new(Vector3::new(6.0, GROUND_THICKNESS, 6.0)));
         let ground = bodies.insert(Ground::new());
         let ground_collider = ColliderDesc::new(ground_shape)
             .translation(Vector3::y() * -GROUND_THICKNESS)
+            .collision_groups(floor_groups())
             .build(BodyPartHandle(ground, 0));
         colliders.insert(ground_collider);
 
-        mechanical_world.set_timestep(1.0 / 120.0);
+        mechanical_world.set_timestep(TIMESTEP);
 
         SimulatedWorld {
             mechanical_world,
@@ -197,6 +250,8 @@ impl SimulatedWorld {
             ground_part_count: 1,
 
             car: car_root,
+            car_body_collider,
+            wall_colliders: Vec::new(),
             car_part_id_body,
             car_part_id_bl,
             car_part_id_br,
@@ -207,10 +262,29 @@ impl SimulatedWorld {
             car_motor_power_fl: 0.0,
             car_motor_power_fr: 0.0,
             motor_stall_torque: MOTOR_STALL_TORQUE,
-            motor_max_speed: (360.0 * MOTOR_MAX_RPM / 60.0).to_radians(),
+            motor_max_speed: (360.0 * CAR_MOTOR_MAX_RPM / 60.0).to_radians(),
+
+            laser_layout: car.lasers,
+
+            car_velocity: NaV3::zeros(),
+            car_acceleration: NaV3::zeros(),
+
+            wheel_travel_bl: 0.0,
+            wheel_travel_br: 0.0,
+            wheel_travel_fl: 0.0,
+            wheel_travel_fr: 0.0,
+
+            battery: SimulatedBattery::new(),
+
+            steps: 0,
         }
     }
 
+    /// Simulated clock: time elapsed in fixed timesteps since creation
+    pub fn now(&self) -> Timestamp {
+        self.steps * 1_000_000 / STEPS_PER_SECOND
+    }
+
     pub fn car_body(&self) -> BodyPartHandle<DefaultBodyHandle> {
         BodyPartHandle(self.car, self.car_part_id_body)
     }
@@ -233,6 +307,63 @@ impl SimulatedWorld {
         body.position()
     }
 
+    fn body_velocity(&self) -> NaV3 {
+        let car = self.bodies.get(self.car).unwrap();
+        let body = car.part(self.car_part_id_body).unwrap();
+        body.velocity().linear
+    }
+
+    /// Read IMU data from the body pose and velocity history
+    ///
+    /// Angles follow the hardware conventions: heading is positive clockwise
+    /// seen from above, pitch is positive nose up and roll is positive when
+    /// leaning on the right side.
+    /// Accelerations are in mm/s2 in the body frame (x left, y up, z front)
+    /// and do not include gravity.
+    pub fn read_imu(&self) -> ImuData {
+        let body = self.body_position();
+        let (heading, pitch, roll) = body_angles(&body.rotation);
+        let acceleration = body
+            .rotation
+            .inverse_transform_vector(&self.car_acceleration)
+            * 1000.0;
+        ImuData {
+            heading,
+            pitch,
+            roll,
+            acceleration_x: MmPerS2(acceleration.x),
+            acceleration_y: MmPerS2(acceleration.y),
+            acceleration_z: MmPerS2(acceleration.z),
+        }
+    }
+
+    /// Gravity acceleration in the body frame (mm/s2, x left, y up, z front)
+    pub fn read_gravity(&self) -> (Acceleration, Acceleration, Acceleration) {
+        let body = self.body_position();
+        let gravity = body
+            .rotation
+            .inverse_transform_vector(&NaV3::new(0.0, -GRAVITY, 0.0))
+            * 1000.0;
+        (MmPerS2(gravity.x), MmPerS2(gravity.y), MmPerS2(gravity.z))
+    }
+
+    /// IMU data as reported on the protocol (degrees and mm/s2, with gravity)
+    pub fn read_protocol_imu(&self) -> ProtocolImuData {
+        let imu = self.read_imu();
+        let (gravity_x, gravity_y, gravity_z) = self.read_gravity();
+        ProtocolImuData {
+            rotation_x: imu.pitch.to_deg().0.round() as i32,
+            rotation_y: imu.heading.to_deg().0.round() as i32,
+            rotation_z: imu.roll.to_deg().0.round() as i32,
+            acceleration_x: imu.acceleration_x.0.round() as i32,
+            acceleration_y: imu.acceleration_y.0.round() as i32,
+            acceleration_z: imu.acceleration_z.0.round() as i32,
+            gravity_x: gravity_x.0.round() as i32,
+            gravity_y: gravity_y.0.round() as i32,
+            gravity_z: gravity_z.0.round() as i32,
+        }
+    }
+
     fn wheel_rotation(&self, wheel_part_id: usize) -> f32 {
         let car = self.bodies.get(self.car).unwrap();
         let body = car.part(self.car_part_id_body).unwrap();
@@ -277,6 +408,37 @@ impl SimulatedWorld {
         self.wheel_velocity(self.car_part_id_fr)
     }
 
+    fn wheel_encoder(travel: f32, velocity: f32) -> WheelEncoderData {
+        let ticks = travel / (2.0 * PI) * ENCODER_TICKS_PER_REVOLUTION as f32;
+        WheelEncoderData {
+            // Wrap like a hardware counter
+            ticks: (ticks as i64) as i32,
+            velocity,
+        }
+    }
+
+    /// Read wheel encoders (ticks from the integrated wheel rotation)
+    pub fn read_encoders(&self) -> EncoderData {
+        EncoderData {
+            back_left: Self::wheel_encoder(self.wheel_travel_bl, self.wheel_velocity_bl()),
+            back_right: Self::wheel_encoder(self.wheel_travel_br, self.wheel_velocity_br()),
+            front_left: Self::wheel_encoder(self.wheel_travel_fl, self.wheel_velocity_fl()),
+            front_right: Self::wheel_encoder(self.wheel_travel_fr, self.wheel_velocity_fr()),
+        }
+    }
+
+    pub fn battery(&self) -> &SimulatedBattery {
+        &self.battery
+    }
+
+    pub fn battery_mut(&mut self) -> &mut SimulatedBattery {
+        &mut self.battery
+    }
+
+    pub fn read_battery(&self) -> BatteryData {
+        self.battery.read()
+    }
+
     fn power_ratio(&self, velocity: f32) -> f32 {
         let reduction = velocity / self.motor_max_speed;
         let reduction = if reduction > 1.0 { 1.0 } else { reduction };
@@ -332,11 +494,86 @@ impl SimulatedWorld {
         self.apply_wheel_power(self.car_part_id_fr, self.car_motor_power_fr);
     }
 
+    /// Set motor power (clamped to [-1, 1] like real motor drivers)
     pub fn set_motor_power(&mut self, bl: f32, br: f32, fl: f32, fr: f32) {
-        self.car_motor_power_bl = bl;
-        self.car_motor_power_br = br;
-        self.car_motor_power_fl = fl;
-        self.car_motor_power_fr = fr;
+        self.car_motor_power_bl = clamp_motor_power(bl);
+        self.car_motor_power_br = clamp_motor_power(br);
+        self.car_motor_power_fl = clamp_motor_power(fl);
+        self.car_motor_power_fr = clamp_motor_power(fr);
+    }
+
+    pub fn laser_layout(&self) -> &LaserLayout {
+        &self.laser_layout
+    }
+
+    pub fn set_laser_layout(&mut self, layout: &LaserLayout) {
+        self.laser_layout = *layout;
+    }
+
+    /// Distance (m) of the first obstacle along a laser beam
+    fn cast_laser_beam(&self, body: &ISO, laser: &LaserMount, max_range: f32) -> f32 {
+        let offset = NaV3::new(
+            laser.offset_x.to_m().0,
+            laser.offset_y.to_m().0,
+            laser.offset_z.to_m().0,
+        );
+        let direction = laser.direction();
+        let direction = NaV3::new(direction[0], direction[1], direction[2]);
+        let ray = Ray::new(
+            body * Point3::from(offset),
+            body.rotation.transform_vector(&direction),
+        );
+        // Lasers only see obstacles: the floor below the cone is not an echo
+        let groups = CollisionGroups::new().with_whitelist(&[OBSTACLE_GROUP]);
+        let mut distance = max_range;
+        for (_, collider, intersection) in
+            self.geometrical_world
+                .interferences_with_ray(&self.colliders, &ray, &groups)
+        {
+            // Ignore hits on the car itself
+            if collider.body() == self.car {
+                continue;
+            }
+            if intersection.toi < distance {
+                distance = intersection.toi;
+            }
+        }
+        distance
+    }
+
+    /// Nearest obstacle inside the laser field of view (mm, clamped to range)
+    ///
+    /// The cone is sampled with its axis and four rays on its border.
+    fn read_laser(&self, body: &ISO, laser: &LaserMount) -> Mm {
+        let max_range = laser.max_range.to_m().0;
+        let half_fov = laser.field_of_view / 2.0;
+        let mut distance = self.cast_laser_beam(body, laser, max_range);
+        for (yaw, pitch) in [
+            (half_fov, Rad(0.0)),
+            (-half_fov, Rad(0.0)),
+            (Rad(0.0), half_fov),
+            (Rad(0.0), -half_fov),
+        ]
+        .iter()
+        {
+            let mut beam = *laser;
+            beam.yaw += *yaw;
+            beam.pitch += *pitch;
+            distance = distance.min(self.cast_laser_beam(body, &beam, max_range));
+        }
+        laser.clamp(Mm(distance * 1000.0))
+    }
+
+    /// Ray cast all laser sensors against the world (results in mm)
+    ///
+    /// Colliders are only visible to the ray caster after the first step.
+    pub fn read_lasers(&self) -> LaserData {
+        let body = self.body_position();
+        let mut data: LaserData = [Mm(0.0); LASER_COUNT];
+        for i in 0..LASER_COUNT {
+            data[i] = self.read_laser(&body, &self.laser_layout.lasers[i]);
+        }
+        data
     }
 
     fn next_ground_part_count(&mut self) -> usize {
@@ -344,32 +581,82 @@ impl SimulatedWorld {
         self.ground_part_count
     }
 
-    fn add_map_box(&mut self, section_box: &map::MapSectionBox) {
+    fn add_map_box(
+        &mut self,
+        section_box: &map::MapSectionBox,
+        groups: CollisionGroups,
+    ) -> DefaultColliderHandle {
         let translation = Vector3::new(
             section_box.center.x,
             section_box.center.y,
             section_box.center.z,
         );
         let rotation = section_box.rotation;
-        let mut box_collider_desc =
-            cuboid(section_box.width, section_box.height, section_box.length)
-                .translation(translation);
+        let mut box_collider_desc = cuboid(
+            section_box.width.0,
+            section_box.height.0,
+            section_box.length.0,
+        )
+        .translation(translation)
+        .collision_groups(groups);
         if let Some(axis) = rotation.axis() {
             box_collider_desc =
                 box_collider_desc.rotation(rotation.angle() * NaV3::new(axis.x, axis.y, axis.z));
         }
         let box_collider =
             box_collider_desc.build(BodyPartHandle(self.ground, self.next_ground_part_count()));
-        self.colliders.insert(box_collider);
+        self.colliders.insert(box_collider)
     }
 
     pub fn setup_map(&mut self, map: &Map) {
         let segments = map_segmentation(map);
         for segment in segments.iter() {
-            self.add_map_box(&segment.floor_box());
-            self.add_map_box(&segment.left_box());
-            self.add_map_box(&segment.right_box());
+            self.add_map_box(&segment.floor_box(), floor_groups());
+            let left = self.add_map_box(&segment.left_box(), CollisionGroups::new());
+            let right = self.add_map_box(&segment.right_box(), CollisionGroups::new());
+            self.wall_colliders.push(left);
+            self.wall_colliders.push(right);
+        }
+    }
+
+    /// Read bumper switches from the contacts between the car body and the walls
+    ///
+    /// Each contact presses the front or rear bumper, depending on which half
+    /// of the body it touches. Contacts are only known after the first step.
+    pub fn read_bumpers(&self) -> BumperData {
+        let body = self.body_position();
+        let mut bumpers = BumperData {
+            front: false,
+            rear: false,
+        };
+        for (handle1, _, handle2, _, _, manifold) in
+            self.geometrical_world.contact_pairs(&self.colliders, true)
+        {
+            let body_is_first = handle1 == self.car_body_collider;
+            let other = if body_is_first {
+                handle2
+            } else if handle2 == self.car_body_collider {
+                handle1
+            } else {
+                continue;
+            };
+            if !self.wall_colliders.contains(&other) {
+                continue;
+            }
+            if let Some(tracked) = manifold.deepest_contact() {
+                let point = if body_is_first {
+                    tracked.contact.world1
+                } else {
+                    tracked.contact.world2
+                };
+                if body.inverse_transform_point(&point).z > 0.0 {
+                    bumpers.front = true;
+                } else {
+                    bumpers.rear = true;
+                }
+            }
         }
+        bumpers
     }
 
     pub fn step(&mut self) {
@@ -381,6 +668,23 @@ impl SimulatedWorld {
             &mut self.joint_constraints,
             &mut self.force_generators,
         );
+        self.steps += 1;
+        let velocity = self.body_velocity();
+        self.car_acceleration = (velocity - self.car_velocity) / TIMESTEP;
+        self.car_velocity = velocity;
+        self.wheel_travel_bl += self.wheel_velocity_bl() * TIMESTEP;
+        self.wheel_travel_br += self.wheel_velocity_br() * TIMESTEP;
+        self.wheel_travel_fl += self.wheel_velocity_fl() * TIMESTEP;
+        self.wheel_travel_fr += self.wheel_velocity_fr() * TIMESTEP;
+        self.battery.discharge(
+            &[
+                self.car_motor_power_bl,
+                self.car_motor_power_br,
+                self.car_motor_power_fl,
+                self.car_motor_power_fr,
+            ],
+            TIMESTEP,
+        );
     }
 
     pub fn run_testbed(self) {
@@ -0,0 +1,34 @@
+use crate::{SimulatedWorld, STEPS_PER_SECOND, TIMESTEP};
+use map::Car;
+
+#[test]
+fn odometry_follows_a_simulated_turn() {
+    let mut world = SimulatedWorld::new();
+    // Let the car land
+    for _ in 0..STEPS_PER_SECOND {
+        world.step();
+    }
+    let start = world.read_imu().heading;
+    let kinematics = Car::new().kinematics();
+
+    // Spin clockwise in place, integrating the odometry yaw rate
+    world.set_motor_power(0.3, -0.3, 0.3, -0.3);
+    let mut odometry = 0.0;
+    for _ in 0..STEPS_PER_SECOND {
+        world.step();
+        odometry += kinematics.twist(&world.read_encoders()).yaw_rate * TIMESTEP;
+        if odometry > 1.0 {
+            break;
+        }
+    }
+    let turn = (world.read_imu().heading - start).0;
+
+    // Skid steering slips a little, the wheel spacing must not add errors
+    assert!(odometry > 0.5, "odometry {}", odometry);
+    assert!(
+        (turn - odometry).abs() < odometry * 0.2,
+        "turn {} odometry {}",
+        turn,
+        odometry
+    );
+}
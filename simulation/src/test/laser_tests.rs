@@ -0,0 +1,33 @@
+use crate::{SimulatedWorld, STEPS_PER_SECOND};
+use hal::layout::LASER_MAX_RANGE;
+use hal::M;
+use protocol::map::{Map, MapSection, MapSectionShape, MapSectionStraigth};
+
+/// Short straight track starting at the car position
+fn short_track() -> Map {
+    let mut map = Map::new();
+    map.configure_section(
+        0,
+        &MapSection::new(
+            MapSectionShape::Straigth(MapSectionStraigth { length: M(0.3) }),
+            M(0.8),
+            M(0.8),
+        ),
+    );
+    map.complete_configuration();
+    map
+}
+
+#[test]
+fn lasers_do_not_see_the_floor() {
+    let mut world = SimulatedWorld::new();
+    world.setup_map(&short_track());
+    // Let the car land on the track
+    for _ in 0..STEPS_PER_SECOND {
+        world.step();
+    }
+    let lasers = world.read_lasers();
+    // The two lasers closest to the heading have no wall ahead
+    assert_eq!(lasers[9], LASER_MAX_RANGE);
+    assert_eq!(lasers[10], LASER_MAX_RANGE);
+}
@@ -0,0 +1,2 @@
+mod kinematics_tests;
+mod laser_tests;
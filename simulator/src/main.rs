@@ -1,7 +1,10 @@
+use bot::Bot;
 use hal::{new_protocol_buffer, ProtocolBuffer};
 use map::*;
-use protocol::map::{Map, MapSection};
-use protocol::protocol::BotCommand;
+use protocol::map::{Map, MapReceiver};
+use protocol::protocol::{BotCommand, MotorsPowerData};
+use serial::{SerialLink, DEFAULT_BAUD_RATE};
+use simulation::device::SimulatedHal;
 
 static SECTIONS: [&str; 7] = [
     "MAP-SECTION:0:STRAIGHT:1000:800:800",
@@ -21,20 +24,28 @@ fn buffer_from_str(s: &str) -> ProtocolBuffer {
     buffer
 }
 
-fn setup_map() -> Map {
-    let mut map = Map::new();
-    map.reset();
+/// Map upload commands (MAP-START, each MAP-SECTION and MAP-END)
+fn map_commands() -> Vec<BotCommand> {
+    let mut cmds = vec![BotCommand::MapStart(SECTIONS.len())];
     for s in SECTIONS.iter() {
         let b = buffer_from_str(s);
-        let cmd = BotCommand::parse(&b).unwrap();
-        if let BotCommand::MapSection(section) = cmd {
-            let index = section.index;
-            let section = MapSection::from_protocol_data(&section.data);
-            map.configure_section(index, &section);
-        }
+        cmds.push(BotCommand::parse(&b).unwrap());
+    }
+    cmds.push(BotCommand::MapEnd);
+    cmds
+}
+
+fn setup_map() -> Map {
+    let mut receiver = MapReceiver::new();
+    let mut result = None;
+    for cmd in map_commands().iter() {
+        result = receiver.handle_command(cmd);
+    }
+    match result {
+        Some(Ok(map)) => map,
+        Some(Err(error)) => panic!("invalid map: {}", error),
+        None => panic!("incomplete map"),
     }
-    map.complete_configuration();
-    map
 }
 
 #[allow(dead_code)]
@@ -55,10 +66,53 @@ fn main_full() {
     visual_world.setup_map(&map);
     let mut simulated_world = simulation::SimulatedWorld::new();
     simulated_world.setup_map(&map);
+    simulated_world.set_laser_layout(&car.lasers);
 
-    // simulated_world.set_motor_power(0.4, 0.4, 0.4, 0.4);
+    let mut bot = Bot::new(SimulatedHal::new(simulated_world));
+    bot.set_laser_layout(&car.lasers);
+    bot.init();
+    for cmd in map_commands().iter() {
+        let mut command = new_protocol_buffer();
+        cmd.write(&mut command);
+        bot.hal_mut().push_incoming(command);
+    }
+
+    // Optionally talk to a ground station over a tty
+    let mut link = std::env::args()
+        .nth(1)
+        .map(|path| SerialLink::open(path, DEFAULT_BAUD_RATE).expect("cannot open serial link"));
 
     while visual_world.render() {
+        let power = match visual_world.ui().power {
+            Some(power) => {
+                let (power_bl, power_br, power_fl, power_lr) = power.power();
+                println!("power {} {} {} {}", power_bl, power_br, power_fl, power_lr);
+                (power_bl, power_br, power_fl, power_lr)
+            }
+            None => (0.0, 0.0, 0.0, 0.0),
+        };
+        let mut command = new_protocol_buffer();
+        BotCommand::Direct(MotorsPowerData {
+            back_left: (power.0 * 100.0) as i32,
+            back_right: (power.1 * 100.0) as i32,
+            front_left: (power.2 * 100.0) as i32,
+            front_right: (power.3 * 100.0) as i32,
+        })
+        .write(&mut command);
+        bot.hal_mut().push_incoming(command);
+        if let Some(link) = &mut link {
+            while let Some(command) = link.poll() {
+                bot.hal_mut().push_incoming(command);
+            }
+        }
+        bot.tick();
+        while let Some(event) = bot.hal_mut().pop_outgoing() {
+            if let Some(link) = &mut link {
+                link.send(event);
+            }
+        }
+
+        let simulated_world = bot.hal_mut().world_mut();
         simulated_world.step();
         simulated_world.step();
 
@@ -77,17 +131,6 @@ fn main_full() {
             simulated_world.wheel_rotation_fr(),
         );
 
-        match visual_world.ui().power {
-            Some(power) => {
-                let (power_bl, power_br, power_fl, power_lr) = power.power();
-                println!("power {} {} {} {}", power_bl, power_br, power_fl, power_lr);
-                simulated_world.set_motor_power(power_bl, power_br, power_fl, power_lr);
-            }
-            None => {
-                simulated_world.set_motor_power(0.0, 0.0, 0.0, 0.0);
-            }
-        }
-
         /*
         println!(
             "w_vel {} {} {} {}",